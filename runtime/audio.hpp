@@ -0,0 +1,284 @@
+#pragma once
+#include "target.hpp"
+
+#include <cstdint>
+#include <fstream>
+#include <map>
+
+// Plays sounds offline: the voices are mixed into a stereo buffer as time goes
+// by, so that changes of volume and effects also apply to the sounds already
+// playing, like in Scratch. With `--sound` the mix is written as a WAV file
+// once the program ends, letting the sounds still playing finish first.
+class ScratchAudio {
+public:
+  static constexpr int SAMPLE_RATE = 44100;
+
+  void start() {
+    recording = !scratch_options.sound_path.empty();
+
+    scratch_scheduler.frame_hooks.push_back(
+        [this] { mix_until(scratch_scheduler.now()); });
+
+    if (recording)
+      scratch_scheduler.exit_hooks.push_back([this] {
+        mix_until(scratch_scheduler.now());
+        finish();
+        save(scratch_options.sound_path);
+      });
+  }
+
+  void play(ScratchTarget &target, ScratchValue sound) {
+    start_voice(target, sound);
+  }
+
+  // Waits until the sound ends, which the pitch effect makes happen sooner or
+  // later, or until it is stopped or started over.
+  void play_until_done(ScratchTarget &target, ScratchValue sound) {
+    long id = start_voice(target, sound);
+
+    while (id >= 0 && is_playing(id))
+      scratch_scheduler.wait(0);
+  }
+
+  void stop_all() {
+    mix_until(scratch_scheduler.now());
+    voices.clear();
+  }
+
+  void set_effect(ScratchTarget &target, std::string effect,
+                  ScratchValue value) {
+    update_effect(target, effect, value.to_number(), false);
+  }
+
+  void change_effect(ScratchTarget &target, std::string effect,
+                     ScratchValue value) {
+    update_effect(target, effect, value.to_number(), true);
+  }
+
+  void clear_effects(ScratchTarget &target) {
+    mix_until(scratch_scheduler.now());
+    effects.erase(&target);
+  }
+
+  void set_volume(ScratchTarget &target, ScratchValue volume) {
+    mix_until(scratch_scheduler.now());
+    target.volume = std::clamp(volume.to_number(), 0.0, 100.0);
+  }
+
+  void change_volume(ScratchTarget &target, ScratchValue volume) {
+    set_volume(target, target.volume + volume.to_number());
+  }
+
+private:
+  struct Effects {
+    double pitch = 0;
+    double pan = 0;
+  };
+
+  struct Voice {
+    ScratchTarget *target;
+    const ScratchSound *sound;
+    long id;
+    // first sample of the mix, and the position in the sound
+    long start;
+    double position;
+  };
+
+  // Same lookup as the VM: by name, then by number wrapped around the sounds.
+  static int sound_index(const ScratchTarget &target, ScratchValue value) {
+    long count = static_cast<long>(target.sounds.size());
+    if (count == 0)
+      return -1;
+
+    std::string name = value.to_string();
+
+    for (long i = 0; i < count; i++) {
+      if (target.sounds[i].name == name)
+        return static_cast<int>(i);
+    }
+
+    char *end = nullptr;
+    long number = std::strtol(name.c_str(), &end, 10);
+    if (end == name.c_str())
+      return -1;
+
+    return static_cast<int>(((number - 1) % count + count) % count);
+  }
+
+  long start_voice(ScratchTarget &target, ScratchValue value) {
+    int index = sound_index(target, value);
+    if (index < 0)
+      return -1;
+
+    const ScratchSound *sound = &target.sounds[index];
+    if (sound->rate <= 0 || sound->sample_count <= 0)
+      return -1;
+
+    mix_until(scratch_scheduler.now());
+
+    // a sound that is already playing starts over
+    voices.erase(std::remove_if(voices.begin(), voices.end(),
+                                [&](const Voice &voice) {
+                                  return voice.target == &target &&
+                                         voice.sound == sound;
+                                }),
+                 voices.end());
+    voices.push_back({&target, sound, ++last_id, mixed, 0});
+
+    return last_id;
+  }
+
+  bool is_playing(long id) const {
+    return std::any_of(voices.begin(), voices.end(),
+                       [&](const Voice &voice) { return voice.id == id; });
+  }
+
+  void update_effect(ScratchTarget &target, std::string effect, double value,
+                     bool change) {
+    std::transform(effect.begin(), effect.end(), effect.begin(), ::tolower);
+    mix_until(scratch_scheduler.now());
+
+    Effects &current = effects[&target];
+
+    if (effect == "pitch")
+      current.pitch =
+          std::clamp(value + (change ? current.pitch : 0), -360.0, 360.0);
+    else if (effect == "pan")
+      current.pan =
+          std::clamp(value + (change ? current.pan : 0), -100.0, 100.0);
+  }
+
+  void mix_until(double time) { mix_to(std::lround(time * SAMPLE_RATE)); }
+
+  void mix_to(long end) {
+    if (end <= mixed)
+      return;
+
+    if (recording)
+      buffer.resize(end * 2, 0);
+
+    for (auto &voice : voices)
+      mix_voice(voice, end);
+
+    voices.erase(std::remove_if(voices.begin(), voices.end(),
+                                [](const Voice &voice) {
+                                  return voice.position >=
+                                         voice.sound->sample_count;
+                                }),
+                 voices.end());
+    mixed = end;
+  }
+
+  void mix_voice(Voice &voice, long end) {
+    const ScratchSound &sound = *voice.sound;
+    Effects current;
+
+    auto found = effects.find(voice.target);
+    if (found != effects.end())
+      current = found->second;
+
+    double left = voice.target->volume / 100, right = left;
+
+    // a pan of zero leaves the sound alone instead of lowering both sides
+    if (current.pan != 0) {
+      double side = (current.pan + 100) / 200 * M_PI / 2;
+      left *= std::cos(side);
+      right *= std::sin(side);
+    }
+
+    double step = sound.rate * std::pow(2, current.pitch / 120) / SAMPLE_RATE;
+
+    for (long i = std::max(mixed, voice.start);
+         i < end && voice.position < sound.sample_count; i++) {
+      if (recording && sound.samples) {
+        double value = sample_at(sound, voice.position);
+        buffer[i * 2] += static_cast<float>(value * left);
+        buffer[i * 2 + 1] += static_cast<float>(value * right);
+      }
+
+      voice.position += step;
+    }
+  }
+
+  // Linear interpolation between the two nearest samples.
+  static double sample_at(const ScratchSound &sound, double position) {
+    long index = static_cast<long>(position);
+    double fraction = position - index;
+    double next =
+        index + 1 < sound.sample_count ? sample(sound, index + 1) : 0;
+
+    return sample(sound, index) * (1 - fraction) + next * fraction;
+  }
+
+  static double sample(const ScratchSound &sound, long index) {
+    const unsigned char *bytes = &sound.samples[index * 2];
+    return static_cast<int16_t>(bytes[0] | bytes[1] << 8) / 32768.0;
+  }
+
+  // Mixes the sounds still playing to their end.
+  void finish() {
+    long end = mixed;
+
+    for (auto &voice : voices) {
+      const ScratchSound &sound = *voice.sound;
+      auto found = effects.find(voice.target);
+      double pitch = found != effects.end() ? found->second.pitch : 0;
+      double step = sound.rate * std::pow(2, pitch / 120) / SAMPLE_RATE;
+
+      end = std::max(end, mixed + static_cast<long>(std::ceil(
+                                      (sound.sample_count - voice.position) /
+                                      step)));
+    }
+
+    mix_to(end);
+  }
+
+  void save(const std::string &path) const {
+    std::string data;
+    data.reserve(buffer.size() * 2);
+
+    for (float value : buffer) {
+      auto sample = static_cast<int16_t>(
+          std::lround(std::clamp(value, -1.0f, 1.0f) * 32767));
+      append_le(data, static_cast<uint16_t>(sample), 2);
+    }
+
+    std::string header = "RIFF";
+    append_le(header, 36 + data.size(), 4);
+    header += "WAVEfmt ";
+    append_le(header, 16, 4);
+    append_le(header, 1, 2); // PCM
+    append_le(header, 2, 2); // stereo
+    append_le(header, SAMPLE_RATE, 4);
+    append_le(header, SAMPLE_RATE * 4, 4);
+    append_le(header, 4, 2);
+    append_le(header, 16, 2);
+    header += "data";
+    append_le(header, data.size(), 4);
+
+    std::ofstream out(path, std::ios::binary);
+    out << header << data;
+
+    if (!out) {
+      std::cerr << "Cannot write sound " << path << std::endl;
+      std::exit(1);
+    }
+  }
+
+  static void append_le(std::string &out, uint32_t value, int bytes) {
+    for (int i = 0; i < bytes; i++)
+      out.push_back(static_cast<char>((value >> (i * 8)) & 0xff));
+  }
+
+  bool recording = false;
+  std::vector<Voice> voices;
+  std::map<ScratchTarget *, Effects> effects;
+  long last_id = 0;
+
+  // length of the mix so far, per channel, and the mix with left and right
+  // interleaved
+  long mixed = 0;
+  std::vector<float> buffer;
+};
+
+inline ScratchAudio scratch_audio;
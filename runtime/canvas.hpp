@@ -0,0 +1,288 @@
+#pragma once
+#include "scratchnative.hpp"
+
+#include <cstdint>
+#include <fstream>
+
+// RGBA image the size of the stage, with straight (not premultiplied) alpha.
+class ScratchCanvas {
+public:
+  static constexpr int WIDTH = 480;
+  static constexpr int HEIGHT = 360;
+
+  ScratchCanvas() : pixels(WIDTH * HEIGHT * 4, 0) {}
+
+  void clear() { std::fill(pixels.begin(), pixels.end(), 0); }
+
+  void fill(unsigned char r, unsigned char g, unsigned char b) {
+    for (size_t i = 0; i < pixels.size(); i += 4) {
+      pixels[i] = r;
+      pixels[i + 1] = g;
+      pixels[i + 2] = b;
+      pixels[i + 3] = 255;
+    }
+  }
+
+  // Draws another canvas, such as the pen layer, over this one.
+  void draw_canvas(const ScratchCanvas &other) {
+    for (int py = 0; py < HEIGHT; py++) {
+      for (int px = 0; px < WIDTH; px++) {
+        const unsigned char *pixel = &other.pixels[(py * WIDTH + px) * 4];
+        double color[3] = {pixel[0] / 255.0, pixel[1] / 255.0,
+                           pixel[2] / 255.0};
+        blend(px, py, color, pixel[3] / 255.0);
+      }
+    }
+  }
+
+  const unsigned char *pixel(int px, int py) const {
+    return &pixels[(py * WIDTH + px) * 4];
+  }
+
+  // Reads a color block value, "#rrggbb" or a number 0xAARRGGBB where no
+  // alpha at all means opaque. Anything else is black.
+  static void parse_color(ScratchValue value, double rgb[3], double &alpha) {
+    uint32_t number = 0;
+    alpha = 1;
+
+    if (value.is_number()) {
+      number = static_cast<uint32_t>(std::llround(value.to_number()));
+      if (number >> 24)
+        alpha = (number >> 24) / 255.0;
+    } else {
+      std::string hex = value.to_string();
+      if (!hex.empty() && hex[0] == '#')
+        hex.erase(0, 1);
+      if (hex.size() == 3)
+        hex = {hex[0], hex[0], hex[1], hex[1], hex[2], hex[2]};
+
+      char *end = nullptr;
+      number = static_cast<uint32_t>(std::strtoul(hex.c_str(), &end, 16));
+      if (hex.size() != 6 || *end != '\0')
+        number = 0;
+    }
+
+    for (int i = 0; i < 3; i++)
+      rgb[i] = ((number >> (16 - i * 8)) & 0xff) / 255.0;
+  }
+
+  // Draws `color` (0 to 1 components) over a pixel with the given opacity.
+  void blend(int px, int py, const double color[3], double alpha) {
+    if (px < 0 || py < 0 || px >= WIDTH || py >= HEIGHT || alpha <= 0)
+      return;
+
+    unsigned char *pixel = &pixels[(py * WIDTH + px) * 4];
+    double below = pixel[3] / 255.0;
+    double out = alpha + below * (1 - alpha);
+
+    for (int i = 0; i < 3; i++) {
+      double mixed =
+          (color[i] * alpha + pixel[i] / 255.0 * below * (1 - alpha)) / out;
+      pixel[i] = static_cast<unsigned char>(std::lround(mixed * 255));
+    }
+
+    pixel[3] = static_cast<unsigned char>(std::lround(out * 255));
+  }
+
+  // Line with round caps between two stage positions, antialiased the same
+  // way as the pen of Scratch.
+  void draw_line(double x0, double y0, double x1, double y1, double diameter,
+                 const double color[3], double alpha) {
+    // thin lines are moved to the center of the pixels they go through
+    double offset = diameter == 1 || diameter == 3 ? 0.5 : 0;
+
+    double ax = x0 + offset + WIDTH / 2.0, ay = HEIGHT / 2.0 - (y0 + offset);
+    double bx = x1 + offset + WIDTH / 2.0, by = HEIGHT / 2.0 - (y1 + offset);
+    double reach = (diameter + 1) / 2;
+
+    int left = static_cast<int>(std::floor(std::min(ax, bx) - reach));
+    int right = static_cast<int>(std::ceil(std::max(ax, bx) + reach));
+    int top = static_cast<int>(std::floor(std::min(ay, by) - reach));
+    int bottom = static_cast<int>(std::ceil(std::max(ay, by) + reach));
+
+    double dx = bx - ax, dy = by - ay;
+    double length = dx * dx + dy * dy;
+
+    for (int py = std::max(top, 0); py < std::min(bottom, HEIGHT); py++) {
+      for (int px = std::max(left, 0); px < std::min(right, WIDTH); px++) {
+        double cx = px + 0.5 - ax, cy = py + 0.5 - ay;
+        double t =
+            length > 0 ? std::clamp((cx * dx + cy * dy) / length, 0.0, 1.0) : 0;
+        double distance = std::hypot(cx - t * dx, cy - t * dy);
+        double coverage = std::clamp(reach - distance, 0.0, 1.0);
+
+        blend(px, py, color, alpha * coverage);
+      }
+    }
+  }
+
+  // Draws the current costume of a target with its graphic effects, which
+  // follow the shaders of the Scratch renderer. Costumes whose pixels weren't
+  // loaded are skipped.
+  void draw_target(const ScratchTarget &target, bool with_ghost = true) {
+    if (target.costumes.empty() || !target.costume().pixels)
+      return;
+
+    ScratchBounds bounds = target.bounds();
+
+    double hue_shift = target.effect("color") / 200;
+    double brightness = target.effect("brightness") / 100;
+    double opacity = with_ghost ? 1 - target.effect("ghost") / 100 : 1;
+
+    int left = std::max(0, static_cast<int>(bounds.left + WIDTH / 2.0));
+    int right = std::min(
+        WIDTH, static_cast<int>(std::ceil(bounds.right + WIDTH / 2.0)));
+    int top = std::max(0, static_cast<int>(HEIGHT / 2.0 - bounds.top));
+    int bottom = std::min(
+        HEIGHT, static_cast<int>(std::ceil(HEIGHT / 2.0 - bounds.bottom)));
+
+    for (int py = top; py < bottom; py++) {
+      for (int px = left; px < right; px++) {
+        const unsigned char *texel =
+            target.texel_at(px + 0.5 - WIDTH / 2.0, HEIGHT / 2.0 - (py + 0.5));
+        if (!texel || texel[3] == 0)
+          continue;
+
+        double color[3] = {texel[0] / 255.0, texel[1] / 255.0,
+                           texel[2] / 255.0};
+
+        if (hue_shift != 0)
+          shift_hue(color, hue_shift);
+
+        for (auto &component : color)
+          component = std::clamp(component + brightness, 0.0, 1.0);
+
+        blend(px, py, color, texel[3] / 255.0 * opacity);
+      }
+    }
+  }
+
+  bool write_png(const std::string &path) const {
+    std::string raw;
+    raw.reserve((WIDTH * 4 + 1) * HEIGHT);
+
+    for (int y = 0; y < HEIGHT; y++) {
+      raw.push_back(0); // no filter
+      raw.append(reinterpret_cast<const char *>(&pixels[y * WIDTH * 4]),
+                 WIDTH * 4);
+    }
+
+    // zlib stream made of uncompressed blocks
+    std::string zlib = "\x78\x01";
+
+    for (size_t start = 0; start < raw.size(); start += 65535) {
+      size_t length = std::min<size_t>(65535, raw.size() - start);
+
+      zlib.push_back(start + length == raw.size() ? 1 : 0);
+      append_u16_le(zlib, length);
+      append_u16_le(zlib, ~length);
+      zlib.append(raw, start, length);
+    }
+
+    append_u32_be(zlib, adler32(raw));
+
+    std::string header;
+    append_u32_be(header, WIDTH);
+    append_u32_be(header, HEIGHT);
+    header.append("\x08\x06\x00\x00\x00", 5); // 8 bit RGBA
+
+    std::ofstream out(path, std::ios::binary);
+    out.write("\x89PNG\r\n\x1a\n", 8);
+    write_chunk(out, "IHDR", header);
+    write_chunk(out, "IDAT", zlib);
+    write_chunk(out, "IEND", "");
+
+    return static_cast<bool>(out);
+  }
+
+  std::vector<unsigned char> pixels;
+
+private:
+  // Same as the color effect shader of Scratch, which first lifts very dark
+  // and grey colors so that they visibly change too.
+  static void shift_hue(double color[3], double shift) {
+    double max = std::max({color[0], color[1], color[2]});
+    double min = std::min({color[0], color[1], color[2]});
+    double h = 0, s = max > 0 ? (max - min) / max : 0, v = max;
+
+    if (max > min) {
+      if (max == color[0])
+        h = std::fmod((color[1] - color[2]) / (max - min) / 6 + 1, 1.0);
+      else if (max == color[1])
+        h = ((color[2] - color[0]) / (max - min) + 2) / 6;
+      else
+        h = ((color[0] - color[1]) / (max - min) + 4) / 6;
+    }
+
+    v = std::max(v, 0.055);
+    s = std::max(s, 0.09);
+    h = std::fmod(h + shift, 1.0);
+    if (h < 0)
+      h += 1;
+
+    int i = static_cast<int>(h * 6) % 6;
+    double f = h * 6 - std::floor(h * 6);
+    double p = v * (1 - s), q = v * (1 - s * f), t = v * (1 - s * (1 - f));
+    double rgb[6][3] = {{v, t, p}, {q, v, p}, {p, v, t},
+                        {p, q, v}, {t, p, v}, {v, p, q}};
+
+    for (int c = 0; c < 3; c++)
+      color[c] = rgb[i][c];
+  }
+
+  static void append_u16_le(std::string &out, size_t value) {
+    out.push_back(static_cast<char>(value & 0xff));
+    out.push_back(static_cast<char>((value >> 8) & 0xff));
+  }
+
+  static void append_u32_be(std::string &out, uint32_t value) {
+    for (int shift = 24; shift >= 0; shift -= 8)
+      out.push_back(static_cast<char>((value >> shift) & 0xff));
+  }
+
+  static uint32_t adler32(const std::string &data) {
+    uint32_t a = 1, b = 0;
+
+    for (unsigned char c : data) {
+      a = (a + c) % 65521;
+      b = (b + a) % 65521;
+    }
+
+    return (b << 16) | a;
+  }
+
+  static uint32_t crc32(const std::string &data) {
+    static uint32_t table[256];
+    static bool filled = false;
+
+    if (!filled) {
+      for (uint32_t n = 0; n < 256; n++) {
+        uint32_t c = n;
+        for (int k = 0; k < 8; k++)
+          c = c & 1 ? 0xedb88320 ^ (c >> 1) : c >> 1;
+        table[n] = c;
+      }
+      filled = true;
+    }
+
+    uint32_t crc = 0xffffffff;
+    for (unsigned char c : data)
+      crc = table[(crc ^ c) & 0xff] ^ (crc >> 8);
+
+    return crc ^ 0xffffffff;
+  }
+
+  static void write_chunk(std::ofstream &out, const char *type,
+                          const std::string &data) {
+    std::string chunk;
+    append_u32_be(chunk, data.size());
+    out.write(chunk.data(), chunk.size());
+
+    std::string body = std::string(type, 4) + data;
+    chunk.clear();
+    append_u32_be(chunk, crc32(body));
+
+    out.write(body.data(), body.size());
+    out.write(chunk.data(), chunk.size());
+  }
+};
@@ -0,0 +1,275 @@
+#pragma once
+#include "scratchnative.hpp"
+
+class ScratchMouse {
+public:
+  void on_click(ScratchTarget &target, std::function<void()> script) {
+    hats.push_back({&target, script, false, 0});
+  }
+
+  // Scratch reports the mouse position in whole stage coordinates.
+  void move(double new_x, double new_y) {
+    x = std::round(std::clamp(new_x, -240.0, 240.0));
+    y = std::round(std::clamp(new_y, -180.0, 180.0));
+  }
+
+  void press() {
+    down = true;
+    click(target_at(x, y));
+  }
+
+  void release() { down = false; }
+
+  void click(ScratchTarget &target) {
+    for (auto &hat : hats) {
+      if (hat.target != &target)
+        continue;
+
+      // a script that is still running isn't started again
+      if (hat.started && scratch_scheduler.is_running(hat.thread))
+        continue;
+
+      hat.started = true;
+      hat.thread = scratch_scheduler.spawn(hat.script);
+    }
+  }
+
+  // Topmost visible sprite under the point, or else the stage.
+  ScratchTarget &target_at(double x, double y) {
+    auto &layers = scratch_runtime.layers;
+
+    for (auto it = layers.rbegin(); it != layers.rend(); ++it) {
+      if (!(*it)->is_stage && (*it)->visible && (*it)->covers(x, y))
+        return **it;
+    }
+
+    return scratch_runtime.stage();
+  }
+
+  ScratchValue mouse_x() const { return x; }
+  ScratchValue mouse_y() const { return y; }
+
+  double x = 0;
+  double y = 0;
+  bool down = false;
+
+private:
+  struct Hat {
+    ScratchTarget *target;
+    std::function<void()> script;
+    bool started;
+    size_t thread;
+  };
+
+  std::vector<Hat> hats;
+};
+
+inline ScratchMouse scratch_mouse;
+
+// Replays a file of input events, one per line:
+//
+//   @<frame> <event>     at the start of the given frame
+//   <secs>s <event>      once the clock reaches <secs>
+//
+// where <event> is one of `key down <key>`, `key up <key>`,
+// `mouse move <x> <y>`, `mouse down`, `mouse up` or `click <sprite>`.
+// Empty lines and lines starting with `#` are ignored.
+class ScratchInputReplay {
+public:
+  void start() {
+    if (scratch_options.input_path.empty())
+      return;
+
+    load(scratch_options.input_path);
+
+    scratch_scheduler.frame_hooks.push_back([this] { replay(); });
+    scratch_scheduler.keep_alive.push_back(
+        [this] { return next < events.size() || release_pending; });
+
+    replay();
+  }
+
+private:
+  struct Event {
+    bool by_frame;
+    double when;
+    std::vector<std::string> args;
+  };
+
+  void load(const std::string &path) {
+    std::ifstream file(path);
+
+    if (!file) {
+      std::cerr << "Cannot open input file " << path << std::endl;
+      std::exit(1);
+    }
+
+    std::string line;
+    int line_number = 0;
+
+    while (std::getline(file, line)) {
+      line_number++;
+
+      std::istringstream stream(line);
+      std::vector<std::string> words;
+      std::string word;
+
+      while (stream >> word)
+        words.push_back(word);
+
+      if (words.empty() || words[0][0] == '#')
+        continue;
+
+      Event event;
+      std::string when = words[0];
+      char *end = nullptr;
+
+      if (when[0] == '@') {
+        event.by_frame = true;
+        event.when = std::strtod(when.c_str() + 1, &end);
+      } else {
+        event.by_frame = false;
+        event.when = std::strtod(when.c_str(), &end);
+        if (*end == 's')
+          end++;
+      }
+
+      if (end == when.c_str() || *end != '\0')
+        fail(path, line_number, "expected @<frame> or <secs>s, not '" + when + "'");
+
+      event.args.assign(words.begin() + 1, words.end());
+
+      if (!is_valid(event.args))
+        fail(path, line_number, "unknown event '" + line + "'");
+
+      events.push_back(event);
+    }
+  }
+
+  static bool is_valid(const std::vector<std::string> &args) {
+    if (args.size() >= 3 && args[0] == "key")
+      return args[1] == "down" || args[1] == "up";
+    if (args.size() == 4 && args[0] == "mouse" && args[1] == "move")
+      return true;
+    if (args.size() == 2 && args[0] == "mouse")
+      return args[1] == "down" || args[1] == "up";
+    return args.size() >= 2 && args[0] == "click";
+  }
+
+  [[noreturn]] static void fail(const std::string &path, int line,
+                                const std::string &message) {
+    std::cerr << path << ":" << line << ": " << message << std::endl;
+    std::exit(2);
+  }
+
+  static std::string join(const std::vector<std::string> &args, size_t from) {
+    std::string str;
+    for (size_t i = from; i < args.size(); i++)
+      str += (i > from ? " " : "") + args[i];
+    return str;
+  }
+
+  void replay() {
+    if (release_pending) {
+      scratch_mouse.release();
+      release_pending = false;
+    }
+
+    while (next < events.size()) {
+      Event &event = events[next];
+      bool due = event.by_frame ? scratch_scheduler.frame() >= event.when
+                                : scratch_scheduler.now() >= event.when;
+      if (!due)
+        break;
+
+      apply(event.args);
+      next++;
+    }
+  }
+
+  void apply(const std::vector<std::string> &args) {
+    if (args[0] == "key") {
+      if (args[1] == "down")
+        scratch_keyboard.key_down(join(args, 2));
+      else
+        scratch_keyboard.key_up(join(args, 2));
+    } else if (args[0] == "mouse") {
+      if (args[1] == "move")
+        scratch_mouse.move(std::strtod(args[2].c_str(), nullptr),
+                           std::strtod(args[3].c_str(), nullptr));
+      else if (args[1] == "down")
+        scratch_mouse.press();
+      else
+        scratch_mouse.release();
+    } else {
+      click(join(args, 1));
+    }
+  }
+
+  // Clicks a sprite by name, the button is released on the next frame.
+  void click(const std::string &name) {
+    for (auto target : scratch_runtime.layers) {
+      if (target->name != name)
+        continue;
+
+      if (!target->is_stage)
+        scratch_mouse.move(target->x, target->y);
+
+      scratch_mouse.down = true;
+      scratch_mouse.click(target->visible ? *target : scratch_runtime.stage());
+      release_pending = true;
+      return;
+    }
+
+    std::cerr << "Cannot click unknown sprite " << name << std::endl;
+  }
+
+  std::vector<Event> events;
+  size_t next = 0;
+  bool release_pending = false;
+};
+
+inline ScratchInputReplay scratch_input;
+
+inline bool ScratchRuntime::position_of(const std::string &name, double &x,
+                                        double &y) {
+  if (name == "_mouse_") {
+    x = scratch_mouse.x;
+    y = scratch_mouse.y;
+  } else if (name == "_random_") {
+    std::uniform_real_distribution<double> dist(-0.5, 0.5);
+    x = std::round(480 * dist(rng));
+    y = std::round(360 * dist(rng));
+  } else if (ScratchTarget *other = find_sprite(name)) {
+    x = other->x;
+    y = other->y;
+  } else {
+    return false;
+  }
+
+  return true;
+}
+
+inline bool ScratchRuntime::touching_object(ScratchTarget &target,
+                                            ScratchValue object) {
+  if (target.is_stage || !target.visible)
+    return false;
+
+  ScratchBounds bounds = target.bounds();
+  std::string name = object.to_string();
+
+  if (name == "_mouse_")
+    return target.covers(scratch_mouse.x, scratch_mouse.y);
+
+  if (name == "_edge_")
+    return bounds.left < -240 || bounds.right > 240 || bounds.bottom < -180 ||
+           bounds.top > 180;
+
+  for (auto other : layers) {
+    if (other != &target && !other->is_stage && other->visible &&
+        other->name == name && overlaps(target, *other))
+      return true;
+  }
+
+  return false;
+}
@@ -0,0 +1,215 @@
+#pragma once
+#include "scratchnative.hpp"
+
+#include <csignal>
+#include <map>
+#include <set>
+#include <poll.h>
+#include <termios.h>
+#include <unistd.h>
+
+// Reads keys from the terminal once per frame. Terminals only report key
+// presses, so a key counts as held down for a moment after its last
+// (auto-repeated) press.
+class ScratchKeyboard {
+public:
+  static constexpr double KEY_HOLD_TIME = 0.5;
+
+  void on_key(std::string key, std::function<void()> script) {
+    enable();
+    hats.push_back({normalize(key), script, false, 0});
+  }
+
+  bool is_pressed(ScratchValue key) {
+    enable();
+
+    std::string name = normalize(key.to_string());
+    double now = scratch_scheduler.now();
+
+    for (auto &pressed : last_pressed) {
+      if ((name == "any" || pressed.first == name) &&
+          now - pressed.second < KEY_HOLD_TIME)
+        return true;
+    }
+
+    if (name == "any")
+      return !held.empty();
+
+    return held.count(name) > 0;
+  }
+
+  // Replayed input knows when keys are released, unlike the terminal.
+  void key_down(std::string key) {
+    key = normalize(key);
+    held.insert(key);
+    start_hats(key);
+  }
+
+  void key_up(std::string key) { held.erase(normalize(key)); }
+
+  // Scratch compares keys by their name or by their uppercased first letter.
+  static std::string normalize(std::string key) {
+    static const char *names[] = {"space",      "left arrow", "up arrow",
+                                  "right arrow", "down arrow", "enter", "any"};
+
+    for (auto name : names) {
+      if (key == name)
+        return key;
+    }
+
+    if (key.empty())
+      return key;
+
+    if (key[0] == ' ')
+      return "space";
+
+    return std::string(1, std::toupper(static_cast<unsigned char>(key[0])));
+  }
+
+  // `ask` reads whole lines, so the terminal goes back to normal meanwhile.
+  void suspend() {
+    if (raw)
+      tcsetattr(STDIN_FILENO, TCSANOW, &saved_termios);
+  }
+
+  void resume() {
+    if (raw)
+      set_raw_mode();
+  }
+
+private:
+  struct Hat {
+    std::string key;
+    std::function<void()> script;
+    bool started;
+    size_t thread;
+  };
+
+  void enable() {
+    if (enabled)
+      return;
+
+    enabled = true;
+
+    // replayed keys replace the terminal, and the run ends with the replay
+    if (!scratch_options.input_path.empty())
+      return;
+
+    if (isatty(STDIN_FILENO) && tcgetattr(STDIN_FILENO, &saved_termios) == 0) {
+      raw = true;
+      set_raw_mode();
+
+      std::atexit(restore_terminal);
+      std::signal(SIGINT, restore_and_die);
+      std::signal(SIGTERM, restore_and_die);
+    }
+
+    scratch_scheduler.frame_hooks.push_back([this] { poll_keys(); });
+
+    // a key can start a script at any time, so the project only ends once
+    // stdin is closed (Ctrl-D) or with --time-limit
+    scratch_scheduler.keep_alive.push_back(
+        [this] { return !hats.empty() && !input_closed; });
+  }
+
+  void set_raw_mode() {
+    termios raw_termios = saved_termios;
+    raw_termios.c_lflag &= ~(ICANON | ECHO);
+    raw_termios.c_cc[VMIN] = 0;
+    raw_termios.c_cc[VTIME] = 0;
+    tcsetattr(STDIN_FILENO, TCSANOW, &raw_termios);
+  }
+
+  static void restore_terminal();
+
+  static void restore_and_die(int sig) {
+    restore_terminal();
+    std::signal(sig, SIG_DFL);
+    std::raise(sig);
+  }
+
+  void poll_keys() {
+    if (input_closed)
+      return;
+
+    pollfd fd = {STDIN_FILENO, POLLIN, 0};
+    unsigned char buffer[64];
+
+    while (poll(&fd, 1, 0) > 0) {
+      ssize_t count = read(STDIN_FILENO, buffer, sizeof(buffer));
+
+      if (count <= 0) {
+        input_closed = true;
+        break;
+      }
+
+      pending.append(reinterpret_cast<char *>(buffer), count);
+    }
+
+    size_t i = 0;
+
+    while (i < pending.size()) {
+      if (pending[i] == '\x1b') {
+        // arrow keys are sent as ESC [ A or ESC O A
+        if (i + 2 >= pending.size())
+          break;
+
+        static const char *arrows[] = {"up arrow", "down arrow", "right arrow",
+                                       "left arrow"};
+        char code = pending[i + 2];
+
+        if ((pending[i + 1] == '[' || pending[i + 1] == 'O') && code >= 'A' &&
+            code <= 'D')
+          press(arrows[code - 'A']);
+
+        i += 3;
+        continue;
+      }
+
+      if (pending[i] == '\r' || pending[i] == '\n')
+        press("enter");
+      else
+        press(normalize(std::string(1, pending[i])));
+
+      i++;
+    }
+
+    pending.erase(0, i);
+  }
+
+  void press(std::string key) {
+    last_pressed[key] = scratch_scheduler.now();
+    start_hats(key);
+  }
+
+  void start_hats(const std::string &key) {
+    for (auto &hat : hats) {
+      if (hat.key != key && hat.key != "any")
+        continue;
+
+      // a script that is still running isn't started again
+      if (hat.started && scratch_scheduler.is_running(hat.thread))
+        continue;
+
+      hat.started = true;
+      hat.thread = scratch_scheduler.spawn(hat.script);
+    }
+  }
+
+  bool enabled = false;
+  bool raw = false;
+  bool input_closed = false;
+  termios saved_termios = {};
+
+  std::string pending;
+  std::map<std::string, double> last_pressed;
+  std::set<std::string> held;
+  std::vector<Hat> hats;
+};
+
+inline ScratchKeyboard scratch_keyboard;
+
+inline void ScratchKeyboard::restore_terminal() {
+  if (scratch_keyboard.raw)
+    tcsetattr(STDIN_FILENO, TCSANOW, &scratch_keyboard.saved_termios);
+}
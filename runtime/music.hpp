@@ -0,0 +1,295 @@
+#pragma once
+#include "target.hpp"
+
+#include <fstream>
+#include <map>
+
+// The music extension. Notes and drums aren't heard, with `--midi` they are
+// written to a Standard MIDI File once the program ends: a first track holds
+// the tempo changes, so that beats line up in a DAW, then comes a track for
+// every sprite that played something.
+class ScratchMusic {
+public:
+  static constexpr int TICKS_PER_BEAT = 480;
+  static constexpr int DRUM_CHANNEL = 9;
+
+  // General MIDI programs of the instruments, in the order of their menu.
+  static constexpr int PROGRAMS[] = {0,  4,  19, 24, 27,  32, 45,
+                                     42, 57, 71, 65, 73,  74, 70,
+                                     52, 11, 10, 114, 12, 80, 89};
+
+  // General MIDI percussion keys of the drums, in the order of their menu.
+  static constexpr int DRUM_KEYS[] = {38, 36, 37, 49, 46, 42, 54, 39, 75,
+                                      76, 56, 81, 60, 63, 69, 73, 58, 79};
+
+  void start(const std::vector<ScratchTarget *> &targets) {
+    layers = &targets;
+
+    for (auto target : targets) {
+      if (target->is_stage)
+        stage = target;
+    }
+
+    tempos.push_back({0, tempo().to_number()});
+
+    if (!scratch_options.midi_path.empty())
+      scratch_scheduler.exit_hooks.push_back(
+          [this] { save(scratch_options.midi_path); });
+  }
+
+  void play_note(ScratchTarget &target, ScratchValue note,
+                 ScratchValue beats) {
+    double secs = beats_to_secs(beats);
+
+    // unlike drums, notes of no beats aren't played at all
+    if (secs == 0)
+      return;
+
+    // MIDI stops a little below the highest note of Scratch
+    int key = static_cast<int>(std::lround(
+        std::clamp(note.to_number(), 0.0, 130.0)));
+    play(target, std::min(key, 127), PROGRAMS[instruments[&target]], secs);
+  }
+
+  void play_drum(ScratchTarget &target, ScratchValue drum,
+                 ScratchValue beats) {
+    int index = wrap(std::lround(drum.to_number()) - 1, std::size(DRUM_KEYS));
+    play(target, DRUM_KEYS[index], -1, beats_to_secs(beats));
+  }
+
+  void rest(ScratchValue beats) {
+    wait_until(schedule(start_time(), beats_to_secs(beats)));
+  }
+
+  void set_instrument(ScratchTarget &target, ScratchValue instrument) {
+    instruments[&target] =
+        wrap(std::lround(instrument.to_number()) - 1, std::size(PROGRAMS));
+  }
+
+  void set_tempo(ScratchValue value) {
+    stage->tempo = std::clamp(value.to_number(), 20.0, 500.0);
+    tempos.push_back({start_time(), stage->tempo});
+  }
+
+  void change_tempo(ScratchValue value) {
+    set_tempo(stage->tempo + value.to_number());
+  }
+
+  ScratchValue tempo() const { return stage ? stage->tempo : 60; }
+
+private:
+  struct Note {
+    ScratchTarget *target;
+    int key;
+    // -1 for drums
+    int program;
+    int velocity;
+    double start;
+    double end;
+  };
+
+  struct Tempo {
+    double time;
+    double bpm;
+  };
+
+  struct Event {
+    long tick;
+    // note offs come first, so that a note can be played again right away
+    int order;
+    std::string bytes;
+  };
+
+  double beats_to_secs(ScratchValue beats) const {
+    return std::clamp(beats.to_number(), 0.0, 100.0) * 60 /
+           tempo().to_number();
+  }
+
+  static int wrap(long index, size_t count) {
+    long size = static_cast<long>(count);
+    return static_cast<int>((index % size + size) % size);
+  }
+
+  void play(ScratchTarget &target, int key, int program, double secs) {
+    double start = start_time();
+    double end = schedule(start, secs);
+    int velocity = static_cast<int>(std::lround(target.volume / 100 * 127));
+
+    if (velocity > 0)
+      notes.push_back({&target, key, program, velocity, start, end});
+
+    wait_until(end);
+  }
+
+  // Waits only end on a frame, so a script playing one note after another
+  // would slowly fall behind the beat. Something that comes less than two
+  // frames after the previous note or rest of the same script starts
+  // exactly where that ended instead.
+  double start_time() {
+    double now = scratch_scheduler.now();
+    double frame = scratch_scheduler.is_virtual() ? scratch_options.frame_time
+                                                  : ScratchScheduler::FRAME_TIME;
+
+    auto cursor = cursors.find(scratch_scheduler.current_script());
+    if (cursor != cursors.end() && now >= cursor->second &&
+        now - cursor->second < 2 * frame)
+      return cursor->second;
+
+    return now;
+  }
+
+  // Remembers when the running script is done with something, and gives it.
+  double schedule(double start, double secs) {
+    double end = start + secs;
+    cursors[scratch_scheduler.current_script()] = end;
+    return end;
+  }
+
+  // always yields, like the VM does for notes of no beats
+  static void wait_until(double time) {
+    scratch_scheduler.wait(std::max(0.0, time - scratch_scheduler.now()));
+  }
+
+  long ticks_at(double time) const {
+    double ticks = 0;
+
+    for (size_t i = 0; i < tempos.size() && time > tempos[i].time; i++) {
+      double until = i + 1 < tempos.size() ? tempos[i + 1].time : time;
+      ticks += (std::min(time, until) - tempos[i].time) * tempos[i].bpm / 60 *
+               TICKS_PER_BEAT;
+    }
+
+    return std::lround(ticks);
+  }
+
+  void save(const std::string &path) const {
+    std::vector<std::string> tracks = {tempo_track()};
+    static constexpr int CHANNELS[] = {0,  1,  2,  3,  4,  5,  6, 7,
+                                       8, 10, 11, 12, 13, 14, 15};
+
+    for (auto target : *layers) {
+      // the drums have a channel of their own, the others are shared once
+      // there are more sprites than channels left
+      int channel = CHANNELS[(tracks.size() - 1) % std::size(CHANNELS)];
+      std::string track = note_track(*target, channel);
+
+      if (!track.empty())
+        tracks.push_back(track);
+    }
+
+    std::string header = "MThd";
+    append_be(header, 6, 4);
+    append_be(header, 1, 2); // simultaneous tracks
+    append_be(header, tracks.size(), 2);
+    append_be(header, TICKS_PER_BEAT, 2);
+
+    std::ofstream out(path, std::ios::binary);
+    out << header;
+
+    for (auto &track : tracks) {
+      std::string chunk = "MTrk";
+      append_be(chunk, track.size(), 4);
+      out << chunk << track;
+    }
+
+    if (!out) {
+      std::cerr << "Cannot write MIDI file " << path << std::endl;
+      std::exit(1);
+    }
+  }
+
+  std::string tempo_track() const {
+    std::vector<Event> events;
+
+    for (auto &tempo : tempos) {
+      std::string bytes = "\xff\x51\x03";
+      append_be(bytes, std::lround(60e6 / tempo.bpm), 3);
+      events.push_back({ticks_at(tempo.time), 0, bytes});
+    }
+
+    return track_bytes("Tempo", events);
+  }
+
+  // Empty when the target played nothing.
+  std::string note_track(const ScratchTarget &target, int channel) const {
+    std::vector<Note> played;
+    for (auto &note : notes) {
+      if (note.target == &target)
+        played.push_back(note);
+    }
+
+    if (played.empty())
+      return "";
+
+    std::vector<Event> events;
+    int program = -1;
+
+    for (auto &note : played) {
+      int note_channel = note.program < 0 ? DRUM_CHANNEL : channel;
+      long on = ticks_at(note.start);
+      long off = std::max(on + 1, ticks_at(note.end));
+
+      if (note.program >= 0 && note.program != program) {
+        program = note.program;
+        events.push_back({on, 1, {char(0xc0 | channel), char(program)}});
+      }
+
+      events.push_back(
+          {on, 2, {char(0x90 | note_channel), char(note.key),
+                   char(note.velocity)}});
+      events.push_back(
+          {off, 0, {char(0x80 | note_channel), char(note.key), 0}});
+    }
+
+    std::stable_sort(events.begin(), events.end(),
+                     [](const Event &a, const Event &b) {
+                       return a.tick != b.tick ? a.tick < b.tick
+                                               : a.order < b.order;
+                     });
+
+    return track_bytes(target.name, events);
+  }
+
+  // Events must be sorted by tick.
+  static std::string track_bytes(const std::string &name,
+                                 const std::vector<Event> &events) {
+    std::string bytes = {0, '\xff', '\x03'};
+    append_varlen(bytes, name.size());
+    bytes += name;
+
+    long tick = 0;
+    for (auto &event : events) {
+      append_varlen(bytes, event.tick - tick);
+      bytes += event.bytes;
+      tick = event.tick;
+    }
+
+    bytes += {0, '\xff', '\x2f', 0};
+    return bytes;
+  }
+
+  static void append_be(std::string &out, uint32_t value, int bytes) {
+    for (int i = bytes - 1; i >= 0; i--)
+      out.push_back(static_cast<char>((value >> (i * 8)) & 0xff));
+  }
+
+  // Seven bits per byte, the highest bit set on all but the last one.
+  static void append_varlen(std::string &out, uint32_t value) {
+    std::string bytes(1, static_cast<char>(value & 0x7f));
+
+    while (value >>= 7)
+      bytes.insert(bytes.begin(), static_cast<char>((value & 0x7f) | 0x80));
+
+    out += bytes;
+  }
+
+  const std::vector<ScratchTarget *> *layers = nullptr;
+  ScratchTarget *stage = nullptr;
+
+  std::map<ScratchTarget *, int> instruments;
+  std::map<size_t, double> cursors;
+  std::vector<Note> notes;
+  std::vector<Tempo> tempos;
+};
+
+inline ScratchMusic scratch_music;
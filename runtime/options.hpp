@@ -0,0 +1,141 @@
+#pragma once
+#include "scratchnative.hpp"
+
+#include <cstdio>
+#include <cstring>
+#include <ctime>
+
+// Command line flags understood by every compiled project.
+struct ScratchOptions {
+  // file to read `ask and wait` answers from instead of stdin
+  std::string answers_path;
+
+  // unix time the project pretends to start at, the real time if negative
+  double start_time = -1;
+
+  // seconds the clock advances per frame, the real time is used if zero
+  double frame_time = 0;
+
+  // seconds on the clock after which every script is stopped, none if zero
+  double time_limit = 0;
+
+  std::string username;
+
+  // file of scripted keyboard and mouse events to replay
+  std::string input_path;
+
+  // PNG files to write the pen layer to, once at the end and every frame
+  std::string pen_path;
+  std::string pen_frames;
+
+  // prefix of the PNG files every rendered frame of the stage is written to
+  std::string frames;
+
+  // WAV file every sound played is mixed into
+  std::string sound_path;
+
+  // Standard MIDI File the notes of the music extension are written to
+  std::string midi_path;
+
+  // draw the stage in the terminal every frame
+  bool terminal = false;
+
+  void parse(int argc, char **argv) {
+    for (int i = 1; i < argc; i++) {
+      bool has_value = i + 1 < argc;
+
+      if (!std::strcmp(argv[i], "--answers") && has_value) {
+        answers_path = argv[++i];
+      } else if (!std::strcmp(argv[i], "--start-time") && has_value) {
+        start_time = parse_time(argv[0], argv[++i]);
+      } else if (!std::strcmp(argv[i], "--frame-time") && has_value) {
+        frame_time = parse_number(argv[0], argv[++i]);
+      } else if (!std::strcmp(argv[i], "--time-limit") && has_value) {
+        time_limit = parse_number(argv[0], argv[++i]);
+      } else if (!std::strcmp(argv[i], "--username") && has_value) {
+        username = argv[++i];
+      } else if (!std::strcmp(argv[i], "--input") && has_value) {
+        input_path = argv[++i];
+      } else if (!std::strcmp(argv[i], "--pen") && has_value) {
+        pen_path = argv[++i];
+      } else if (!std::strcmp(argv[i], "--pen-frames") && has_value) {
+        pen_frames = argv[++i];
+      } else if (!std::strcmp(argv[i], "--frames") && has_value) {
+        frames = argv[++i];
+      } else if (!std::strcmp(argv[i], "--sound") && has_value) {
+        sound_path = argv[++i];
+      } else if (!std::strcmp(argv[i], "--midi") && has_value) {
+        midi_path = argv[++i];
+      } else if (!std::strcmp(argv[i], "--terminal")) {
+        terminal = true;
+      } else if (!std::strcmp(argv[i], "-h") || !std::strcmp(argv[i], "--help")) {
+        usage(argv[0]);
+        std::exit(0);
+      } else {
+        std::cerr << argv[0] << ": unknown option '" << argv[i] << "'"
+                  << std::endl;
+        usage(argv[0]);
+        std::exit(2);
+      }
+    }
+  }
+
+  static void usage(const char *program) {
+    std::cerr << "usage: " << program << " [options]\n"
+              << "  --answers <file>      read answers to `ask` from a file\n"
+              << "  --start-time <time>   start the clock at a unix timestamp or\n"
+              << "                        a UTC date (YYYY-MM-DD[THH:MM:SS]),\n"
+              << "                        `current` then reports UTC time\n"
+              << "  --frame-time <secs>   advance a virtual clock by <secs> every\n"
+              << "                        frame instead of following real time\n"
+              << "  --time-limit <secs>   stop every script once the clock reaches\n"
+              << "                        <secs>, projects with `when key pressed`\n"
+              << "                        otherwise run until stdin is closed\n"
+              << "  --username <name>     value of the `username` block\n"
+              << "  --input <file>        replay keyboard and mouse events, keys\n"
+              << "                        typed in the terminal are ignored\n"
+              << "  --pen <file>          write the pen layer as PNG at the end\n"
+              << "  --pen-frames <prefix> write the pen layer of every frame to\n"
+              << "                        <prefix>00001.png, <prefix>00002.png...\n"
+              << "  --frames <prefix>     render the stage every frame, to PNG\n"
+              << "                        files named like with --pen-frames\n"
+              << "  --sound <file>        mix the sounds played into a WAV file\n"
+              << "  --midi <file>         write the notes and drums played to a\n"
+              << "                        MIDI file, one track per sprite\n"
+              << "  --terminal            draw the stage in the terminal, with\n"
+              << "                        speech bubbles and variable monitors\n";
+  }
+
+private:
+  static double parse_number(const char *program, const char *str) {
+    char *end = nullptr;
+    double number = std::strtod(str, &end);
+
+    if (*str == '\0' || *end != '\0' || number < 0) {
+      std::cerr << program << ": invalid number '" << str << "'" << std::endl;
+      std::exit(2);
+    }
+
+    return number;
+  }
+
+  static double parse_time(const char *program, const char *str) {
+    std::tm tm = {};
+    int consumed = 0;
+
+    if (std::sscanf(str, "%d-%d-%d%n", &tm.tm_year, &tm.tm_mon, &tm.tm_mday,
+                    &consumed) != 3)
+      return parse_number(program, str);
+
+    if (str[consumed] == 'T' || str[consumed] == ' ')
+      std::sscanf(str + consumed + 1, "%d:%d:%d", &tm.tm_hour, &tm.tm_min,
+                  &tm.tm_sec);
+
+    tm.tm_year -= 1900;
+    tm.tm_mon -= 1;
+
+    return static_cast<double>(timegm(&tm));
+  }
+};
+
+inline ScratchOptions scratch_options;
@@ -0,0 +1,209 @@
+#pragma once
+#include "canvas.hpp"
+
+#include <cstdio>
+
+// The pen extension, drawing into its own layer which is written out as PNG
+// with `--pen` (once the program ends) or `--pen-frames` (every frame).
+class ScratchPen {
+public:
+  void start() {
+    if (!scratch_options.pen_frames.empty()) {
+      scratch_scheduler.frame_hooks.push_back(
+          [this] { save_frame(scratch_scheduler.frame()); });
+      // the last frame shows whatever changed after the final frame ended
+      scratch_scheduler.exit_hooks.push_back(
+          [this] { save_frame(scratch_scheduler.frame() + 1); });
+    }
+
+    if (!scratch_options.pen_path.empty())
+      scratch_scheduler.exit_hooks.push_back(
+          [this] { save(scratch_options.pen_path); });
+  }
+
+  void clear() { canvas.clear(); }
+
+  void stamp(ScratchTarget &target) { canvas.draw_target(target); }
+
+  void pen_down(ScratchTarget &target) {
+    state(target).down = true;
+    draw(target, target.x, target.y);
+  }
+
+  void pen_up(ScratchTarget &target) { state(target).down = false; }
+
+  // Called after a target moved, draws the line it left behind.
+  void moved(ScratchTarget &target, double old_x, double old_y) {
+    if (state(target).down)
+      draw(target, old_x, old_y);
+  }
+
+  void set_color(ScratchTarget &target, ScratchValue value) {
+    PenState &pen = state(target);
+    double rgb[3], alpha;
+
+    ScratchCanvas::parse_color(value, rgb, alpha);
+    rgb_to_hsv(rgb, pen.color, pen.saturation, pen.brightness);
+    pen.transparency = 100 * (1 - alpha);
+  }
+
+  void set_color_param(ScratchTarget &target, ScratchValue param,
+                       ScratchValue value) {
+    set_param(state(target), param.to_string(), value.to_number(), false);
+  }
+
+  void change_color_param(ScratchTarget &target, ScratchValue param,
+                          ScratchValue value) {
+    set_param(state(target), param.to_string(), value.to_number(), true);
+  }
+
+  void set_size(ScratchTarget &target, ScratchValue size) {
+    state(target).size = std::clamp(size.to_number(), 1.0, 1200.0);
+  }
+
+  void change_size(ScratchTarget &target, ScratchValue size) {
+    set_size(target, state(target).size + size.to_number());
+  }
+
+  // Blocks of Scratch 2, which described colors with a hue and a shade.
+  void set_hue(ScratchTarget &target, ScratchValue hue) {
+    PenState &pen = state(target);
+    set_param(pen, "color", hue.to_number() / 2, false);
+    pen.transparency = 0;
+    apply_shade(pen);
+  }
+
+  void change_hue(ScratchTarget &target, ScratchValue hue) {
+    PenState &pen = state(target);
+    set_param(pen, "color", hue.to_number() / 2, true);
+    apply_shade(pen);
+  }
+
+  void set_shade(ScratchTarget &target, ScratchValue shade) {
+    PenState &pen = state(target);
+    pen.shade = std::fmod(shade.to_number(), 200.0);
+    if (pen.shade < 0)
+      pen.shade += 200;
+    apply_shade(pen);
+  }
+
+  void change_shade(ScratchTarget &target, ScratchValue shade) {
+    set_shade(target, state(target).shade + shade.to_number());
+  }
+
+  ScratchCanvas canvas;
+
+private:
+  struct PenState {
+    bool down = false;
+    double color = 66.66;
+    double saturation = 100;
+    double brightness = 100;
+    double transparency = 0;
+    double size = 1;
+    double shade = 50;
+  };
+
+  PenState &state(ScratchTarget &target) { return states[&target]; }
+
+  void draw(ScratchTarget &target, double from_x, double from_y) {
+    PenState &pen = state(target);
+    double rgb[3];
+
+    hsv_to_rgb(pen.color * 3.6, pen.saturation / 100, pen.brightness / 100,
+               rgb);
+    canvas.draw_line(from_x, from_y, target.x, target.y, pen.size, rgb,
+                     1 - pen.transparency / 100);
+  }
+
+  void save_frame(long number) {
+    char name[16];
+    std::snprintf(name, sizeof(name), "%05ld.png", number);
+    save(scratch_options.pen_frames + name);
+  }
+
+  void save(const std::string &path) {
+    if (!canvas.write_png(path)) {
+      std::cerr << "Cannot write pen image " << path << std::endl;
+      std::exit(1);
+    }
+  }
+
+  static void set_param(PenState &pen, std::string param, double value,
+                        bool change) {
+    std::transform(param.begin(), param.end(), param.begin(), ::tolower);
+
+    if (param == "color") {
+      // wraps like the VM, which uses a range of 101 values here
+      value += change ? pen.color : 0;
+      pen.color = value - std::floor(value / 101) * 101;
+    } else if (param == "saturation") {
+      pen.saturation =
+          std::clamp(value + (change ? pen.saturation : 0), 0.0, 100.0);
+    } else if (param == "brightness") {
+      pen.brightness =
+          std::clamp(value + (change ? pen.brightness : 0), 0.0, 100.0);
+    } else if (param == "transparency") {
+      pen.transparency =
+          std::clamp(value + (change ? pen.transparency : 0), 0.0, 100.0);
+    }
+  }
+
+  // Mixes the hue with black or white depending on the shade.
+  static void apply_shade(PenState &pen) {
+    double rgb[3];
+    hsv_to_rgb(pen.color * 3.6, 1, 1, rgb);
+
+    double shade = pen.shade > 100 ? 200 - pen.shade : pen.shade;
+
+    for (auto &component : rgb) {
+      if (shade < 50)
+        component *= std::clamp((10 + shade) / 60, 0.0, 1.0);
+      else
+        component += (1 - component) * std::clamp((shade - 50) / 60, 0.0, 1.0);
+    }
+
+    rgb_to_hsv(rgb, pen.color, pen.saturation, pen.brightness);
+  }
+
+  // `h` in degrees, the result is rounded down to 8 bits like in the VM.
+  static void hsv_to_rgb(double h, double s, double v, double rgb[3]) {
+    h = std::fmod(h, 360.0);
+    if (h < 0)
+      h += 360;
+    s = std::clamp(s, 0.0, 1.0);
+    v = std::clamp(v, 0.0, 1.0);
+
+    int i = static_cast<int>(std::floor(h / 60));
+    double f = h / 60 - i;
+    double p = v * (1 - s), q = v * (1 - s * f), t = v * (1 - s * (1 - f));
+    double sectors[6][3] = {{v, t, p}, {q, v, p}, {p, v, t},
+                            {p, q, v}, {t, p, v}, {v, p, q}};
+
+    for (int c = 0; c < 3; c++)
+      rgb[c] = std::floor(sectors[i % 6][c] * 255) / 255;
+  }
+
+  // Gives the pen color, saturation and brightness, all from 0 to 100.
+  static void rgb_to_hsv(const double rgb[3], double &color, double &saturation,
+                         double &brightness) {
+    double r = rgb[0], g = rgb[1], b = rgb[2];
+    double min = std::min({r, g, b}), max = std::max({r, g, b});
+    double h = 0, s = 0;
+
+    if (min != max) {
+      double f = r == min ? g - b : (g == min ? b - r : r - g);
+      double i = r == min ? 3 : (g == min ? 5 : 1);
+      h = std::fmod((i - f / (max - min)) * 60, 360.0);
+      s = (max - min) / max;
+    }
+
+    color = h / 360 * 100;
+    saturation = s * 100;
+    brightness = max * 100;
+  }
+
+  std::map<ScratchTarget *, PenState> states;
+};
+
+inline ScratchPen scratch_pen;
@@ -0,0 +1,92 @@
+#pragma once
+#include "pen.hpp"
+
+#include <cstdio>
+
+// Composites the stage without a GPU: the backdrop, the pen layer, then every
+// visible sprite from the back to the front. Speech bubbles aren't drawn.
+class ScratchRenderer {
+public:
+  void start(const std::vector<ScratchTarget *> &targets) {
+    layers = &targets;
+
+    if (scratch_options.frames.empty())
+      return;
+
+    // the last frame shows whatever changed after the final frame ended
+    scratch_scheduler.frame_hooks.push_back(
+        [this] { save_frame(scratch_scheduler.frame()); });
+    scratch_scheduler.exit_hooks.push_back(
+        [this] { save_frame(scratch_scheduler.frame() + 1); });
+  }
+
+  // Renders the stage, optionally leaving one target out.
+  void render(ScratchCanvas &canvas, const ScratchTarget *skip = nullptr) {
+    canvas.fill(255, 255, 255);
+
+    for (auto target : *layers) {
+      if (target != skip && target->visible)
+        canvas.draw_target(*target);
+
+      // the pen layer sits right above the backdrop
+      if (target->is_stage)
+        canvas.draw_canvas(scratch_pen.canvas);
+    }
+  }
+
+  // Whether any pixel of the target covers the given color on the stage.
+  // Colors are compared with the same precision as in Scratch.
+  bool touching_color(const ScratchTarget &target, ScratchValue color) {
+    if (target.is_stage || !target.visible)
+      return false;
+
+    double rgb[3], alpha;
+    ScratchCanvas::parse_color(color, rgb, alpha);
+
+    unsigned char wanted[3];
+    for (int i = 0; i < 3; i++)
+      wanted[i] = static_cast<unsigned char>(std::lround(rgb[i] * 255));
+
+    // the ghost effect doesn't make a sprite any less solid
+    mask.clear();
+    mask.draw_target(target, false);
+    render(stage, &target);
+
+    for (int py = 0; py < ScratchCanvas::HEIGHT; py++) {
+      for (int px = 0; px < ScratchCanvas::WIDTH; px++) {
+        if (mask.pixel(px, py)[3] > 0 &&
+            color_matches(stage.pixel(px, py), wanted))
+          return true;
+      }
+    }
+
+    return false;
+  }
+
+private:
+  static bool color_matches(const unsigned char *a, const unsigned char *b) {
+    return (a[0] & 0xf8) == (b[0] & 0xf8) && (a[1] & 0xf8) == (b[1] & 0xf8) &&
+           (a[2] & 0xf0) == (b[2] & 0xf0);
+  }
+
+  void save_frame(long number) {
+    char name[16];
+    std::snprintf(name, sizeof(name), "%05ld.png", number);
+    std::string path = scratch_options.frames + name;
+
+    render(frame);
+
+    if (!frame.write_png(path)) {
+      std::cerr << "Cannot write frame " << path << std::endl;
+      std::exit(1);
+    }
+  }
+
+  const std::vector<ScratchTarget *> *layers = nullptr;
+
+  ScratchCanvas frame;
+  ScratchCanvas stage;
+  ScratchCanvas mask;
+};
+
+inline ScratchRenderer scratch_renderer;
@@ -0,0 +1,459 @@
+#pragma once
+#include "audio.hpp"
+#include "music.hpp"
+#include "pen.hpp"
+#include "renderer.hpp"
+#include "target.hpp"
+#include "terminal.hpp"
+
+#include <ctime>
+#include <fstream>
+#include <set>
+
+class ScratchRuntime {
+public:
+  void add_target(ScratchTarget &target) { layers.push_back(&target); }
+
+  // Called once every target has been added, sorts them by their saved
+  // `layerOrder` so that the stage ends up at the bottom.
+  void start() {
+    std::stable_sort(layers.begin(), layers.end(),
+                     [](ScratchTarget *a, ScratchTarget *b) {
+                       if (a->is_stage != b->is_stage)
+                         return a->is_stage;
+                       return a->layer_order < b->layer_order;
+                     });
+    update_layer_order();
+
+    if (scratch_options.start_time >= 0) {
+      start_wall_time = scratch_options.start_time;
+    } else {
+      std::chrono::duration<double> since_epoch =
+          std::chrono::system_clock::now().time_since_epoch();
+      start_wall_time = since_epoch.count();
+    }
+
+    if (!scratch_options.answers_path.empty()) {
+      answers_file.open(scratch_options.answers_path);
+
+      if (!answers_file) {
+        std::cerr << "Cannot open answers file "
+                  << scratch_options.answers_path << std::endl;
+        std::exit(1);
+      }
+    }
+
+    scratch_audio.start();
+    scratch_music.start(layers);
+    scratch_pen.start();
+    scratch_renderer.start(layers);
+    scratch_terminal.start(layers);
+  }
+
+  ScratchTarget &stage() { return *layers.front(); }
+
+  void switch_backdrop(ScratchValue value) {
+    stage().switch_costume(value, rng);
+  }
+
+  void next_backdrop() { stage().next_costume(); }
+
+  void switch_costume(ScratchTarget &target, ScratchValue value) {
+    target.switch_costume(value, rng);
+  }
+
+  void say(ScratchTarget &target, ScratchValue message, bool think) {
+    target.bubble = message.to_string();
+    target.bubble_think = think;
+    target.changed();
+
+    // the terminal view shows the bubble instead, which a line would scroll
+    if (!target.bubble.empty() && !scratch_options.terminal)
+      std::cout << target.name << (think ? " thinks: " : ": ") << target.bubble
+                << std::endl;
+  }
+
+  void say_for_secs(ScratchTarget &target, ScratchValue message,
+                    ScratchValue secs, bool think) {
+    say(target, message, think);
+    std::string bubble = target.bubble;
+
+    scratch_scheduler.wait(secs.to_number());
+
+    // another say block may have replaced the bubble in the meantime
+    if (target.bubble == bubble) {
+      target.bubble.clear();
+      target.changed();
+    }
+  }
+
+  // Sprites ask through a speech bubble, the stage just shows the question.
+  void ask(ScratchTarget &target, ScratchValue question) {
+    if (target.is_stage) {
+      std::string text = question.to_string();
+      if (!text.empty())
+        std::cout << text << std::endl;
+    } else {
+      say(target, question, false);
+    }
+
+    std::istream &input = answers_file.is_open() ? answers_file : std::cin;
+    std::string line;
+
+    scratch_keyboard.suspend();
+
+    // once the input runs out every question gets an empty answer
+    if (std::getline(input, line)) {
+      if (!line.empty() && line.back() == '\r')
+        line.pop_back();
+      answer = line;
+    } else {
+      answer = "";
+    }
+
+    scratch_keyboard.resume();
+
+    if (!target.is_stage && !target.bubble.empty()) {
+      target.bubble.clear();
+      target.changed();
+    }
+  }
+
+  ScratchValue timer() const { return scratch_scheduler.now() - timer_start; }
+
+  void reset_timer() { timer_start = scratch_scheduler.now(); }
+
+  // Unix time as seen by the project, follows the scheduler clock.
+  double wall_time() const { return start_wall_time + scratch_scheduler.now(); }
+
+  ScratchValue current(std::string what) const {
+    std::time_t time = static_cast<std::time_t>(std::floor(wall_time()));
+    std::tm tm = {};
+
+    // a given start time is a UTC date, so the project sees it unchanged
+    // whatever the time zone of the machine running it
+    if (scratch_options.start_time >= 0)
+      gmtime_r(&time, &tm);
+    else
+      localtime_r(&time, &tm);
+
+    std::transform(what.begin(), what.end(), what.begin(), ::tolower);
+
+    if (what == "year")
+      return tm.tm_year + 1900;
+    if (what == "month")
+      return tm.tm_mon + 1;
+    if (what == "date")
+      return tm.tm_mday;
+    if (what == "dayofweek")
+      return tm.tm_wday + 1;
+    if (what == "hour")
+      return tm.tm_hour;
+    if (what == "minute")
+      return tm.tm_min;
+    if (what == "second")
+      return tm.tm_sec;
+
+    return 0;
+  }
+
+  ScratchValue days_since_2000() const {
+    // 2000-01-01T00:00:00Z
+    return (wall_time() - 946684800.0) / 86400.0;
+  }
+
+  ScratchValue username() const { return scratch_options.username; }
+
+  void go_to_front(ScratchTarget &target) { move_layer(target, layers.size()); }
+
+  void go_to_back(ScratchTarget &target) { move_layer(target, 1); }
+
+  void go_forward_layers(ScratchTarget &target, ScratchValue layers) {
+    move_layer(target, layer_of(target) + std::lround(layers.to_number()));
+  }
+
+  void go_backward_layers(ScratchTarget &target, ScratchValue layers) {
+    move_layer(target, layer_of(target) - std::lround(layers.to_number()));
+  }
+
+  ScratchTarget *find_sprite(const std::string &name) {
+    for (auto target : layers) {
+      if (!target->is_stage && target->name == name)
+        return target;
+    }
+
+    return nullptr;
+  }
+
+  ScratchValue sensing_of(ScratchValue object, std::string property) {
+    std::string name = object.to_string();
+    ScratchTarget *target = name == "_stage_" ? &stage() : find_sprite(name);
+
+    if (!target)
+      return 0;
+
+    if (property == "volume")
+      return target->reported_volume();
+
+    if (target->is_stage) {
+      if (property == "backdrop #")
+        return target->costume_number();
+      if (property == "backdrop name")
+        return target->costume_name();
+    } else {
+      if (property == "x position")
+        return target->x;
+      if (property == "y position")
+        return target->y;
+      if (property == "direction")
+        return target->direction;
+      if (property == "costume #")
+        return target->costume_number();
+      if (property == "costume name")
+        return target->costume_name();
+      if (property == "size")
+        return target->reported_size();
+    }
+
+    auto variable = target->variables.find(property);
+    if (variable != target->variables.end())
+      return *variable->second;
+
+    return 0;
+  }
+
+  // Position of the mouse (`_mouse_`), a random one (`_random_`) or of a
+  // sprite, false if there is no such sprite. Defined next to the mouse.
+  bool position_of(const std::string &name, double &x, double &y);
+
+  ScratchValue distance_to(ScratchTarget &target, ScratchValue object) {
+    double x, y;
+    std::string name = object.to_string();
+
+    if (target.is_stage || name == "_random_" || !position_of(name, x, y))
+      return 10000;
+
+    return std::hypot(target.x - x, target.y - y);
+  }
+
+  // defined next to the mouse, which it also tests against
+  bool touching_object(ScratchTarget &target, ScratchValue object);
+
+  bool touching_color(ScratchTarget &target, ScratchValue color) {
+    return scratch_renderer.touching_color(target, color);
+  }
+
+  void set_xy(ScratchTarget &target, double x, double y) {
+    if (target.is_stage)
+      return;
+
+    double old_x = target.x, old_y = target.y;
+    target.keep_in_fence(x, y);
+    target.x = x;
+    target.y = y;
+
+    scratch_pen.moved(target, old_x, old_y);
+    target.changed();
+  }
+
+  void move_steps(ScratchTarget &target, ScratchValue steps) {
+    double angle = (90 - target.direction) * M_PI / 180;
+    set_xy(target, target.x + steps.to_number() * std::cos(angle),
+           target.y + steps.to_number() * std::sin(angle));
+  }
+
+  void turn_right(ScratchTarget &target, ScratchValue degrees) {
+    target.set_direction(target.direction + degrees.to_number());
+  }
+
+  void turn_left(ScratchTarget &target, ScratchValue degrees) {
+    target.set_direction(target.direction - degrees.to_number());
+  }
+
+  void point_in_direction(ScratchTarget &target, ScratchValue direction) {
+    target.set_direction(direction.to_number());
+  }
+
+  void point_towards(ScratchTarget &target, ScratchValue object) {
+    double x, y;
+    std::string name = object.to_string();
+
+    if (name == "_random_") {
+      std::uniform_int_distribution<int> dist(0, 360);
+      target.set_direction(dist(rng) - 180);
+    } else if (position_of(name, x, y)) {
+      double dx = x - target.x, dy = y - target.y;
+      target.set_direction(90 - std::atan2(dy, dx) * 180 / M_PI);
+    }
+  }
+
+  void go_to_xy(ScratchTarget &target, ScratchValue x, ScratchValue y) {
+    set_xy(target, x.to_number(), y.to_number());
+  }
+
+  void go_to(ScratchTarget &target, ScratchValue object) {
+    double x, y;
+    if (position_of(object.to_string(), x, y))
+      set_xy(target, x, y);
+  }
+
+  // Moves a bit further every frame until the time is up.
+  void glide_to_xy(ScratchTarget &target, ScratchValue secs, ScratchValue x,
+                   ScratchValue y) {
+    double duration = secs.to_number();
+    double start = scratch_scheduler.now();
+    double start_x = target.x, start_y = target.y;
+    double end_x = x.to_number(), end_y = y.to_number();
+
+    while (scratch_scheduler.now() - start < duration) {
+      double fraction = (scratch_scheduler.now() - start) / duration;
+      set_xy(target, start_x + (end_x - start_x) * fraction,
+             start_y + (end_y - start_y) * fraction);
+      scratch_scheduler.wait(0);
+    }
+
+    set_xy(target, end_x, end_y);
+  }
+
+  void glide_to(ScratchTarget &target, ScratchValue secs, ScratchValue object) {
+    double x, y;
+    if (position_of(object.to_string(), x, y))
+      glide_to_xy(target, secs, x, y);
+  }
+
+  void change_x(ScratchTarget &target, ScratchValue dx) {
+    set_xy(target, target.x + dx.to_number(), target.y);
+  }
+
+  void set_x(ScratchTarget &target, ScratchValue x) {
+    set_xy(target, x.to_number(), target.y);
+  }
+
+  void change_y(ScratchTarget &target, ScratchValue dy) {
+    set_xy(target, target.x, target.y + dy.to_number());
+  }
+
+  void set_y(ScratchTarget &target, ScratchValue y) {
+    set_xy(target, target.x, y.to_number());
+  }
+
+  // Turns away from the nearest edge the sprite went past, if any.
+  void if_on_edge_bounce(ScratchTarget &target) {
+    if (target.is_stage || target.costumes.empty())
+      return;
+
+    ScratchBounds bounds = target.bounds();
+    double distances[4] = {
+        std::max(0.0, 240 + bounds.left), std::max(0.0, 180 - bounds.top),
+        std::max(0.0, 240 - bounds.right), std::max(0.0, 180 + bounds.bottom)};
+    int nearest = static_cast<int>(
+        std::min_element(distances, distances + 4) - distances);
+
+    if (distances[nearest] > 0)
+      return;
+
+    double angle = (90 - target.direction) * M_PI / 180;
+    double dx = std::cos(angle), dy = -std::sin(angle);
+
+    if (nearest == 0)
+      dx = std::max(0.2, std::abs(dx));
+    else if (nearest == 1)
+      dy = std::max(0.2, std::abs(dy));
+    else if (nearest == 2)
+      dx = -std::max(0.2, std::abs(dx));
+    else
+      dy = -std::max(0.2, std::abs(dy));
+
+    target.set_direction(std::atan2(dy, dx) * 180 / M_PI + 90);
+    set_xy(target, target.x, target.y);
+  }
+
+  // A whole number when both ends are whole, like in the editor, and any
+  // number between them otherwise.
+  ScratchValue random(ScratchValue from, ScratchValue to) {
+    auto whole = [](ScratchValue value) {
+      double number = value.to_number();
+      return number == std::floor(number) &&
+             value.to_string().find('.') == std::string::npos;
+    };
+    double low = std::min(from.to_number(), to.to_number());
+    double high = std::max(from.to_number(), to.to_number());
+
+    if (whole(from) && whole(to)) {
+      std::uniform_int_distribution<long long> dist(low, high);
+      return (double)dist(rng);
+    }
+
+    std::uniform_real_distribution<double> dist(low, high);
+    return dist(rng);
+  }
+
+  // Stands for a block that wasn't compiled, and warns the first time it is
+  // run. Its reporter gives an empty value.
+  ScratchValue unsupported(ScratchTarget &target, const std::string &opcode,
+                           const std::string &block_id) {
+    if (warned.insert(block_id).second)
+      std::cerr << "Warning: " << target.name << ": block " << block_id
+                << " (" << opcode << ") is not supported" << std::endl;
+
+    return ScratchValue();
+  }
+
+  std::vector<ScratchTarget *> layers;
+  std::mt19937 rng{std::random_device{}()};
+
+  ScratchValue answer = "";
+
+private:
+  std::ifstream answers_file;
+  std::set<std::string> warned;
+
+  double start_wall_time = 0;
+  double timer_start = 0;
+
+  // Whether two targets cover a same pixel of the stage.
+  static bool overlaps(const ScratchTarget &a, const ScratchTarget &b) {
+    ScratchBounds first = a.bounds(), second = b.bounds();
+
+    if (!first.intersects(second))
+      return false;
+
+    double left = std::max({first.left, second.left, -240.0});
+    double right = std::min({first.right, second.right, 240.0});
+    double bottom = std::max({first.bottom, second.bottom, -180.0});
+    double top = std::min({first.top, second.top, 180.0});
+
+    // sampled at the center of every pixel
+    for (double y = std::floor(bottom) + 0.5; y < top; y++) {
+      for (double x = std::floor(left) + 0.5; x < right; x++) {
+        if (a.covers(x, y) && b.covers(x, y))
+          return true;
+      }
+    }
+
+    return false;
+  }
+
+  long layer_of(ScratchTarget &target) {
+    return std::find(layers.begin(), layers.end(), &target) - layers.begin();
+  }
+
+  void move_layer(ScratchTarget &target, long layer) {
+    if (target.is_stage)
+      return;
+
+    layers.erase(layers.begin() + layer_of(target));
+    layer = std::clamp(layer, 1L, static_cast<long>(layers.size()));
+    layers.insert(layers.begin() + layer, &target);
+
+    update_layer_order();
+    target.changed();
+  }
+
+  void update_layer_order() {
+    for (size_t i = 0; i < layers.size(); i++)
+      layers[i]->layer_order = static_cast<int>(i);
+  }
+};
+
+inline ScratchRuntime scratch_runtime;
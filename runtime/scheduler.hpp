@@ -0,0 +1,254 @@
+#pragma once
+#include "scratchnative.hpp"
+
+#include <chrono>
+#include <condition_variable>
+#include <functional>
+#include <memory>
+#include <mutex>
+#include <thread>
+
+// Thrown in every script once the time limit is reached, to unwind it.
+struct ScratchStop {};
+
+// Every script runs on its own thread, but only the thread holding the baton
+// (`current`) is ever running: scripts hand it over when they yield, which
+// gives the same interleaving as the Scratch VM without needing coroutines.
+class ScratchScheduler {
+public:
+  static constexpr double FRAME_TIME = 1.0 / 30.0;
+
+  // With a virtual clock the frame time can't be used to decide when a busy
+  // frame ends, so it ends after a fixed number of passes instead.
+  static constexpr int VIRTUAL_PASSES_PER_FRAME = 1000;
+
+  // Starts a script, it first runs once every script before it had its turn.
+  size_t spawn(std::function<void()> script) {
+    std::lock_guard<std::mutex> lock(mutex);
+
+    size_t index = threads.size();
+    auto thread = std::make_unique<Thread>();
+
+    thread->handle = std::thread([this, index, script] {
+      {
+        std::unique_lock<std::mutex> lock(mutex);
+        cv.wait(lock, [&] { return current == index; });
+      }
+
+      try {
+        if (!stopping)
+          script();
+      } catch (const ScratchStop &) {
+      }
+
+      std::unique_lock<std::mutex> lock(mutex);
+      threads[index]->done = true;
+      advance(lock);
+      cv.notify_all();
+    });
+
+    threads.push_back(std::move(thread));
+
+    return index;
+  }
+
+  // Index given by `spawn` to the script that is running.
+  size_t current_script() {
+    std::lock_guard<std::mutex> lock(mutex);
+    return current;
+  }
+
+  bool is_running(size_t index) {
+    std::lock_guard<std::mutex> lock(mutex);
+    return !threads[index]->done;
+  }
+
+  // Runs every spawned script until they are all done and nothing is left
+  // that could start new ones, or until the time limit.
+  void run() {
+    std::unique_lock<std::mutex> lock(mutex);
+    start_time = frame_start = std::chrono::steady_clock::now();
+
+    while (true) {
+      size_t first = next_alive(0);
+
+      if (first == MAIN) {
+        lock.unlock();
+        join_finished();
+
+        bool alive = false;
+        for (auto &predicate : keep_alive)
+          alive |= predicate();
+
+        if (!alive || stopping) {
+          for (auto &hook : exit_hooks)
+            hook();
+          break;
+        }
+
+        // nothing runs, but the frame hooks can still start new scripts
+        end_frame();
+        lock.lock();
+        continue;
+      }
+
+      pass_progress = false;
+      current = first;
+      cv.notify_all();
+      cv.wait(lock, [&] { return current == MAIN; });
+    }
+  }
+
+  // Called at the end of every loop iteration, gives the other scripts a
+  // chance to run.
+  void yield() { switch_thread(true); }
+
+  void wait(double secs) {
+    double until = now() + secs;
+
+    // always yield at least once, like the VM does for `wait 0`
+    do {
+      switch_thread(false);
+    } while (now() < until);
+  }
+
+  // Seconds since the project started.
+  double now() const {
+    if (is_virtual())
+      return virtual_time;
+
+    std::chrono::duration<double> elapsed =
+        std::chrono::steady_clock::now() - start_time;
+    return elapsed.count();
+  }
+
+  bool is_virtual() const { return scratch_options.frame_time > 0; }
+
+  void request_redraw() { redraw_requested = true; }
+
+  // Number of frames that ended so far.
+  long frame() const { return frames; }
+
+  std::vector<std::function<void()>> frame_hooks;
+
+  // The program keeps running while any of these return true.
+  std::vector<std::function<bool()>> keep_alive;
+
+  // Run once every script is done, before `run` returns.
+  std::vector<std::function<void()>> exit_hooks;
+
+private:
+  static constexpr size_t MAIN = static_cast<size_t>(-1);
+
+  struct Thread {
+    std::thread handle;
+    bool done = false;
+  };
+
+  // Only called by the main thread while no script is running.
+  void join_finished() {
+    for (auto &thread : threads) {
+      if (thread->handle.joinable())
+        thread->handle.join();
+    }
+  }
+
+  void switch_thread(bool progress) {
+    std::unique_lock<std::mutex> lock(mutex);
+    size_t self = current;
+
+    pass_progress |= progress;
+    advance(lock);
+
+    if (current != self) {
+      cv.notify_all();
+      cv.wait(lock, [&] { return current == self; });
+    }
+
+    if (stopping)
+      throw ScratchStop();
+  }
+
+  size_t next_alive(size_t from) const {
+    for (size_t i = from; i < threads.size(); i++) {
+      if (!threads[i]->done)
+        return i;
+    }
+
+    return MAIN;
+  }
+
+  // Hands the baton to the next script, ending the frame once every script
+  // had its turn and either something was drawn, nobody made progress or
+  // the frame ran out of time.
+  void advance(std::unique_lock<std::mutex> &lock) {
+    size_t next = next_alive(current + 1);
+
+    if (next == MAIN) {
+      if (next_alive(0) != MAIN &&
+          (redraw_requested || !pass_progress || frame_over())) {
+        lock.unlock();
+        end_frame();
+        lock.lock();
+      }
+
+      passes++;
+      pass_progress = false;
+      next = next_alive(0);
+    }
+
+    current = next;
+  }
+
+  bool frame_over() const {
+    if (is_virtual())
+      return passes >= VIRTUAL_PASSES_PER_FRAME;
+
+    std::chrono::duration<double> frame_elapsed =
+        std::chrono::steady_clock::now() - frame_start;
+    return frame_elapsed.count() > FRAME_TIME * 0.75;
+  }
+
+  // Waits for the next frame, then lets the hooks handle input and
+  // drawing for it.
+  void end_frame() {
+    redraw_requested = false;
+    passes = 0;
+
+    if (is_virtual()) {
+      virtual_time += scratch_options.frame_time;
+    } else {
+      auto frame_end =
+          frame_start + std::chrono::duration_cast<std::chrono::nanoseconds>(
+                            std::chrono::duration<double>(FRAME_TIME));
+      std::this_thread::sleep_until(frame_end);
+      frame_start = std::chrono::steady_clock::now();
+    }
+
+    frames++;
+
+    for (auto &hook : frame_hooks)
+      hook();
+
+    if (scratch_options.time_limit > 0 && now() >= scratch_options.time_limit)
+      stopping = true;
+  }
+
+  std::mutex mutex;
+  std::condition_variable cv;
+  std::vector<std::unique_ptr<Thread>> threads;
+  size_t current = MAIN;
+
+  bool pass_progress = false;
+  bool redraw_requested = false;
+  bool stopping = false;
+  int passes = 0;
+  long frames = 0;
+
+  double virtual_time = 0;
+
+  std::chrono::steady_clock::time_point start_time;
+  std::chrono::steady_clock::time_point frame_start;
+};
+
+inline ScratchScheduler scratch_scheduler;
@@ -1,5 +1,8 @@
 #pragma once
+#include <cmath>
+#include <cstdlib>
 #include <iostream>
+#include <sstream>
 #include <string>
 #include <variant>
 #include <vector>
@@ -32,6 +35,9 @@ class ScratchValue {
 public:
   ScratchValue() : _type(EMPTY) {}
   ScratchValue(int number) : _value((double)number), _type(NUMBER) {}
+  // boolean reporters read as text, like `say <touching edge?>`
+  ScratchValue(bool boolean)
+      : _value(std::string(boolean ? "true" : "false")), _type(STRING) {}
   ScratchValue(double number) : _value(number), _type(NUMBER) {}
   ScratchValue(std::string string) : _value(string), _type(STRING) {}
   ScratchValue(const char *string) : _value(string), _type(STRING) {}
@@ -116,9 +122,66 @@ public:
     }
   }
 
+  bool is_number() const { return _type == NUMBER; }
+
+  double to_number() const {
+    switch (_type) {
+    case NUMBER:
+      return std::get<double>(_value);
+    case STRING: {
+      const std::string &str = std::get<std::string>(_value);
+      char *end = nullptr;
+      double number = std::strtod(str.c_str(), &end);
+      if (str.empty() || *end != '\0' || std::isnan(number))
+        return 0;
+      return number;
+    }
+    default:
+      return 0;
+    }
+  }
+
+  std::string to_string() const {
+    switch (_type) {
+    case NUMBER: {
+      double number = std::get<double>(_value);
+      if (number == std::floor(number) && std::abs(number) < 1e15)
+        return std::to_string(static_cast<long long>(number));
+      std::ostringstream stream;
+      stream << number;
+      return stream.str();
+    }
+    case STRING:
+      return std::get<std::string>(_value);
+    case LIST: {
+      std::string str;
+      for (auto &elem : std::get<ScratchList>(_value)) {
+        if (!str.empty())
+          str += " ";
+        str += elem.to_string();
+      }
+      return str;
+    }
+    default:
+      return "";
+    }
+  }
+
   template <typename T> T &get() { return std::get<T>(_value); }
 
 private:
   std::variant<double, std::string, ScratchList> _value;
   ValueType _type;
-};
\ No newline at end of file
+};
+
+#include "options.hpp"
+#include "scheduler.hpp"
+#include "target.hpp"
+#include "keyboard.hpp"
+#include "audio.hpp"
+#include "music.hpp"
+#include "pen.hpp"
+#include "renderer.hpp"
+#include "terminal.hpp"
+#include "runtime.hpp"
+#include "input.hpp"
@@ -0,0 +1,407 @@
+#pragma once
+#include "scratchnative.hpp"
+
+#include <algorithm>
+#include <cctype>
+#include <map>
+#include <random>
+
+struct ScratchCostume {
+  std::string name;
+  std::string md5ext;
+  std::string data_format;
+  double rotation_center_x;
+  double rotation_center_y;
+  int bitmap_resolution;
+  double width;
+  double height;
+  // RGBA rows, only loaded for bitmaps
+  const unsigned char *pixels = nullptr;
+};
+
+struct ScratchSound {
+  std::string name;
+  std::string md5ext;
+  int rate;
+  long sample_count;
+  // mono 16 bit little endian samples, unless they couldn't be decoded
+  const unsigned char *samples = nullptr;
+};
+
+// Axis aligned box in stage coordinates, y pointing up.
+struct ScratchBounds {
+  double left;
+  double right;
+  double bottom;
+  double top;
+
+  bool contains(double x, double y) const {
+    return x >= left && x <= right && y >= bottom && y <= top;
+  }
+
+  bool intersects(const ScratchBounds &other) const {
+    return left <= other.right && other.left <= right &&
+           bottom <= other.top && other.bottom <= top;
+  }
+};
+
+class ScratchTarget {
+public:
+  ScratchTarget(std::string name, bool is_stage,
+                std::vector<ScratchCostume> costumes,
+                std::vector<ScratchSound> sounds, int current_costume,
+                int layer_order, double x, double y, double size,
+                double direction, std::string rotation_style, bool visible,
+                double volume)
+      : name(name), is_stage(is_stage), costumes(costumes), sounds(sounds),
+        current_costume(current_costume), layer_order(layer_order), x(x),
+        y(y), size(size), direction(direction),
+        rotation_style(rotation_style), visible(visible), volume(volume) {}
+
+  std::string name;
+  bool is_stage;
+
+  std::vector<ScratchCostume> costumes;
+  std::vector<ScratchSound> sounds;
+  int current_costume;
+  int layer_order;
+
+  double x;
+  double y;
+  double size;
+  double direction;
+  std::string rotation_style;
+  bool visible;
+  double volume;
+
+  // beats per minute, only used on the stage
+  double tempo = 60;
+
+  std::map<std::string, double> effects;
+
+  // local variables by name, for the `of` block of other sprites
+  std::map<std::string, ScratchValue *> variables;
+
+  std::string bubble;
+  bool bubble_think = false;
+
+  const ScratchCostume &costume() const { return costumes[current_costume]; }
+
+  void set_costume(long index) {
+    long count = static_cast<long>(costumes.size());
+    if (count == 0)
+      return;
+    current_costume = static_cast<int>(((index % count) + count) % count);
+    changed();
+  }
+
+  // Mirrors the lookup order of the Scratch VM: a costume name wins over the
+  // special menu entries, which win over a numeric string.
+  void switch_costume(ScratchValue value, std::mt19937 &rng) {
+    if (value.is_number()) {
+      set_costume(std::lround(value.to_number()) - 1);
+      return;
+    }
+
+    std::string requested = value.to_string();
+
+    for (size_t i = 0; i < costumes.size(); i++) {
+      if (costumes[i].name == requested) {
+        current_costume = static_cast<int>(i);
+        changed();
+        return;
+      }
+    }
+
+    if (requested == "next costume" || requested == "next backdrop") {
+      next_costume();
+    } else if (requested == "previous costume" ||
+               requested == "previous backdrop") {
+      set_costume(current_costume - 1);
+    } else if (requested == "random costume" ||
+               requested == "random backdrop") {
+      if (costumes.size() > 1) {
+        std::uniform_int_distribution<long> dist(1, costumes.size() - 1);
+        set_costume(current_costume + dist(rng));
+      }
+    } else if (!is_whitespace(requested)) {
+      char *end = nullptr;
+      double number = std::strtod(requested.c_str(), &end);
+      if (*end == '\0')
+        set_costume(std::lround(number) - 1);
+    }
+  }
+
+  void next_costume() { set_costume(current_costume + 1); }
+
+  ScratchValue costume_number() const { return current_costume + 1; }
+
+  ScratchValue costume_name() const {
+    if (costumes.empty())
+      return "";
+    return costume().name;
+  }
+
+  ScratchValue reported_size() const { return std::round(size); }
+
+  ScratchValue reported_volume() const { return volume; }
+
+  void set_size(ScratchValue value) {
+    size = std::max(0.0, value.to_number());
+    changed();
+  }
+
+  void change_size(ScratchValue value) {
+    set_size(size + value.to_number());
+  }
+
+  void set_effect(std::string effect, ScratchValue value) {
+    std::transform(effect.begin(), effect.end(), effect.begin(), ::tolower);
+    double amount = value.to_number();
+
+    if (effect == "ghost")
+      amount = std::clamp(amount, 0.0, 100.0);
+    else if (effect == "brightness")
+      amount = std::clamp(amount, -100.0, 100.0);
+
+    effects[effect] = amount;
+    changed();
+  }
+
+  void change_effect(std::string effect, ScratchValue value) {
+    std::transform(effect.begin(), effect.end(), effect.begin(), ::tolower);
+    set_effect(effect, effects[effect] + value.to_number());
+  }
+
+  void clear_effects() {
+    effects.clear();
+    changed();
+  }
+
+  void show() {
+    visible = true;
+    changed();
+  }
+
+  void hide() {
+    changed();
+    visible = false;
+  }
+
+  // Box around the current costume once it is scaled, flipped and rotated.
+  ScratchBounds bounds() const {
+    if (costumes.empty())
+      return {x, x, y, y};
+
+    const ScratchCostume &c = costume();
+    ScratchBounds box = {INFINITY, -INFINITY, INFINITY, -INFINITY};
+
+    for (double px : {0.0, c.width}) {
+      for (double py : {0.0, c.height}) {
+        double sx, sy;
+        to_stage(px, py, sx, sy);
+
+        box.left = std::min(box.left, sx);
+        box.right = std::max(box.right, sx);
+        box.bottom = std::min(box.bottom, sy);
+        box.top = std::max(box.top, sy);
+      }
+    }
+
+    return box;
+  }
+
+  // Maps a point of the costume image to the stage.
+  void to_stage(double px, double py, double &sx, double &sy) const {
+    const ScratchCostume &c = costume();
+    double scale = costume_scale();
+    double angle = costume_angle();
+
+    // image rows grow downwards, stage coordinates upwards
+    double dx = (px - c.rotation_center_x) * scale * (is_flipped() ? -1 : 1);
+    double dy = (c.rotation_center_y - py) * scale;
+
+    sx = x + dx * std::cos(angle) - dy * std::sin(angle);
+    sy = y + dx * std::sin(angle) + dy * std::cos(angle);
+  }
+
+  // Maps a point of the stage to the costume image, the inverse of to_stage.
+  void to_costume(double sx, double sy, double &px, double &py) const {
+    const ScratchCostume &c = costume();
+    double scale = costume_scale();
+    double angle = costume_angle();
+
+    double dx = sx - x, dy = sy - y;
+    double rx = dx * std::cos(angle) + dy * std::sin(angle);
+    double ry = dy * std::cos(angle) - dx * std::sin(angle);
+
+    px = rx / scale * (is_flipped() ? -1 : 1) + c.rotation_center_x;
+    py = c.rotation_center_y - ry / scale;
+  }
+
+  double effect(const std::string &name) const {
+    auto it = effects.find(name);
+    return it == effects.end() ? 0 : it->second;
+  }
+
+  // Pixel of the loaded costume drawn at a point of the stage, null if the
+  // point is outside of the costume.
+  const unsigned char *texel_at(double sx, double sy) const {
+    const ScratchCostume &c = costume();
+    double px, py;
+
+    to_costume(sx, sy, px, py);
+    distort(px, py);
+
+    int ix = static_cast<int>(std::floor(px));
+    int iy = static_cast<int>(std::floor(py));
+    int width = static_cast<int>(c.width);
+
+    if (ix < 0 || iy < 0 || ix >= width || iy >= static_cast<int>(c.height))
+      return nullptr;
+
+    return &c.pixels[(iy * width + ix) * 4];
+  }
+
+  // Whether the costume covers a point of the stage. Costumes whose pixels
+  // weren't loaded count as solid boxes.
+  bool covers(double sx, double sy) const {
+    if (costumes.empty())
+      return false;
+
+    if (!costume().pixels)
+      return bounds().contains(sx, sy);
+
+    const unsigned char *texel = texel_at(sx, sy);
+    return texel && texel[3] > 0;
+  }
+
+  // Moves a position back so that some of the sprite stays on the stage,
+  // the same way as the fence of the Scratch renderer.
+  void keep_in_fence(double &new_x, double &new_y) const {
+    if (is_stage || costumes.empty() || costume().width == 0)
+      return;
+
+    static constexpr double FENCE_WIDTH = 15;
+
+    ScratchBounds box = bounds();
+    double inset = std::floor(
+        std::min(box.right - box.left, box.top - box.bottom) / 2);
+    double dx = new_x - x, dy = new_y - y;
+
+    double sx = 240 - std::min(FENCE_WIDTH, inset);
+    if (box.right + dx < -sx)
+      new_x = std::ceil(x - (sx + box.right));
+    else if (box.left + dx > sx)
+      new_x = std::floor(x + (sx - box.left));
+
+    double sy = 180 - std::min(FENCE_WIDTH, inset);
+    if (box.top + dy < -sy)
+      new_y = std::ceil(y - (sy + box.top));
+    else if (box.bottom + dy > sy)
+      new_y = std::floor(y + (sy - box.bottom));
+  }
+
+  ScratchValue reported_x() const { return limit_precision(x); }
+  ScratchValue reported_y() const { return limit_precision(y); }
+  ScratchValue reported_direction() const { return direction; }
+
+  // Keeps the direction between -179 and 180.
+  void set_direction(double new_direction) {
+    if (!std::isfinite(new_direction))
+      return;
+
+    direction = new_direction - std::floor((new_direction + 179) / 360) * 360;
+    changed();
+  }
+
+  void set_rotation_style(const std::string &style) {
+    if (style == "all around" || style == "left-right" ||
+        style == "don't rotate")
+      rotation_style = style;
+    changed();
+  }
+
+  // Asks the scheduler to end the frame if this target is on screen.
+  void changed() const {
+    if (visible)
+      scratch_scheduler.request_redraw();
+  }
+
+private:
+  // The effects that move pixels around, like the shaders of the Scratch
+  // renderer which work on coordinates from 0 to 1.
+  void distort(double &px, double &py) const {
+    if (effects.empty())
+      return;
+
+    double mosaic = std::clamp(
+        std::round((std::abs(effect("mosaic")) + 10) / 10), 1.0, 512.0);
+    double pixelate = std::abs(effect("pixelate")) / 10;
+    double whirl = -effect("whirl") * M_PI / 180;
+    double fisheye = std::max(0.0, (effect("fisheye") + 100) / 100);
+
+    double width = costume().width, height = costume().height;
+    double u = px / width, v = py / height;
+
+    if (mosaic != 1) {
+      u = mosaic * u - std::floor(mosaic * u);
+      v = mosaic * v - std::floor(mosaic * v);
+    }
+
+    if (pixelate != 0) {
+      double size_u = pixelate / width, size_v = pixelate / height;
+      u = (std::floor(u / size_u) + 0.5) * size_u;
+      v = (std::floor(v / size_v) + 0.5) * size_v;
+    }
+
+    if (whirl != 0) {
+      double du = u - 0.5, dv = v - 0.5;
+      double factor = std::max(1 - std::hypot(du, dv) / 0.5, 0.0);
+      double angle = whirl * factor * factor;
+
+      u = std::cos(angle) * du + std::sin(angle) * dv + 0.5;
+      v = std::cos(angle) * dv - std::sin(angle) * du + 0.5;
+    }
+
+    if (fisheye != 1) {
+      double du = (u - 0.5) / 0.5, dv = (v - 0.5) / 0.5;
+      double length = std::hypot(du, dv);
+
+      if (length > 0) {
+        double r =
+            std::pow(std::min(length, 1.0), fisheye) * std::max(1.0, length);
+        u = 0.5 + r * du / length * 0.5;
+        v = 0.5 + r * dv / length * 0.5;
+      }
+    }
+
+    px = u * width;
+    py = v * height;
+  }
+
+  double costume_scale() const {
+    return size / 100.0 / costume().bitmap_resolution;
+  }
+
+  double costume_angle() const {
+    if (rotation_style != "all around")
+      return 0;
+    return (90 - direction) * M_PI / 180;
+  }
+
+  bool is_flipped() const {
+    return rotation_style == "left-right" && direction < 0;
+  }
+
+  // Hides the rounding errors of moving around, e.g. 1e-15 instead of 0.
+  static double limit_precision(double coordinate) {
+    double rounded = std::round(coordinate);
+    return std::abs(coordinate - rounded) < 1e-9 ? rounded : coordinate;
+  }
+
+  static bool is_whitespace(const std::string &str) {
+    return std::all_of(str.begin(), str.end(),
+                       [](unsigned char c) { return std::isspace(c); });
+  }
+};
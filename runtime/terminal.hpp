@@ -0,0 +1,241 @@
+#pragma once
+#include "renderer.hpp"
+
+#include <sys/ioctl.h>
+#include <unistd.h>
+
+// Draws the stage in the terminal every frame with `--terminal`, as a grid of
+// half block characters in 24 bit colors, so two pixels per character. Speech
+// bubbles and variable monitors are written over it as text.
+class ScratchTerminal {
+public:
+  void add_monitor(std::string label, ScratchValue *value, std::string mode,
+                   double x, double y) {
+    monitors.push_back({label, value, mode, x, y});
+  }
+
+  void start(const std::vector<ScratchTarget *> &targets) {
+    layers = &targets;
+
+    if (!scratch_options.terminal)
+      return;
+
+    scratch_scheduler.frame_hooks.push_back([this] { draw(); });
+    scratch_scheduler.exit_hooks.push_back([this] { draw(); });
+  }
+
+private:
+  struct Monitor {
+    std::string label;
+    ScratchValue *value;
+    std::string mode;
+    double x, y;
+  };
+
+  struct Color {
+    unsigned char r, g, b;
+
+    bool operator!=(const Color &other) const {
+      return r != other.r || g != other.g || b != other.b;
+    }
+  };
+
+  // Text replaces the half block of its cell, and is drawn in the upper
+  // color over the lower one.
+  struct Cell {
+    Color top, bottom;
+    std::string text;
+  };
+
+  static constexpr Color BUBBLE_TEXT = {87, 94, 117};
+  static constexpr Color BUBBLE = {255, 255, 255};
+  static constexpr Color MONITOR_TEXT = {87, 94, 117};
+  static constexpr Color MONITOR = {230, 240, 255};
+  static constexpr Color VALUE_TEXT = {255, 255, 255};
+  static constexpr Color VALUE = {255, 140, 26};
+
+  void draw() {
+    int columns, rows;
+    grid_size(columns, rows);
+
+    // whatever was drawn outside a smaller grid is left behind otherwise
+    std::string out = columns != last_columns || rows != last_rows
+                          ? "\x1b[0m\x1b[2J\x1b[H"
+                          : "\x1b[H";
+    last_columns = columns;
+    last_rows = rows;
+
+    scratch_renderer.render(canvas);
+    downsample(columns, rows);
+    draw_monitors(columns, rows);
+    draw_bubbles(columns, rows);
+
+    for (int row = 0; row < rows; row++) {
+      bool first = true;
+      Color fg = {}, bg = {};
+
+      for (int column = 0; column < columns; column++) {
+        const Cell &cell = cells[row * columns + column];
+        Color want_fg = cell.top, want_bg = cell.bottom;
+
+        if (first || want_fg != fg)
+          out += sgr(38, want_fg);
+        if (first || want_bg != bg)
+          out += sgr(48, want_bg);
+
+        out += cell.text.empty() ? "▀" : cell.text;
+        fg = want_fg;
+        bg = want_bg;
+        first = false;
+      }
+
+      out += "\x1b[0m\n";
+    }
+
+    std::cout << out << std::flush;
+  }
+
+  // As large as the terminal allows while keeping the shape of the stage, one
+  // line being left for the cursor.
+  static void grid_size(int &columns, int &rows) {
+    winsize size = {};
+    int width = 80, height = 31;
+
+    if (ioctl(STDOUT_FILENO, TIOCGWINSZ, &size) == 0 && size.ws_col > 0 &&
+        size.ws_row > 1) {
+      width = size.ws_col;
+      height = size.ws_row;
+    }
+
+    columns = std::min({width, (height - 1) * 8 / 3, ScratchCanvas::WIDTH});
+    rows = std::max(1, columns * 3 / 8);
+  }
+
+  void downsample(int columns, int rows) {
+    cells.assign(columns * rows, Cell{});
+
+    for (int row = 0; row < rows; row++) {
+      for (int column = 0; column < columns; column++) {
+        Cell &cell = cells[row * columns + column];
+        cell.top = average(column, row * 2, columns, rows * 2);
+        cell.bottom = average(column, row * 2 + 1, columns, rows * 2);
+      }
+    }
+  }
+
+  // Mean color of the stage pixels under one pixel of the grid.
+  Color average(int gx, int gy, int width, int height) const {
+    int left = gx * ScratchCanvas::WIDTH / width;
+    int right = std::max(left + 1, (gx + 1) * ScratchCanvas::WIDTH / width);
+    int top = gy * ScratchCanvas::HEIGHT / height;
+    int bottom = std::max(top + 1, (gy + 1) * ScratchCanvas::HEIGHT / height);
+    long sum[3] = {0, 0, 0}, count = 0;
+
+    for (int py = top; py < bottom; py++) {
+      for (int px = left; px < right; px++) {
+        const unsigned char *pixel = canvas.pixel(px, py);
+        for (int i = 0; i < 3; i++)
+          sum[i] += pixel[i];
+        count++;
+      }
+    }
+
+    return {static_cast<unsigned char>(sum[0] / count),
+            static_cast<unsigned char>(sum[1] / count),
+            static_cast<unsigned char>(sum[2] / count)};
+  }
+
+  // Monitors are placed from the top left corner of the stage, like in the
+  // editor, but are only as wide as their text.
+  void draw_monitors(int columns, int rows) {
+    for (auto &monitor : monitors) {
+      int column = static_cast<int>(monitor.x * columns / ScratchCanvas::WIDTH);
+      int row = static_cast<int>(monitor.y * rows / ScratchCanvas::HEIGHT);
+      std::string value = " " + monitor.value->to_string() + " ";
+
+      if (monitor.mode != "large") {
+        std::string label = " " + monitor.label + " ";
+        column = write(column, row, label, MONITOR_TEXT, MONITOR, columns, rows);
+      }
+
+      write(column, row, value, VALUE_TEXT, VALUE, columns, rows);
+    }
+  }
+
+  // Bubbles go above the sprite, on its right unless that leaves the stage.
+  void draw_bubbles(int columns, int rows) {
+    for (auto target : *layers) {
+      if (target->is_stage || !target->visible || target->bubble.empty())
+        continue;
+
+      std::string text = target->bubble_think ? "( " + target->bubble + " )"
+                                              : " " + target->bubble + " ";
+      int length = static_cast<int>(glyphs(text).size());
+      ScratchBounds bounds = target->bounds();
+
+      double scale = static_cast<double>(columns) / ScratchCanvas::WIDTH;
+      int left = static_cast<int>((bounds.left + ScratchCanvas::WIDTH / 2.0) *
+                                  scale);
+      int right = static_cast<int>(
+          std::ceil((bounds.right + ScratchCanvas::WIDTH / 2.0) * scale));
+      int top = static_cast<int>((ScratchCanvas::HEIGHT / 2.0 - bounds.top) *
+                                 rows / ScratchCanvas::HEIGHT);
+
+      int column = right + length <= columns ? right : left - length;
+      column = std::clamp(column, 0, std::max(0, columns - length));
+
+      write(column, std::clamp(top - 1, 0, rows - 1), text, BUBBLE_TEXT, BUBBLE,
+            columns, rows);
+    }
+  }
+
+  // Writes text over the cells it covers, cut at the edge of the grid, and
+  // gives the column after it.
+  int write(int column, int row, const std::string &text, Color fg, Color bg,
+            int columns, int rows) {
+    if (row < 0 || row >= rows)
+      return column;
+
+    for (auto &glyph : glyphs(text)) {
+      if (column >= 0 && column < columns) {
+        Cell &cell = cells[row * columns + column];
+        // printable characters only, anything else would break the grid
+        cell.text = static_cast<unsigned char>(glyph[0]) < 0x20 ? " " : glyph;
+        cell.top = fg;
+        cell.bottom = bg;
+      }
+      column++;
+    }
+
+    return column;
+  }
+
+  // Splits UTF-8 text into characters, each taking one cell.
+  static std::vector<std::string> glyphs(const std::string &text) {
+    std::vector<std::string> result;
+
+    for (char c : text) {
+      if ((static_cast<unsigned char>(c) & 0xc0) == 0x80 && !result.empty())
+        result.back() += c;
+      else
+        result.push_back(std::string(1, c));
+    }
+
+    return result;
+  }
+
+  static std::string sgr(int code, Color color) {
+    return "\x1b[" + std::to_string(code) + ";2;" + std::to_string(color.r) +
+           ";" + std::to_string(color.g) + ";" + std::to_string(color.b) + "m";
+  }
+
+  const std::vector<ScratchTarget *> *layers = nullptr;
+  std::vector<Monitor> monitors;
+  std::vector<Cell> cells;
+  ScratchCanvas canvas;
+
+  int last_columns = 0;
+  int last_rows = 0;
+};
+
+inline ScratchTerminal scratch_terminal;
@@ -0,0 +1,494 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use log::{debug, warn};
+use resvg::{tiny_skia, usvg};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use zune_jpeg::zune_core::colorspace::ColorSpace;
+use zune_jpeg::zune_core::options::DecoderOptions as JpegOptions;
+use zune_jpeg::JpegDecoder;
+
+use crate::parser::{self, CostumePixels, ScratchFile, SoundSamples};
+
+enum AssetSource {
+    Archive(zip::ZipArchive<File>),
+    Directory(PathBuf),
+}
+
+/// A project opened either from an `.sb3` archive or from an extracted
+/// `project.json`, whose assets are then expected next to it.
+pub struct ProjectSource {
+    pub json: String,
+    assets: AssetSource,
+}
+
+impl ProjectSource {
+    pub fn open(path: &str) -> Result<ProjectSource, String> {
+        let mut file = File::open(path).map_err(|x| x.to_string())?;
+        let mut magic = [0u8; 2];
+        let is_archive = file.read_exact(&mut magic).is_ok() && &magic == b"PK";
+
+        if !is_archive {
+            let json = std::fs::read_to_string(path).map_err(|x| x.to_string())?;
+            let dir = Path::new(path)
+                .parent()
+                .unwrap_or_else(|| Path::new("."))
+                .to_path_buf();
+
+            return Ok(ProjectSource {
+                json,
+                assets: AssetSource::Directory(dir),
+            });
+        }
+
+        let file = File::open(path).map_err(|x| x.to_string())?;
+        let mut archive = zip::ZipArchive::new(file).map_err(|x| x.to_string())?;
+        let mut json = String::new();
+
+        archive
+            .by_name("project.json")
+            .map_err(|x| x.to_string())?
+            .read_to_string(&mut json)
+            .map_err(|x| x.to_string())?;
+
+        Ok(ProjectSource {
+            json,
+            assets: AssetSource::Archive(archive),
+        })
+    }
+
+    pub fn read_asset(&mut self, md5ext: &str) -> Option<Vec<u8>> {
+        let mut data = vec![];
+
+        match &mut self.assets {
+            AssetSource::Archive(archive) => {
+                archive.by_name(md5ext).ok()?.read_to_end(&mut data).ok()?;
+            }
+            AssetSource::Directory(dir) => {
+                data = std::fs::read(dir.join(md5ext)).ok()?;
+            }
+        }
+
+        Some(data)
+    }
+}
+
+fn png_size(data: &[u8]) -> Option<(f64, f64)> {
+    if data.len() < 24 || &data[1..4] != b"PNG" {
+        return None;
+    }
+
+    let width = u32::from_be_bytes(data[16..20].try_into().unwrap());
+    let height = u32::from_be_bytes(data[20..24].try_into().unwrap());
+
+    Some((width as f64, height as f64))
+}
+
+fn jpeg_size(data: &[u8]) -> Option<(f64, f64)> {
+    let mut i = 2;
+
+    // walk the segments until the start of frame marker
+    while i + 9 < data.len() {
+        if data[i] != 0xff {
+            return None;
+        }
+
+        let marker = data[i + 1];
+        let len = u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
+
+        if (0xc0..=0xc3).contains(&marker) {
+            let height = u16::from_be_bytes([data[i + 5], data[i + 6]]);
+            let width = u16::from_be_bytes([data[i + 7], data[i + 8]]);
+            return Some((width as f64, height as f64));
+        }
+
+        i += 2 + len;
+    }
+
+    None
+}
+
+fn svg_attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+
+    while let Some(pos) = rest.find(name) {
+        let before = rest[..pos].chars().last();
+        let after = rest[pos + name.len()..].trim_start();
+        rest = &rest[pos + name.len()..];
+
+        if !matches!(before, Some(c) if c.is_whitespace()) || !after.starts_with('=') {
+            continue;
+        }
+
+        let value = after[1..].trim_start();
+        let quote = value.chars().next()?;
+        let end = value[1..].find(quote)?;
+
+        return Some(value[1..end + 1].to_string());
+    }
+
+    None
+}
+
+fn svg_length(value: &str) -> Option<f64> {
+    value.trim().trim_end_matches("px").parse::<f64>().ok()
+}
+
+fn svg_size(data: &[u8]) -> Option<(f64, f64)> {
+    let text = String::from_utf8_lossy(data);
+    let start = text.find("<svg")?;
+    let tag = &text[start..start + text[start..].find('>')?];
+
+    let width = svg_attribute(tag, "width").and_then(|x| svg_length(&x));
+    let height = svg_attribute(tag, "height").and_then(|x| svg_length(&x));
+
+    if let (Some(width), Some(height)) = (width, height) {
+        return Some((width, height));
+    }
+
+    let view_box: Vec<f64> = svg_attribute(tag, "viewBox")?
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter_map(|x| x.parse::<f64>().ok())
+        .collect();
+
+    match view_box[..] {
+        [_, _, width, height] => Some((width, height)),
+        _ => None,
+    }
+}
+
+fn decode_png(data: &[u8]) -> Option<Vec<u8>> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+    let mut reader = decoder.read_info().ok()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).ok()?;
+    buffer.truncate(info.buffer_size());
+
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => buffer
+            .chunks(3)
+            .flat_map(|x| [x[0], x[1], x[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buffer
+            .chunks(2)
+            .flat_map(|x| [x[0], x[0], x[0], x[1]])
+            .collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|&x| [x, x, x, 255]).collect(),
+        // expanded into one of the above
+        png::ColorType::Indexed => return None,
+    };
+
+    Some(rgba)
+}
+
+fn decode_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    let options = JpegOptions::default().jpeg_set_out_colorspace(ColorSpace::RGBA);
+    JpegDecoder::new_with_options(data, options).decode().ok()
+}
+
+// fonts of the Scratch editor, with the generic family closest to each
+const SCRATCH_FONTS: [(&str, &str); 7] = [
+    ("Sans Serif", "sans-serif"),
+    ("Serif", "serif"),
+    ("Handwriting", "cursive"),
+    ("Marker", "fantasy"),
+    ("Curly", "cursive"),
+    ("Pixel", "monospace"),
+    ("Scratch", "sans-serif"),
+];
+
+fn load_fonts() -> usvg::fontdb::Database {
+    use usvg::fontdb::Family;
+
+    let mut database = usvg::fontdb::Database::new();
+    database.load_system_fonts();
+
+    let installed: Vec<String> = database
+        .faces()
+        .flat_map(|face| face.families.iter().map(|x| x.0.to_string()))
+        .collect();
+
+    let find = |includes: &str, excludes: &[&str]| {
+        installed
+            .iter()
+            .find(|x| x.contains(includes) && !excludes.iter().any(|e| x.contains(e)))
+            .or(installed.first())
+            .cloned()
+    };
+
+    // the generic families default to fonts that most systems don't have
+    for (family, includes, excludes) in [
+        (Family::SansSerif, "Sans", &["Mono"][..]),
+        (Family::Serif, "Serif", &["Sans"]),
+        (Family::Cursive, "Sans", &["Mono"]),
+        (Family::Fantasy, "Sans", &["Mono"]),
+        (Family::Monospace, "Mono", &[]),
+    ] {
+        if installed.iter().any(|x| x == database.family_name(&family)) {
+            continue;
+        }
+
+        let Some(name) = find(includes, excludes) else {
+            continue;
+        };
+
+        debug!("using {} as {:?}", name, family);
+
+        match family {
+            Family::SansSerif => database.set_sans_serif_family(name),
+            Family::Serif => database.set_serif_family(name),
+            Family::Cursive => database.set_cursive_family(name),
+            Family::Fantasy => database.set_fantasy_family(name),
+            _ => database.set_monospace_family(name),
+        }
+    }
+
+    database
+}
+
+/// Draws an SVG at its own size, giving its width, height and RGBA rows.
+fn rasterize_svg(data: &[u8], fonts: &Arc<usvg::fontdb::Database>) -> Option<(u32, u32, Vec<u8>)> {
+    let options = usvg::Options {
+        fontdb: fonts.clone(),
+        ..Default::default()
+    };
+
+    // text can only be drawn with the fonts of this system
+    let mut svg = String::from_utf8_lossy(data).into_owned();
+    for (name, generic) in SCRATCH_FONTS {
+        svg = svg.replace(
+            &format!("font-family=\"{}\"", name),
+            &format!("font-family=\"{}, {}\"", name, generic),
+        );
+    }
+
+    let tree = usvg::Tree::from_str(&svg, &options).ok()?;
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())?;
+
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+
+    let rgba = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|x| {
+            let color = x.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+
+    Some((size.width(), size.height(), rgba))
+}
+
+/// Decodes a WAV (PCM or ADPCM) or MP3 sound, giving its sample rate and its
+/// samples mixed down to mono.
+fn decode_sound(data: Vec<u8>, data_format: &str) -> Option<(u32, Vec<i16>)> {
+    let stream = MediaSourceStream::new(Box::new(std::io::Cursor::new(data)), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(data_format);
+
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .ok()?
+        .format;
+
+    let track = format.default_track()?;
+    let track_id = track.id;
+    let rate = track.codec_params.sample_rate?;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .ok()?;
+
+    let mut samples = vec![];
+
+    // the end of the stream is reported as an error too
+    while let Ok(packet) = format.next_packet() {
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(_) => break,
+        };
+
+        let spec = *decoded.spec();
+        let channels = spec.channels.count();
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+
+        samples.extend(buffer.samples().chunks(channels).map(|frame| {
+            let mono = frame.iter().sum::<f32>() / channels as f32;
+            (mono.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+        }));
+    }
+
+    Some((rate, samples))
+}
+
+/// Size of an image asset in pixels.
+pub fn image_size(data: &[u8], data_format: &str) -> Option<(f64, f64)> {
+    match data_format {
+        "png" => png_size(data),
+        "jpg" | "jpeg" => jpeg_size(data),
+        "svg" => svg_size(data),
+        _ => None,
+    }
+}
+
+/// Fills in the size of every costume, which project.json doesn't record,
+/// and the pixels of those that can be shown. Vector costumes are rasterized
+/// at their own size.
+pub fn load_costumes(file: &mut ScratchFile, source: &mut ProjectSource) {
+    // loading the system fonts is slow, so only done for the first SVG
+    let mut fonts: Option<Arc<usvg::fontdb::Database>> = None;
+    let reachable = parser::reachable_costumes(file);
+
+    for (target, reachable) in file.targets.iter_mut().zip(reachable) {
+        for (costume, reachable) in target.costumes.iter_mut().zip(reachable) {
+            let Some(data) = source.read_asset(&costume.md5ext) else {
+                warn!(
+                    "Cannot find costume {} ({}) of {}, it won't be drawn",
+                    costume.name, costume.md5ext, target.name
+                );
+                continue;
+            };
+
+            match image_size(&data, &costume.data_format) {
+                Some((width, height)) => {
+                    debug!("{} is {}x{}", costume.md5ext, width, height);
+                    costume.width = width;
+                    costume.height = height;
+                }
+                None => warn!(
+                    "Cannot find the size of costume {} ({}) of {}",
+                    costume.name, costume.md5ext, target.name
+                ),
+            }
+
+            // the pixels are embedded in the program, megabytes for a backdrop
+            if !reachable {
+                debug!("{} is never shown, it isn't decoded", costume.md5ext);
+                continue;
+            }
+
+            if costume.data_format == "png" {
+                costume.pixels = decode_png(&data).map(|x| CostumePixels(Rc::new(x)));
+            }
+
+            if matches!(costume.data_format.as_str(), "jpg" | "jpeg") {
+                costume.pixels = decode_jpeg(&data).map(|x| CostumePixels(Rc::new(x)));
+            }
+
+            if costume.data_format == "svg" {
+                let fonts = fonts.get_or_insert_with(|| Arc::new(load_fonts()));
+
+                if let Some((width, height, rgba)) = rasterize_svg(&data, fonts) {
+                    // the pixels are what the runtime indexes, so their size wins
+                    costume.width = width as f64;
+                    costume.height = height as f64;
+                    costume.pixels = Some(CostumePixels(Rc::new(rgba)));
+                }
+            }
+
+            if costume.pixels.is_none() {
+                warn!(
+                    "Cannot decode costume {} ({}) of {}, it won't be drawn",
+                    costume.name, costume.md5ext, target.name
+                );
+            }
+        }
+    }
+}
+
+/// Decodes every sound, whose length `play sound until done` depends on.
+/// Sounds that can't be decoded keep the length saved in project.json and
+/// play silently.
+pub fn load_sounds(file: &mut ScratchFile, source: &mut ProjectSource) {
+    for target in &mut file.targets {
+        for sound in &mut target.sounds {
+            let decoded = source
+                .read_asset(&sound.md5ext)
+                .and_then(|data| decode_sound(data, &sound.data_format));
+
+            let Some((rate, mut samples)) = decoded else {
+                warn!(
+                    "Cannot decode sound {} ({}) of {}, it will be silent",
+                    sound.name, sound.md5ext, target.name
+                );
+                continue;
+            };
+
+            // the last block of ADPCM is padded, the editor saved the real length
+            if sound.sample_count > 0 && sound.sample_count < samples.len() as u64 {
+                samples.truncate(sound.sample_count as usize);
+            }
+
+            debug!(
+                "{} has {} samples at {} Hz",
+                sound.md5ext,
+                samples.len(),
+                rate
+            );
+
+            sound.rate = rate;
+            sound.sample_count = samples.len() as u64;
+            let bytes = samples.iter().flat_map(|x| x.to_le_bytes()).collect();
+            sound.samples = Some(SoundSamples(Rc::new(bytes)));
+        }
+    }
+}
+
+/// Copies the costume and sound files of the project as they were saved.
+/// Those missing from the project are left out.
+pub fn copy_assets(
+    file: &ScratchFile,
+    source: &mut ProjectSource,
+    dir: &Path,
+) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|x| x.to_string())?;
+
+    let mut copied = HashSet::new();
+
+    for target in &file.targets {
+        let md5exts = target
+            .costumes
+            .iter()
+            .map(|x| &x.md5ext)
+            .chain(target.sounds.iter().map(|x| &x.md5ext));
+
+        for md5ext in md5exts {
+            if !copied.insert(md5ext) {
+                continue;
+            }
+
+            let Some(data) = source.read_asset(md5ext) else {
+                warn!("Cannot find {} of {}", md5ext, target.name);
+                continue;
+            };
+
+            std::fs::write(dir.join(md5ext), data).map_err(|x| format!("{}: {}", md5ext, x))?;
+        }
+    }
+
+    Ok(())
+}
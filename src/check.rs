@@ -0,0 +1,55 @@
+use std::collections::BTreeMap;
+
+use colored::*;
+
+use crate::extensions::{self, Support};
+use crate::parser::{self, ScratchFile};
+
+/// Lists every opcode the project uses, how many blocks use it and whether it
+/// can be compiled. Gives false when some can't.
+pub fn check_opcodes(file: &ScratchFile) -> bool {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+
+    for target in &file.targets {
+        for block in target.blocks.values() {
+            *counts.entry(block.opcode.as_str()).or_default() += 1;
+        }
+    }
+
+    let width = counts.keys().map(|x| x.len()).max().unwrap_or(0);
+    let mut unsupported = 0;
+
+    println!("{}", "Opcodes: ".white().bold());
+
+    for (opcode, count) in &counts {
+        let category = opcode.split('_').next().unwrap_or_default();
+
+        let status = if parser::is_supported(opcode) {
+            "supported".green()
+        } else if extensions::support(category) == Support::Stubbed {
+            "stubbed".yellow()
+        } else {
+            unsupported += 1;
+            "unsupported".red().bold()
+        };
+
+        println!(
+            "  {} {:>5}  {}",
+            format!("{:width$}", opcode).white(),
+            count,
+            status
+        );
+    }
+
+    println!(
+        "\n{} opcodes, {} unsupported",
+        counts.len(),
+        if unsupported > 0 {
+            unsupported.to_string().red().bold()
+        } else {
+            unsupported.to_string().green()
+        }
+    );
+
+    unsupported == 0
+}
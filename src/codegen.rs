@@ -1,6 +1,173 @@
+use std::collections::HashSet;
+use std::fmt::{self, Write};
+use std::io;
+
+use serde::Serialize;
+
 use crate::parser::*;
+use crate::runtime;
+
+const INDENT: &str = "  ";
+
+/// The lines of generated code that come from a block, with the script it is
+/// part of: its hat block, or the definition of its procedure.
+#[derive(Debug, Serialize)]
+pub struct Mapping {
+    // unset for a single file, which may not have a name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    pub target: String,
+    pub script: String,
+    pub block: String,
+    pub opcode: String,
+    // first and last, counting from 1
+    pub lines: (usize, usize),
+}
+
+/// What to generate along with the code of the project.
+#[derive(Debug, Default)]
+pub struct Options {
+    // a comment above every statement telling the block it comes from
+    pub comments: bool,
+    // the runtime in the code instead of an include of it
+    pub inline_runtime: bool,
+}
+
+/// Streams the generated code to a sink, indented by the braces that are left
+/// open, and maps the lines of every statement to its block, optionally
+/// commenting them with it too.
+struct Writer<'a> {
+    sink: &'a mut dyn io::Write,
+    // the first error of the sink, which formatting can't carry
+    error: Option<io::Error>,
+    // lines written so far
+    line: usize,
+    depth: usize,
+    // the last character written, '\n' at the start of a line
+    last: char,
+    comments: bool,
+    // the target and script being written
+    target: String,
+    script: String,
+    mappings: Vec<Mapping>,
+    // mappings of the blocks whose code isn't over yet
+    open: Vec<usize>,
+}
+
+impl<'a> Writer<'a> {
+    fn new(sink: &'a mut dyn io::Write, comments: bool) -> Writer<'a> {
+        Writer {
+            sink,
+            error: None,
+            line: 0,
+            depth: 0,
+            last: '\n',
+            comments,
+            target: String::new(),
+            script: String::new(),
+            mappings: vec![],
+            open: vec![],
+        }
+    }
+
+    fn emit(&mut self, text: &str) -> fmt::Result {
+        self.sink.write_all(text.as_bytes()).map_err(|x| {
+            self.error.get_or_insert(x);
+            fmt::Error
+        })
+    }
+
+    // code with an indentation of its own, written as it is
+    fn write_raw(&mut self, text: &str) -> fmt::Result {
+        self.emit(text)?;
+        self.line += text.matches('\n').count();
+        self.last = text.chars().last().unwrap_or(self.last);
+
+        Ok(())
+    }
+
+    // Starts the code of a block, which lasts until the matching `end`, after
+    // a comment like "// Cat: a1b2 (motion_movesteps)".
+    fn begin(&mut self, origin: &Origin) -> fmt::Result {
+        if self.comments {
+            let target = self.target.replace('\n', " ");
+            let block_id = origin.block_id.replace('\n', " ");
+            writeln!(self, "// {}: {} ({})", target, block_id, origin.opcode)?;
+        }
+
+        self.open.push(self.mappings.len());
+        self.mappings.push(Mapping {
+            file: None,
+            target: self.target.to_string(),
+            script: self.script.to_string(),
+            block: origin.block_id.to_string(),
+            opcode: origin.opcode.to_string(),
+            lines: (self.line + 1, self.line + 1),
+        });
+
+        Ok(())
+    }
+
+    // blocks that gave no code, such as stubbed ones, aren't mapped
+    fn end(&mut self) {
+        let index = self.open.pop().unwrap();
+
+        if self.line < self.mappings[index].lines.0 {
+            self.mappings.truncate(index);
+        } else {
+            self.mappings[index].lines.1 = self.line;
+        }
+    }
+
+    // Gives the mappings once all the code is written, or why it couldn't be.
+    fn finish(self, result: fmt::Result) -> io::Result<Vec<Mapping>> {
+        match (result, self.error) {
+            (Ok(()), _) => {
+                self.sink.flush()?;
+                Ok(self.mappings)
+            }
+            (Err(_), Some(error)) => Err(error),
+            (Err(_), None) => Err(io::Error::other("cannot format the code")),
+        }
+    }
+}
+
+impl fmt::Write for Writer<'_> {
+    // a line closing a brace is indented one level less, and the lines after
+    // one opening a brace one level more
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        for (i, part) in text.split('\n').enumerate() {
+            if i > 0 {
+                if self.last == '{' {
+                    self.depth += 1;
+                }
+
+                self.emit("\n")?;
+                self.line += 1;
+                self.last = '\n';
+            }
+
+            if part.is_empty() {
+                continue;
+            }
+
+            if self.last == '\n' {
+                if part.starts_with('}') {
+                    self.depth = self.depth.saturating_sub(1);
+                }
 
-fn bin_op_to_str(op: OpType) -> String {
+                self.emit(&INDENT.repeat(self.depth))?;
+            }
+
+            self.emit(part)?;
+            self.last = part.chars().last().unwrap();
+        }
+
+        Ok(())
+    }
+}
+
+fn bin_op_to_str(op: &OpType) -> String {
     match op {
         OpType::Add => "+",
         OpType::And => "&&",
@@ -14,94 +181,205 @@ fn bin_op_to_str(op: OpType) -> String {
         OpType::Or => "||",
         OpType::Join => "+",
         OpType::Subtract => "-",
-        _ => todo!("{:#?}", op),
+        _ => unreachable!("{:#?} has no C++ operator", op),
     }
     .to_string()
 }
-fn codegen_expr(expr: Expr) -> String {
-    let mut str: String = "".to_string();
+/// A C++ literal of `x`: an `int` when it is a small whole number, so that it
+/// converts to `ScratchValue` and `double` alike, or a `double` otherwise.
+fn number_literal(x: f64) -> String {
+    if x.fract() == 0.0 && x.abs() <= i32::MAX as f64 {
+        (x as i32).to_string()
+    } else {
+        format!("{:?}", x)
+    }
+}
+
+fn escape_str(str: &str) -> String {
+    let mut ret = String::with_capacity(str.len());
 
+    for c in str.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            _ => ret.push(c),
+        }
+    }
+
+    ret
+}
+
+// turns a scratch name into a valid C++ identifier
+fn ident(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+// locals are named after their target too, sprites may share variable names
+fn var_ident(var: &VarRef) -> String {
+    match var.target {
+        Some(index) => format!("target_{}_{}", index, ident(&var.name)),
+        None => format!("var_{}", ident(&var.name)),
+    }
+}
+
+fn procedure_ident(name: &str) -> String {
+    format!("proc_{}", ident(name))
+}
+
+// An expression written where it is formatted, so that nested ones don't
+// each build a string.
+struct Code<'a>(&'a Expr);
+
+impl fmt::Display for Code<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        codegen_expr(f, self.0)
+    }
+}
+
+fn codegen_expr(f: &mut fmt::Formatter, expr: &Expr) -> fmt::Result {
     match expr {
         Expr::BinOp { op, lhs, rhs } => match op {
+            // the left hand side may be a literal, which has no operators of its own
             OpType::Add
             | OpType::Subtract
             | OpType::Multiply
             | OpType::Divide
             | OpType::Modulo
-            | OpType::And
             | OpType::GreaterThan
             | OpType::LessThan
-            | OpType::Equals
-            | OpType::Join
-            | OpType::Or => str.push_str(&format!(
-                "({}) {} ({})",
-                codegen_expr(*lhs),
+            | OpType::Equals => write!(
+                f,
+                "ScratchValue({}) {} ({})",
+                Code(lhs),
                 bin_op_to_str(op),
-                codegen_expr(*rhs)
-            )),
+                Code(rhs)
+            ),
+
+            OpType::And | OpType::Or => {
+                write!(f, "({}) {} ({})", Code(lhs), bin_op_to_str(op), Code(rhs))
+            }
 
-            _ => todo!("{:#?}", op),
+            OpType::Join => write!(
+                f,
+                "ScratchValue(ScratchValue({}).to_string() + ScratchValue({}).to_string())",
+                Code(lhs),
+                Code(rhs)
+            ),
+
+            OpType::Random => write!(f, "scratch_runtime.random({}, {})", Code(lhs), Code(rhs)),
+
+            // lowered to other expressions
+            OpType::Not | OpType::Length | OpType::LetterOf => {
+                unreachable!("{:#?} is not a binary operator", op)
+            }
         },
 
         Expr::SingleOp { op, expr } => match op {
-            OpType::Not => str.push_str(&format!("!({})", codegen_expr(*expr))),
-            OpType::Length => str.push_str(&format!("({}).length()", codegen_expr(*expr))),
-            _ => todo!("{:#?}", op),
+            OpType::Not => write!(f, "!({})", Code(expr)),
+            OpType::Length => write!(f, "({}).length()", Code(expr)),
+            _ => unreachable!("{:#?} is not a unary operator", op),
         },
 
         Expr::Val(x) => match x {
-            Value::Number(x) => str.push_str(&format!("{}", x)),
-            Value::String(x) => str.push_str(&format!("\"{}\"", x)),
+            Value::Number(x) => f.write_str(&number_literal(*x)),
+            Value::String(x) => write!(f, "\"{}\"", escape_str(x)),
         },
 
-        Expr::ItemOf { list_name, index } => str.push_str(&format!(
+        Expr::ItemOf { list, index } => write!(
+            f,
             "{}[static_cast<int>(({}-1).get<double>())]",
-            list_name.replace(' ', "_").to_string(),
-            codegen_expr(*index)
-        )),
+            var_ident(list),
+            Code(index)
+        ),
 
-        Expr::LetterOf { val, index } => str.push_str(&format!(
+        Expr::LetterOf { val, index } => write!(
+            f,
             "{}[static_cast<int>(({}-1).get<double>())]",
-            codegen_expr(*val),
-            codegen_expr(*index)
-        )),
+            Code(val),
+            Code(index)
+        ),
 
-        Expr::Var(name) | Expr::Param(name) => str.push_str(&format!("{}", name.replace(' ', "_"))),
-    }
+        Expr::CostumeNumber => f.write_str("sprite.costume_number()"),
+        Expr::CostumeName => f.write_str("sprite.costume_name()"),
+        Expr::BackdropNumber => f.write_str("scratch_runtime.stage().costume_number()"),
+        Expr::BackdropName => f.write_str("scratch_runtime.stage().costume_name()"),
+        Expr::Size => f.write_str("sprite.reported_size()"),
+        Expr::Volume => f.write_str("sprite.reported_volume()"),
+        Expr::Tempo => f.write_str("scratch_music.tempo()"),
+        Expr::Unsupported { opcode, block_id } => write!(
+            f,
+            "scratch_runtime.unsupported(sprite, \"{}\", \"{}\")",
+            escape_str(opcode),
+            escape_str(block_id)
+        ),
 
-    str
-}
+        Expr::Answer => f.write_str("scratch_runtime.answer"),
+        Expr::Timer => f.write_str("scratch_runtime.timer()"),
+        Expr::Current(x) => {
+            write!(f, "scratch_runtime.current(\"{}\")", escape_str(x))
+        }
+        Expr::DaysSince2000 => f.write_str("scratch_runtime.days_since_2000()"),
+        Expr::Username => f.write_str("scratch_runtime.username()"),
+        Expr::KeyPressed(key) => write!(f, "scratch_keyboard.is_pressed({})", Code(key)),
+        Expr::MouseX => f.write_str("scratch_mouse.mouse_x()"),
+        Expr::MouseY => f.write_str("scratch_mouse.mouse_y()"),
+        Expr::MouseDown => f.write_str("scratch_mouse.down"),
 
-fn codegen_stmt(statement: Stmt) -> String {
-    let mut str: String = "".to_string();
+        Expr::Of { object, property } => write!(
+            f,
+            "scratch_runtime.sensing_of({}, \"{}\")",
+            Code(object),
+            escape_str(property)
+        ),
 
-    fn gen_block(block: BlockStmt, str: &mut String) {
-        for stmt in block.stmts {
-            str.push_str(&codegen_stmt(stmt).to_string())
+        Expr::DistanceTo(object) => {
+            write!(f, "scratch_runtime.distance_to(sprite, {})", Code(object))
         }
-    }
 
-    match statement {
-        Stmt::WhenFlagClicked(x) => {
-            for stmt in x.stmts {
-                str.push_str(&codegen_stmt(stmt).to_string());
-            }
-        }
+        Expr::TouchingObject(object) => write!(
+            f,
+            "scratch_runtime.touching_object(sprite, {})",
+            Code(object)
+        ),
 
-        Stmt::SetVariable { name, id: _, val } => {
-            str.push_str(&format!(
-                "{} = ({});\n",
-                name.replace(' ', "_"),
-                codegen_expr(val)
-            ));
+        Expr::TouchingColor(color) => {
+            write!(f, "scratch_runtime.touching_color(sprite, {})", Code(color))
         }
 
+        Expr::XPosition => f.write_str("sprite.reported_x()"),
+        Expr::YPosition => f.write_str("sprite.reported_y()"),
+        Expr::Direction => f.write_str("sprite.reported_direction()"),
+
+        Expr::Var(var) => f.write_str(&var_ident(var)),
+        Expr::Param(name) => f.write_str(&ident(name)),
+    }
+}
+
+fn codegen_block(w: &mut Writer, block: &BlockStmt) -> fmt::Result {
+    for (origin, stmt) in &block.stmts {
+        w.begin(origin)?;
+        codegen_stmt(w, stmt)?;
+        w.end();
+    }
+
+    Ok(())
+}
+
+fn codegen_stmt(w: &mut Writer, statement: &Stmt) -> fmt::Result {
+    match statement {
+        Stmt::SetVariable { var, val } => writeln!(w, "{} = ({});", var_ident(var), Code(val)),
+
         Stmt::If { condition, block } => {
-            str.push_str(&format!("if ({}) {{\n", codegen_expr(condition)));
+            writeln!(w, "if ({}) {{", Code(condition))?;
 
-            gen_block(block, &mut str);
+            codegen_block(w, block)?;
 
-            str.push_str("}\n");
+            w.write_str("}\n")
         }
 
         Stmt::IfElse {
@@ -109,113 +387,908 @@ fn codegen_stmt(statement: Stmt) -> String {
             if_block,
             else_block,
         } => {
-            str.push_str(&format!("if ({}) {{\n", codegen_expr(condition)));
+            writeln!(w, "if ({}) {{", Code(condition))?;
 
-            gen_block(if_block, &mut str);
+            codegen_block(w, if_block)?;
 
-            str.push_str("} else {\n");
+            w.write_str("} else {\n")?;
 
-            gen_block(else_block, &mut str);
+            codegen_block(w, else_block)?;
 
-            str.push_str("}\n");
+            w.write_str("}\n")
         }
 
         Stmt::Repeat { times, block } => {
-            str.push_str(&format!("for (auto _ = {}; _--;){{\n", codegen_expr(times)));
+            // the count is rounded like in the editor, and never runs below zero
+            writeln!(
+                w,
+                "for (long _ = std::lround(ScratchValue({}).to_number()); _-- > 0;) {{",
+                Code(times)
+            )?;
 
-            for stmt in block.stmts {
-                str.push_str(&codegen_stmt(stmt).to_string());
-            }
-            str.push_str("}\n");
+            codegen_block(w, block)?;
+            w.write_str("scratch_scheduler.yield();\n}\n")
         }
 
+        Stmt::Wait { secs } => writeln!(
+            w,
+            "scratch_scheduler.wait(ScratchValue({}).to_number());",
+            Code(secs)
+        ),
+
         Stmt::RepeatUntil { condition, block } => {
-            str.push_str(&format!("while(!({})) {{\n", codegen_expr(condition)));
-            for stmt in block.stmts {
-                str.push_str(&codegen_stmt(stmt).to_string());
+            writeln!(w, "while (!({})) {{", Code(condition))?;
+            codegen_block(w, block)?;
+            w.write_str("scratch_scheduler.yield();\n}\n")
+        }
+
+        Stmt::ChangeBy { var, inc } => writeln!(w, "{} += {};", var_ident(var), Code(inc)),
+
+        Stmt::DeleteAllOfList { list } => writeln!(w, "{}.clear();", var_ident(list)),
+
+        Stmt::AddToList { list, val } => {
+            writeln!(w, "{}.push_back({});", var_ident(list), Code(val))
+        }
+
+        Stmt::ProcedureCall { proc, params } => {
+            write!(w, "{}(", procedure_ident(proc))?;
+
+            for (i, param) in params.iter().enumerate() {
+                if i > 0 {
+                    w.write_str(",")?;
+                }
+
+                write!(w, "{}", Code(param))?;
             }
-            str.push_str("}\n");
+
+            w.write_str(");\n")
+        }
+
+        Stmt::SwitchCostume { costume } => writeln!(
+            w,
+            "scratch_runtime.switch_costume(sprite, {});",
+            Code(costume)
+        ),
+
+        Stmt::NextCostume => w.write_str("sprite.next_costume();\n"),
+
+        Stmt::SwitchBackdrop { backdrop } => {
+            writeln!(w, "scratch_runtime.switch_backdrop({});", Code(backdrop))
+        }
+
+        Stmt::NextBackdrop => w.write_str("scratch_runtime.next_backdrop();\n"),
+
+        Stmt::SetSize { size } => writeln!(w, "sprite.set_size({});", Code(size)),
+
+        Stmt::ChangeSize { inc } => {
+            writeln!(w, "sprite.change_size({});", Code(inc))
+        }
+
+        Stmt::SetEffect { effect, val } => writeln!(
+            w,
+            "sprite.set_effect(\"{}\", {});",
+            escape_str(effect),
+            Code(val)
+        ),
+
+        Stmt::ChangeEffect { effect, inc } => writeln!(
+            w,
+            "sprite.change_effect(\"{}\", {});",
+            escape_str(effect),
+            Code(inc)
+        ),
+
+        Stmt::ClearEffects => w.write_str("sprite.clear_effects();\n"),
+
+        Stmt::Say {
+            message,
+            secs: None,
+            think,
+        } => writeln!(
+            w,
+            "scratch_runtime.say(sprite, {}, {});",
+            Code(message),
+            think
+        ),
+
+        Stmt::Say {
+            message,
+            secs: Some(secs),
+            think,
+        } => writeln!(
+            w,
+            "scratch_runtime.say_for_secs(sprite, {}, {}, {});",
+            Code(message),
+            Code(secs),
+            think
+        ),
+
+        Stmt::Show => w.write_str("sprite.show();\n"),
+        Stmt::Hide => w.write_str("sprite.hide();\n"),
+
+        Stmt::AskAndWait { question } => {
+            writeln!(w, "scratch_runtime.ask(sprite, {});", Code(question))
         }
 
-        Stmt::ChangeBy { var_name, inc } => str.push_str(&format!(
-            "{} += {};",
-            var_name.replace(' ', "_"),
-            codegen_expr(inc)
-        )),
+        Stmt::ResetTimer => w.write_str("scratch_runtime.reset_timer();\n"),
+
+        Stmt::GoToFrontBack { front } => w.write_str(match front {
+            true => "scratch_runtime.go_to_front(sprite);\n",
+            false => "scratch_runtime.go_to_back(sprite);\n",
+        }),
+
+        Stmt::GoForwardBackwardLayers { forward, layers } => writeln!(
+            w,
+            "scratch_runtime.go_{}_layers(sprite, {});",
+            if *forward { "forward" } else { "backward" },
+            Code(layers)
+        ),
 
-        Stmt::DeleteAllOfList { name } => {
-            str.push_str(&format!("{}.clear();", name.replace(' ', "_")))
+        Stmt::MoveSteps { steps } => {
+            writeln!(w, "scratch_runtime.move_steps(sprite, {});", Code(steps))
         }
 
-        Stmt::AddToList { name, val, .. } => str.push_str(&format!(
-            "{}.push_back({});",
-            name.replace(' ', "_"),
-            codegen_expr(val)
-        )),
+        Stmt::Turn { right, degrees } => writeln!(
+            w,
+            "scratch_runtime.turn_{}(sprite, {});",
+            if *right { "right" } else { "left" },
+            Code(degrees)
+        ),
+
+        Stmt::PointInDirection { direction } => writeln!(
+            w,
+            "scratch_runtime.point_in_direction(sprite, {});",
+            Code(direction)
+        ),
+
+        Stmt::PointTowards { object } => writeln!(
+            w,
+            "scratch_runtime.point_towards(sprite, {});",
+            Code(object)
+        ),
+
+        Stmt::GoToXY { x, y } => writeln!(
+            w,
+            "scratch_runtime.go_to_xy(sprite, {}, {});",
+            Code(x),
+            Code(y)
+        ),
+
+        Stmt::GoTo { object } => writeln!(w, "scratch_runtime.go_to(sprite, {});", Code(object)),
+
+        Stmt::GlideToXY { secs, x, y } => writeln!(
+            w,
+            "scratch_runtime.glide_to_xy(sprite, {}, {}, {});",
+            Code(secs),
+            Code(x),
+            Code(y)
+        ),
+
+        Stmt::GlideTo { secs, object } => writeln!(
+            w,
+            "scratch_runtime.glide_to(sprite, {}, {});",
+            Code(secs),
+            Code(object)
+        ),
+
+        Stmt::ChangeX { inc } => writeln!(w, "scratch_runtime.change_x(sprite, {});", Code(inc)),
 
-        Stmt::ProcedureCall { proc, params } => str.push_str(&format!(
-            "{}({});",
-            proc,
-            params
-                .iter()
-                .map(|x| codegen_expr(x.clone()))
-                .collect::<Vec<_>>()
-                .join(",")
-        )),
+        Stmt::SetX { x } => writeln!(w, "scratch_runtime.set_x(sprite, {});", Code(x)),
 
-        Stmt::ProcedureDefinition { prototype, body } => {
-            str.push_str(&format!(
-                "auto {} = [&]({}) {{",
-                prototype.name,
-                prototype
-                    .param_order
-                    .iter()
-                    .map(|x| (format!("ScratchValue {}", x)))
-                    .collect::<Vec<_>>()
-                    .join(","),
-            ));
+        Stmt::ChangeY { inc } => writeln!(w, "scratch_runtime.change_y(sprite, {});", Code(inc)),
 
-            gen_block(body, &mut str);
+        Stmt::SetY { y } => writeln!(w, "scratch_runtime.set_y(sprite, {});", Code(y)),
 
-            str.push_str("};");
+        Stmt::IfOnEdgeBounce => w.write_str("scratch_runtime.if_on_edge_bounce(sprite);\n"),
+
+        Stmt::SetRotationStyle { style } => {
+            writeln!(w, "sprite.set_rotation_style(\"{}\");", escape_str(style))
         }
 
-        _ => todo!("{:#?}", statement),
+        Stmt::PenClear => w.write_str("scratch_pen.clear();\n"),
+        Stmt::PenStamp => w.write_str("scratch_pen.stamp(sprite);\n"),
+        Stmt::PenDown => w.write_str("scratch_pen.pen_down(sprite);\n"),
+        Stmt::PenUp => w.write_str("scratch_pen.pen_up(sprite);\n"),
+
+        Stmt::SetPenColor { color } => {
+            writeln!(w, "scratch_pen.set_color(sprite, {});", Code(color))
+        }
+
+        Stmt::SetPenColorParam { param, val, change } => writeln!(
+            w,
+            "scratch_pen.{}_color_param(sprite, {}, {});",
+            if *change { "change" } else { "set" },
+            Code(param),
+            Code(val)
+        ),
+
+        Stmt::SetPenSize { size, change } => writeln!(
+            w,
+            "scratch_pen.{}_size(sprite, {});",
+            if *change { "change" } else { "set" },
+            Code(size)
+        ),
+
+        Stmt::SetPenHue { hue, change } => writeln!(
+            w,
+            "scratch_pen.{}_hue(sprite, {});",
+            if *change { "change" } else { "set" },
+            Code(hue)
+        ),
+
+        Stmt::SetPenShade { shade, change } => writeln!(
+            w,
+            "scratch_pen.{}_shade(sprite, {});",
+            if *change { "change" } else { "set" },
+            Code(shade)
+        ),
+
+        Stmt::PlaySound { sound, until_done } => writeln!(
+            w,
+            "scratch_audio.{}(sprite, {});",
+            if *until_done {
+                "play_until_done"
+            } else {
+                "play"
+            },
+            Code(sound)
+        ),
+
+        Stmt::StopAllSounds => w.write_str("scratch_audio.stop_all();\n"),
+
+        Stmt::SetSoundEffect {
+            effect,
+            val,
+            change,
+        } => writeln!(
+            w,
+            "scratch_audio.{}_effect(sprite, \"{}\", {});",
+            if *change { "change" } else { "set" },
+            escape_str(effect),
+            Code(val)
+        ),
+
+        Stmt::ClearSoundEffects => w.write_str("scratch_audio.clear_effects(sprite);\n"),
+
+        Stmt::SetVolume { volume, change } => writeln!(
+            w,
+            "scratch_audio.{}_volume(sprite, {});",
+            if *change { "change" } else { "set" },
+            Code(volume)
+        ),
+
+        Stmt::PlayNote { note, beats } => writeln!(
+            w,
+            "scratch_music.play_note(sprite, {}, {});",
+            Code(note),
+            Code(beats)
+        ),
+
+        Stmt::PlayDrum { drum, beats } => writeln!(
+            w,
+            "scratch_music.play_drum(sprite, {}, {});",
+            Code(drum),
+            Code(beats)
+        ),
+
+        Stmt::Rest { beats } => writeln!(w, "scratch_music.rest({});", Code(beats)),
+
+        Stmt::SetInstrument { instrument } => writeln!(
+            w,
+            "scratch_music.set_instrument(sprite, {});",
+            Code(instrument)
+        ),
+
+        Stmt::SetTempo { tempo, change } => writeln!(
+            w,
+            "scratch_music.{}_tempo({});",
+            if *change { "change" } else { "set" },
+            Code(tempo)
+        ),
+
+        Stmt::Unsupported { opcode, block_id } => writeln!(
+            w,
+            "scratch_runtime.unsupported(sprite, \"{}\", \"{}\");",
+            escape_str(opcode),
+            escape_str(block_id)
+        ),
+
+        Stmt::Empty => Ok(()),
+
+        // scripts start with these, and nothing nests them in a block
+        Stmt::WhenFlagClicked(_)
+        | Stmt::WhenKeyPressed { .. }
+        | Stmt::WhenClicked(_)
+        | Stmt::ProcedureDefinition { .. } => unreachable!("{:#?}", statement),
     }
+}
 
-    str
+fn pixels_ident(costume: &ScratchCostume) -> String {
+    format!("costume_{}", ident(&costume.md5ext))
 }
 
-pub fn codegen_project(project: Project) -> String {
-    let mut str = r#"#include <runtime/scratchnative.hpp>
-int main(void)
-{
-"#
-    .to_string();
+// raw bytes as a string literal, split over several lines
+fn codegen_bytes(w: &mut Writer, bytes: &[u8]) -> fmt::Result {
+    let mut line = String::new();
 
-    for var in &project.variables {
-        str.push_str(&format!("ScratchValue {} = {{}};\n", var.replace(' ', "_")));
+    for chunk in bytes.chunks(64) {
+        line.clear();
+        line.push('"');
+
+        for &byte in chunk {
+            match byte {
+                b'"' | b'\\' | b'?' => write!(line, "\\{}", byte as char)?,
+                0x20..=0x7e => line.push(byte as char),
+                // always three digits, so the next byte can't be read as part of it
+                _ => write!(line, "\\{:03o}", byte)?,
+            }
+        }
+
+        line.push_str("\"\n");
+        w.write_str(&line)?;
     }
 
-    for list in project.lists {
-        str.push_str(&format!("ScratchList {} = {{}};\n", list.replace(' ', "_")));
+    Ok(())
+}
+
+fn samples_ident(sound: &ScratchSound) -> String {
+    format!("sound_{}", ident(&sound.md5ext))
+}
+
+// The data of every costume and sound once, as they may be shared between
+// sprites: pixels, and samples as 16 bit little endian.
+fn asset_arrays(project: &Project) -> Vec<(String, &[u8])> {
+    let mut arrays = vec![];
+    let mut seen = HashSet::new();
+
+    for sprite in &project.sprites {
+        for costume in &sprite.costumes {
+            if let Some(pixels) = &costume.pixels {
+                if seen.insert(pixels_ident(costume)) {
+                    arrays.push((pixels_ident(costume), &pixels.0[..]));
+                }
+            }
+        }
+
+        for sound in &sprite.sounds {
+            if let Some(samples) = &sound.samples {
+                if seen.insert(samples_ident(sound)) {
+                    arrays.push((samples_ident(sound), &samples.0[..]));
+                }
+            }
+        }
+    }
+
+    arrays
+}
+
+// `storage` is "static const" for a single file, "const" for arrays shared
+// between translation units
+fn codegen_asset_arrays(w: &mut Writer, project: &Project, storage: &str) -> fmt::Result {
+    for (name, bytes) in asset_arrays(project) {
+        writeln!(w, "{} unsigned char {}[] =", storage, name)?;
+        codegen_bytes(w, bytes)?;
+        w.write_str(";\n")?;
+    }
+
+    Ok(())
+}
+
+fn codegen_sound(w: &mut Writer, sound: &ScratchSound) -> fmt::Result {
+    write!(
+        w,
+        "{{\"{}\", \"{}\", {}, {}, ",
+        escape_str(&sound.name),
+        escape_str(&sound.md5ext),
+        sound.rate,
+        sound.sample_count
+    )?;
+
+    match sound.samples {
+        Some(_) => write!(w, "{}}}", samples_ident(sound)),
+        None => w.write_str("nullptr}"),
+    }
+}
+
+fn codegen_costume(w: &mut Writer, costume: &ScratchCostume) -> fmt::Result {
+    write!(
+        w,
+        "{{\"{}\", \"{}\", \"{}\", {:?}, {:?}, {}, {:?}, {:?}, ",
+        escape_str(&costume.name),
+        escape_str(&costume.md5ext),
+        escape_str(&costume.data_format),
+        costume.rotation_center_x,
+        costume.rotation_center_y,
+        costume.bitmap_resolution,
+        costume.width,
+        costume.height
+    )?;
+
+    match costume.pixels {
+        Some(_) => write!(w, "{}}}", pixels_ident(costume)),
+        None => w.write_str("nullptr}"),
+    }
+}
+
+fn codegen_sprite_decl(w: &mut Writer, index: usize, sprite: &Sprite) -> fmt::Result {
+    write!(
+        w,
+        "ScratchTarget target_{}(\"{}\", {}, {{",
+        index,
+        escape_str(&sprite.name),
+        sprite.is_stage
+    )?;
+
+    for (i, costume) in sprite.costumes.iter().enumerate() {
+        if i > 0 {
+            w.write_str(", ")?;
+        }
+        codegen_costume(w, costume)?;
+    }
+
+    w.write_str("}, {")?;
+
+    for (i, sound) in sprite.sounds.iter().enumerate() {
+        if i > 0 {
+            w.write_str(", ")?;
+        }
+        codegen_sound(w, sound)?;
+    }
+
+    writeln!(
+        w,
+        "}}, {}, {}, {:?}, {:?}, {:?}, {:?}, \"{}\", {}, {:?});",
+        sprite.current_costume,
+        sprite.layer_order,
+        sprite.x,
+        sprite.y,
+        sprite.size,
+        sprite.direction,
+        escape_str(&sprite.rotation_style),
+        sprite.visible,
+        sprite.volume
+    )
+}
+
+// registers the target once it is declared
+fn codegen_sprite_setup(w: &mut Writer, index: usize, sprite: &Sprite) -> fmt::Result {
+    writeln!(w, "scratch_runtime.add_target(target_{});", index)?;
+
+    if sprite.is_stage {
+        writeln!(w, "target_{}.tempo = {:?};", index, sprite.tempo)?;
+    }
+
+    codegen_sprite_variables(w, index, sprite)
+}
+
+fn codegen_sprite_variables(w: &mut Writer, index: usize, sprite: &Sprite) -> fmt::Result {
+    for var in &sprite.variables {
+        writeln!(
+            w,
+            "target_{}.variables[\"{}\"] = &{};",
+            index,
+            escape_str(&var.name),
+            var_ident(var)
+        )?;
+    }
+
+    Ok(())
+}
+
+// monitors hidden in the editor stay hidden, and show/hide variable isn't
+// supported, so only the visible ones are registered
+fn codegen_monitors(w: &mut Writer, project: &Project) -> fmt::Result {
+    for monitor in &project.monitors {
+        let var = project.variables.iter().find(|var| {
+            var.name == monitor.variable
+                && var.target.map(|index| &project.sprites[index].name)
+                    == monitor.sprite_name.as_ref()
+        });
+
+        let Some(var) = var.filter(|_| monitor.visible) else {
+            continue;
+        };
+
+        let label = match &monitor.sprite_name {
+            Some(sprite) => format!("{}: {}", sprite, monitor.variable),
+            None => monitor.variable.to_string(),
+        };
+
+        writeln!(
+            w,
+            "scratch_terminal.add_monitor(\"{}\", &{}, \"{}\", {:?}, {:?});",
+            escape_str(&label),
+            var_ident(var),
+            escape_str(&monitor.mode),
+            monitor.x,
+            monitor.y
+        )?;
+    }
+
+    Ok(())
+}
+
+fn codegen_procedure_decl(w: &mut Writer, proc: &Stmt, storage: &str) -> fmt::Result {
+    let Stmt::ProcedureDefinition { prototype, .. } = proc else {
+        unreachable!()
+    };
+
+    writeln!(
+        w,
+        "{}std::function<void({})> {};",
+        storage,
+        vec!["ScratchValue"; prototype.param_order.len()].join(", "),
+        procedure_ident(&prototype.name)
+    )
+}
+
+// procedures are declared up front so that they can call each other and recurse
+fn codegen_procedure(w: &mut Writer, index: usize, origin: &Origin, proc: &Stmt) -> fmt::Result {
+    let Stmt::ProcedureDefinition { prototype, body } = proc else {
+        unreachable!()
+    };
+
+    w.script = origin.block_id.to_string();
+    w.begin(origin)?;
+    writeln!(
+        w,
+        "{} = [&]({}) {{\nScratchTarget &sprite = target_{};",
+        procedure_ident(&prototype.name),
+        prototype
+            .param_order
+            .iter()
+            .map(|x| format!("ScratchValue {}", ident(x)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        index
+    )?;
+
+    codegen_block(w, body)?;
+
+    w.write_str("};\n")?;
+    w.end();
+
+    Ok(())
+}
+
+fn codegen_script(w: &mut Writer, index: usize, origin: &Origin, script: &Stmt) -> fmt::Result {
+    let (start, block) = match script {
+        Stmt::WhenFlagClicked(block) => ("scratch_scheduler.spawn(".to_string(), block),
+        Stmt::WhenKeyPressed { key, block } => (
+            format!("scratch_keyboard.on_key(\"{}\", ", escape_str(key)),
+            block,
+        ),
+        Stmt::WhenClicked(block) => (format!("scratch_mouse.on_click(target_{}, ", index), block),
+        _ => unreachable!(),
+    };
+
+    w.script = origin.block_id.to_string();
+    w.begin(origin)?;
+    writeln!(
+        w,
+        "{}[&] {{\nScratchTarget &sprite = target_{};",
+        start, index
+    )?;
+
+    codegen_block(w, block)?;
+
+    w.write_str("});\n")?;
+    w.end();
+
+    Ok(())
+}
+
+// every script and procedure of a sprite refers to it as `sprite`
+fn codegen_sprite_scripts(w: &mut Writer, index: usize, sprite: &Sprite) -> fmt::Result {
+    w.target = sprite.name.to_string();
+
+    for (origin, proc) in &sprite.procedures {
+        codegen_procedure(w, index, origin, proc)?;
+    }
+
+    for (origin, script) in &sprite.scripts {
+        codegen_script(w, index, origin, script)?;
+    }
+
+    Ok(())
+}
+
+fn codegen_globals(w: &mut Writer, project: &Project, storage: &str) -> fmt::Result {
+    for var in &project.variables {
+        writeln!(w, "{}ScratchValue {};", storage, var_ident(var))?;
     }
 
-    for proc in project.procedures {
-        str.push_str(&codegen_stmt(proc));
+    for list in &project.lists {
+        writeln!(w, "{}ScratchList {};", storage, var_ident(list))?;
     }
 
-    str.push_str(&codegen_stmt(project.body));
+    Ok(())
+}
+
+// runs the scripts, then prints the variables
+fn codegen_run(w: &mut Writer, project: &Project) -> fmt::Result {
+    w.write_str("scratch_scheduler.run();\n")?;
 
     for var in &project.variables {
-        str.push_str(&format!(
-            "{}.print(\"{} = \");\n",
-            var.replace(' ', "_"),
-            var.replace(' ', "_")
-        ));
+        let label = match var.target {
+            Some(index) => format!("{}: {}", project.sprites[index].name, var.name),
+            None => var.name.to_string(),
+        };
+
+        writeln!(
+            w,
+            "{}.print(\"{} = \");",
+            var_ident(var),
+            escape_str(&label)
+        )?;
+    }
+
+    w.write_str("\nreturn 0;\n\n}\n")
+}
+
+fn codegen_main_file(w: &mut Writer, project: &Project, inline_runtime: bool) -> fmt::Result {
+    match inline_runtime {
+        true => w.write_raw(&runtime::inline())?,
+        false => writeln!(w, "{}", runtime::INCLUDE)?,
+    }
+
+    codegen_asset_arrays(w, project, "static const")?;
+    w.write_str(
+        r#"int main(int argc, char **argv)
+{
+scratch_options.parse(argc, argv);
+"#,
+    )?;
+
+    codegen_globals(w, project, "")?;
+
+    for (i, sprite) in project.sprites.iter().enumerate() {
+        codegen_sprite_decl(w, i, sprite)?;
+        codegen_sprite_setup(w, i, sprite)?;
+    }
+
+    codegen_monitors(w, project)?;
+    w.write_str("scratch_runtime.start();\nscratch_input.start();\n")?;
+
+    for sprite in &project.sprites {
+        for (_, proc) in &sprite.procedures {
+            codegen_procedure_decl(w, proc, "")?;
+        }
+    }
+
+    for (i, sprite) in project.sprites.iter().enumerate() {
+        codegen_sprite_scripts(w, i, sprite)?;
+    }
+
+    codegen_run(w, project)
+}
+
+/// Generates a single C++ file from the project, streamed to `sink`. Gives
+/// where its statements come from.
+pub fn codegen_project(
+    sink: &mut dyn io::Write,
+    project: &Project,
+    options: &Options,
+) -> io::Result<Vec<Mapping>> {
+    let mut w = Writer::new(sink, options.comments);
+    let result = codegen_main_file(&mut w, project, options.inline_runtime);
+
+    w.finish(result)
+}
+
+/// Generates a directory of sources instead of a single file: a translation
+/// unit per target, with its declaration, procedures and scripts, one for the
+/// data of costumes and sounds, and `main.cpp`, all sharing `project.hpp`.
+/// Gives the name and contents of each file, and where their statements come
+/// from.
+pub fn codegen_sources(project: &Project, comments: bool) -> (Vec<(String, String)>, Vec<Mapping>) {
+    let mut files = vec![];
+    let mut mappings = vec![];
+
+    // writing to memory can't fail
+    let mut add_file = |name: String, codegen: &dyn Fn(&mut Writer) -> fmt::Result| {
+        let mut code = vec![];
+        let mut w = Writer::new(&mut code, comments);
+        let result = codegen(&mut w);
+
+        for mut mapping in w.finish(result).unwrap() {
+            mapping.file = Some(name.to_string());
+            mappings.push(mapping);
+        }
+
+        files.push((name, String::from_utf8(code).unwrap()));
+    };
+
+    add_file("project.hpp".to_string(), &|w| {
+        writeln!(w, "#pragma once\n{}", runtime::INCLUDE)?;
+        codegen_globals(w, project, "extern ")?;
+
+        for (name, _) in asset_arrays(project) {
+            writeln!(w, "extern const unsigned char {}[];", name)?;
+        }
+
+        for i in 0..project.sprites.len() {
+            writeln!(
+                w,
+                "extern ScratchTarget target_{};\nvoid start_target_{}();",
+                i, i
+            )?;
+        }
+
+        Ok(())
+    });
+
+    add_file("assets.cpp".to_string(), &|w| {
+        w.write_str("#include \"project.hpp\"\n")?;
+        codegen_asset_arrays(w, project, "const")
+    });
+
+    add_file("main.cpp".to_string(), &|w| {
+        w.write_str("#include \"project.hpp\"\n")?;
+        codegen_globals(w, project, "")?;
+        w.write_str(
+            r#"int main(int argc, char **argv)
+{
+scratch_options.parse(argc, argv);
+"#,
+        )?;
+
+        for (i, sprite) in project.sprites.iter().enumerate() {
+            codegen_sprite_setup(w, i, sprite)?;
+        }
+
+        codegen_monitors(w, project)?;
+        w.write_str("scratch_runtime.start();\nscratch_input.start();\n")?;
+
+        for i in 0..project.sprites.len() {
+            writeln!(w, "start_target_{}();", i)?;
+        }
+
+        codegen_run(w, project)
+    });
+
+    for (i, sprite) in project.sprites.iter().enumerate() {
+        add_file(format!("target_{}.cpp", i), &|w| {
+            // targets are declared outside of any function, as the scripts and
+            // procedures capturing them outlive the one starting them
+            writeln!(w, "// {}", sprite.name.replace('\n', " "))?;
+            w.write_str("#include \"project.hpp\"\n")?;
+            codegen_sprite_decl(w, i, sprite)?;
+
+            for (_, proc) in &sprite.procedures {
+                codegen_procedure_decl(w, proc, "static ")?;
+            }
+
+            writeln!(w, "void start_target_{}() {{", i)?;
+            codegen_sprite_scripts(w, i, sprite)?;
+            w.write_str("}\n")
+        });
     }
 
-    str.push_str("\nreturn 0;\n\n}");
+    (files, mappings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // takes `room` bytes, a few at a time, then fails
+    struct Sink {
+        written: Vec<u8>,
+        room: usize,
+    }
+
+    impl io::Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.written.len() >= self.room {
+                return Err(io::Error::other("full"));
+            }
+            let n = buf.len().min(3).min(self.room - self.written.len());
+            self.written.extend(&buf[..n]);
+            Ok(n)
+        }
 
-    str
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn project() -> Project {
+        let say = |text: &str| Stmt::Say {
+            message: Expr::Val(Value::String(text.to_string())),
+            secs: None,
+            think: false,
+        };
+        let origin = |id: &str| Origin {
+            block_id: id.to_string(),
+            opcode: "looks_say".to_string(),
+        };
+
+        Project {
+            sprites: vec![Sprite {
+                name: "Cat".to_string(),
+                is_stage: false,
+                costumes: vec![],
+                sounds: vec![],
+                current_costume: 0,
+                layer_order: 1,
+                volume: 100.0,
+                tempo: 60.0,
+                visible: true,
+                x: 0.0,
+                y: 0.0,
+                size: 100.0,
+                direction: 90.0,
+                rotation_style: "all around".to_string(),
+                variables: vec![],
+                scripts: vec![(
+                    Origin {
+                        block_id: "h".to_string(),
+                        opcode: "event_whenflagclicked".to_string(),
+                    },
+                    Stmt::WhenFlagClicked(BlockStmt {
+                        stmts: vec![(origin("a"), say("hi")), (origin("b"), say("bye"))],
+                    }),
+                )],
+                procedures: vec![],
+            }],
+            variables: vec![],
+            lists: vec![],
+            monitors: vec![],
+        }
+    }
+
+    #[test]
+    fn streams_the_same_code_in_small_writes() {
+        let project = project();
+        let mut whole = vec![];
+        codegen_project(&mut whole, &project, &Options::default()).unwrap();
+
+        let mut sink = Sink {
+            written: vec![],
+            room: usize::MAX,
+        };
+        codegen_project(&mut sink, &project, &Options::default()).unwrap();
+
+        assert_eq!(sink.written, whole);
+    }
+
+    #[test]
+    fn gives_the_error_of_the_sink() {
+        let mut sink = Sink {
+            written: vec![],
+            room: 100,
+        };
+        let error = codegen_project(&mut sink, &project(), &Options::default()).unwrap_err();
+
+        assert_eq!(error.to_string(), "full");
+        assert_eq!(sink.written.len(), 100);
+    }
+
+    #[test]
+    fn maps_statements_to_their_lines() {
+        let mut code = vec![];
+        let mappings = codegen_project(&mut code, &project(), &Options::default()).unwrap();
+        let code = String::from_utf8(code).unwrap();
+        let lines: Vec<&str> = code.lines().collect();
+
+        let blocks: Vec<&str> = mappings.iter().map(|x| x.block.as_str()).collect();
+        assert_eq!(blocks, ["h", "a", "b"]);
+
+        let (first, last) = mappings[1].lines;
+        assert_eq!((first, last), (first, first));
+        assert_eq!(
+            lines[first - 1].trim(),
+            "scratch_runtime.say(sprite, \"hi\", false);"
+        );
+
+        // the script spans the statements and the lines around them
+        let (first, last) = mappings[0].lines;
+        assert!(lines[first - 1].contains("scratch_scheduler.spawn("));
+        assert_eq!(lines[last - 1].trim(), "});");
+        assert_eq!(mappings[2].lines.1 + 1, last);
+        assert!(mappings
+            .iter()
+            .all(|x| x.target == "Cat" && x.script == "h"));
+    }
 }
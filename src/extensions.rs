@@ -0,0 +1,90 @@
+use std::collections::HashSet;
+
+use colored::*;
+
+use crate::parser::ScratchFile;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Support {
+    Supported,
+    Stubbed,
+    Unsupported,
+}
+
+/// What to do with the blocks of unsupported extensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    Fail,
+    Stub,
+}
+
+const SUPPORTED: [&str; 2] = ["pen", "music"];
+
+// these need a camera, a browser or a keyboard made of bananas, so without
+// them their blocks can only do nothing
+const STUBBED: [&str; 5] = [
+    "videoSensing",
+    "faceSensing",
+    "text2speech",
+    "translate",
+    "makeymakey",
+];
+
+pub fn support(extension: &str) -> Support {
+    if SUPPORTED.contains(&extension) {
+        Support::Supported
+    } else if STUBBED.contains(&extension) {
+        Support::Stubbed
+    } else {
+        Support::Unsupported
+    }
+}
+
+/// Reports the extensions the project declares that aren't fully supported,
+/// and gives those whose blocks are to be compiled as stubs. Fails with the
+/// unsupported ones unless the policy is to stub them as well, or the
+/// compilation is partial.
+pub fn check_extensions(file: &ScratchFile, policy: Policy) -> Result<HashSet<String>, String> {
+    let mut stubbed = HashSet::new();
+    let mut unsupported = vec![];
+    let mut report = vec![];
+
+    for extension in &file.extensions {
+        let status = match (support(extension), policy) {
+            (Support::Supported, _) => continue,
+            (Support::Stubbed, _) => {
+                stubbed.insert(extension.to_string());
+                "stubbed, its blocks do nothing".yellow()
+            }
+            (Support::Unsupported, Policy::Stub) => {
+                stubbed.insert(extension.to_string());
+                "unsupported, its blocks do nothing".red()
+            }
+            // their blocks are left to the stubs of a partial compilation
+            (Support::Unsupported, Policy::Fail) if file.partial => {
+                "unsupported, its blocks warn when run".red()
+            }
+            (Support::Unsupported, Policy::Fail) => {
+                unsupported.push(extension.as_str());
+                "unsupported".red().bold()
+            }
+        };
+
+        report.push(format!("  {} : {}", extension.white(), status));
+    }
+
+    // nothing to say about projects that only use supported extensions
+    if !report.is_empty() {
+        eprintln!("{}", "Extensions: ".white().bold());
+        eprintln!("{}", report.join("\n"));
+    }
+
+    if !unsupported.is_empty() {
+        return Err(format!(
+            "{} (use --unsupported stub to compile their blocks to no-ops, or --partial)",
+            unsupported.join(", ")
+        ));
+    }
+
+    Ok(stubbed)
+}
@@ -0,0 +1,272 @@
+use std::collections::BTreeMap;
+
+use colored::*;
+use serde::Serialize;
+
+use crate::parser::{is_hat, JsonScratchFile, JsonScratchTarget};
+
+#[derive(Serialize)]
+struct ProjectInfo {
+    agent: String,
+    semver: String,
+    vm: String,
+    extensions: Vec<String>,
+    targets: Vec<TargetInfo>,
+    scripts: usize,
+    blocks: usize,
+    monitors: Vec<MonitorInfo>,
+}
+
+#[derive(Serialize)]
+struct TargetInfo {
+    name: String,
+    is_stage: bool,
+    layer_order: i32,
+    // by name, with their initial values
+    variables: BTreeMap<String, serde_json::Value>,
+    lists: BTreeMap<String, serde_json::Value>,
+    broadcasts: Vec<String>,
+    // counts by format
+    costumes: BTreeMap<String, usize>,
+    sounds: BTreeMap<String, usize>,
+    // counts by hat block
+    scripts: BTreeMap<String, usize>,
+    // shadow blocks, such as menus, aren't counted
+    blocks: usize,
+}
+
+#[derive(Serialize)]
+struct MonitorInfo {
+    opcode: String,
+    // the variable or list shown, if any
+    name: Option<String>,
+    sprite: Option<String>,
+    mode: String,
+    x: f64,
+    y: f64,
+    visible: bool,
+}
+
+/// Prints an overview of a project, as text or as JSON.
+pub fn show_info(json: &JsonScratchFile, as_json: bool) {
+    let info = project_info(json);
+
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&info).unwrap());
+    } else {
+        print_info(&info);
+    }
+}
+
+fn by_format(
+    assets: &[std::collections::HashMap<String, serde_json::Value>],
+) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+
+    for asset in assets {
+        let format = asset
+            .get("dataFormat")
+            .and_then(|x| x.as_str())
+            .unwrap_or("?");
+        *counts.entry(format.to_string()).or_default() += 1;
+    }
+
+    counts
+}
+
+fn target_info(target: &JsonScratchTarget) -> TargetInfo {
+    let declared = |decls: &std::collections::HashMap<String, Vec<serde_json::Value>>| {
+        decls
+            .values()
+            .filter_map(|x| Some((x.first()?.as_str()?.to_string(), x.get(1)?.clone())))
+            .collect()
+    };
+
+    let mut scripts = BTreeMap::new();
+    for block in target.blocks.values() {
+        if block.top_level && is_hat(&block.opcode) {
+            *scripts.entry(block.opcode.to_string()).or_default() += 1;
+        }
+    }
+
+    let mut broadcasts: Vec<String> = target
+        .broadcasts
+        .values()
+        .filter_map(|x| x.as_str().map(|x| x.to_string()))
+        .collect();
+    broadcasts.sort();
+
+    TargetInfo {
+        name: target.name.to_string(),
+        is_stage: target.is_stage,
+        layer_order: target.layer_order,
+        variables: declared(&target.variables),
+        lists: declared(&target.lists),
+        broadcasts,
+        costumes: by_format(&target.costumes),
+        sounds: by_format(&target.sounds),
+        scripts,
+        blocks: target.blocks.values().filter(|x| !x.shadow).count(),
+    }
+}
+
+fn monitor_info(monitor: &std::collections::HashMap<String, serde_json::Value>) -> MonitorInfo {
+    let get_str = |key: &str| {
+        monitor
+            .get(key)
+            .and_then(|x| x.as_str())
+            .map(|x| x.to_string())
+    };
+    let get_f64 = |key: &str| monitor.get(key).and_then(|x| x.as_f64()).unwrap_or(0.0);
+
+    let params = monitor.get("params").and_then(|x| x.as_object());
+    let name = params
+        .and_then(|x| x.get("VARIABLE").or(x.get("LIST")))
+        .and_then(|x| x.as_str())
+        .map(|x| x.to_string());
+
+    MonitorInfo {
+        opcode: get_str("opcode").unwrap_or_default(),
+        name,
+        sprite: get_str("spriteName"),
+        mode: get_str("mode").unwrap_or_default(),
+        x: get_f64("x"),
+        y: get_f64("y"),
+        visible: monitor
+            .get("visible")
+            .and_then(|x| x.as_bool())
+            .unwrap_or(false),
+    }
+}
+
+fn project_info(json: &JsonScratchFile) -> ProjectInfo {
+    let mut targets: Vec<TargetInfo> = json.targets.iter().map(target_info).collect();
+    targets.sort_by_key(|x| x.layer_order);
+
+    ProjectInfo {
+        agent: json.meta.agent.to_string(),
+        semver: json.meta.semver.to_string(),
+        vm: json.meta.vm.to_string(),
+        extensions: json.extensions.clone(),
+        scripts: targets
+            .iter()
+            .map(|x| x.scripts.values().sum::<usize>())
+            .sum(),
+        blocks: targets.iter().map(|x| x.blocks).sum(),
+        targets,
+        monitors: json.monitors.iter().map(monitor_info).collect(),
+    }
+}
+
+// "3 (svg 2, png 1)"
+fn counts_str(counts: &BTreeMap<String, usize>) -> String {
+    if counts.is_empty() {
+        return "0".to_string();
+    }
+
+    format!(
+        "{} ({})",
+        counts.values().sum::<usize>(),
+        counts
+            .iter()
+            .map(|(name, count)| format!("{} {}", name, count))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+// long lists only show their first items
+fn value_str(value: &serde_json::Value) -> String {
+    match value.as_array() {
+        Some(items) if items.len() > 5 => format!(
+            "[{}, … {} items]",
+            items[..5]
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            items.len()
+        ),
+        _ => value.to_string(),
+    }
+}
+
+fn print_info(info: &ProjectInfo) {
+    let print_val =
+        |name: &str, val: String| println!("  {} : {}", name.white(), val.cyan().bold());
+    let print_sub = |name: &str, val: String| println!("    {} : {}", name.white(), val.cyan());
+    let print_decl = |name: &str, val: String| println!("      {} = {}", name, val);
+
+    println!("{}", "File metadata: ".white().bold());
+    print_val("User Agent", info.agent.to_string());
+    print_val("Semantic version", info.semver.to_string());
+    print_val("Virtual machine", info.vm.to_string());
+
+    println!("{}", "Project: ".white().bold());
+    print_val(
+        "Extensions",
+        match info.extensions.is_empty() {
+            true => "none".to_string(),
+            false => info.extensions.join(", "),
+        },
+    );
+    print_val("Scripts", info.scripts.to_string());
+    print_val("Blocks", info.blocks.to_string());
+
+    println!("{}", "Targets: ".white().bold());
+
+    for target in &info.targets {
+        println!(
+            "  {} {}",
+            target.name.white().bold(),
+            match target.is_stage {
+                true => "(stage)".to_string(),
+                false => format!("(layer {})", target.layer_order),
+            }
+        );
+
+        print_sub("Variables", target.variables.len().to_string());
+        for (name, value) in &target.variables {
+            print_decl(name, value_str(value));
+        }
+
+        print_sub("Lists", target.lists.len().to_string());
+        for (name, value) in &target.lists {
+            print_decl(name, value_str(value));
+        }
+
+        if !target.broadcasts.is_empty() {
+            print_sub("Broadcasts", target.broadcasts.join(", "));
+        }
+
+        print_sub("Costumes", counts_str(&target.costumes));
+
+        if !target.sounds.is_empty() {
+            print_sub("Sounds", counts_str(&target.sounds));
+        }
+
+        print_sub("Scripts", counts_str(&target.scripts));
+        print_sub("Blocks", target.blocks.to_string());
+    }
+
+    if !info.monitors.is_empty() {
+        println!("{}", "Monitors: ".white().bold());
+    }
+
+    for monitor in &info.monitors {
+        let shown = match (&monitor.sprite, &monitor.name) {
+            (Some(sprite), Some(name)) => format!("{}: {}", sprite, name),
+            (None, Some(name)) => name.to_string(),
+            _ => monitor.opcode.to_string(),
+        };
+
+        println!(
+            "  {} : {} at {}, {}{}",
+            shown.white(),
+            monitor.mode.cyan(),
+            monitor.x,
+            monitor.y,
+            if monitor.visible { "" } else { ", hidden" }
+        );
+    }
+}
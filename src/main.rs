@@ -2,40 +2,265 @@ use clap::arg;
 use log::*;
 use simplelog::*;
 
+mod assets;
+mod check;
 mod codegen;
+mod extensions;
+mod info;
+mod native;
 mod parser;
+mod runtime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    // project.json as it was saved
+    Json,
+    // blocks as parsed from it
+    Ast,
+    // scripts lowered to statements and expressions
+    Ir,
+    Code,
+}
+
+#[derive(Debug)]
+enum Mode {
+    Compile {
+        output: Option<String>,
+        inline_runtime: bool,
+        emit_runtime: bool,
+        out_dir: Option<String>,
+        source_map: Option<String>,
+    },
+    Build {
+        output: String,
+    },
+    Run {
+        args: Vec<String>,
+    },
+    Info {
+        json: bool,
+    },
+    Check,
+    Dump {
+        stage: Stage,
+    },
+}
 
 #[derive(Debug)]
 struct Args {
     project_path: String,
     debug: bool,
-    show_info: bool,
+    mode: Mode,
+    unsupported: extensions::Policy,
+    partial: bool,
+    block_comments: bool,
+}
+
+fn block_comments_arg() -> clap::Arg {
+    arg!(--"block-comments" "Comment each statement with the sprite, block ID and opcode it comes from")
+}
+
+// options of the subcommands that compile the project
+fn with_compile_args(command: clap::Command) -> clap::Command {
+    command
+        .arg(
+            arg!(--unsupported <POLICY> "Fail on unsupported extensions or stub their blocks")
+                .value_parser(["fail", "stub"])
+                .default_value("fail"),
+        )
+        .arg(arg!(--partial "Compile unsupported blocks to stubs that warn when run"))
 }
 
 fn get_args() -> Args {
+    let project_path = || arg!(<project_path> "Project path, a .sb3 file or a project.json");
+
     let matches = clap::Command::new("scratchnative")
-        .arg(arg!(-d --debug "Enable debugging"))
-        .arg(arg!(-i --info "Show info about the project"))
-        .arg(arg!([project_path] "Project path").required(true))
+        .about("Compiles Scratch 3 projects to C++")
+        .arg(arg!(-d --debug "Enable debugging").global(true))
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(with_compile_args(
+            clap::Command::new("compile")
+                .about("Compile the project to C++")
+                .arg(project_path())
+                .arg(arg!(-o --output <FILE> "Write the C++ there instead of to stdout"))
+                .arg(arg!(--"inline-runtime" "Put the runtime in the C++ instead of including it"))
+                .arg(
+                    arg!(--"emit-runtime" "Write the runtime next to the C++ file")
+                        .requires("output")
+                        .conflicts_with("inline-runtime"),
+                )
+                .arg(
+                    arg!(--"out-dir" <DIR> "Write a directory of sources, with the runtime, assets and build files")
+                        .conflicts_with_all(["output", "inline-runtime", "emit-runtime"]),
+                )
+                .arg(arg!(--"source-map" <FILE> "Write a JSON map from the lines of the C++ to the blocks they come from"))
+                .arg(block_comments_arg()),
+        ))
+        .subcommand(with_compile_args(
+            clap::Command::new("build")
+                .about("Compile the project to an executable with the system C++ compiler")
+                .arg(project_path())
+                .arg(arg!(-o --output <FILE> "Path of the executable, the name of the project by default")),
+        ))
+        .subcommand(with_compile_args(
+            clap::Command::new("run")
+                .about("Compile the project to an executable and run it")
+                .arg(project_path())
+                .arg(arg!([args] ... "Arguments of the program, after --").last(true)),
+        ))
+        .subcommand(
+            clap::Command::new("info")
+                .about("Show info about the project")
+                .arg(project_path())
+                .arg(arg!(--json "Print the info as JSON")),
+        )
+        .subcommand(
+            clap::Command::new("check")
+                .about("List the opcodes of the project and whether they are supported")
+                .arg(project_path()),
+        )
+        .subcommand(with_compile_args(
+            clap::Command::new("dump")
+                .about("Print the project at a stage of the compilation")
+                .arg(project_path())
+                .arg(
+                    arg!(--stage <STAGE> "json: as saved, ast: parsed blocks, ir: lowered scripts, code: C++")
+                        .value_parser(["json", "ast", "ir", "code"])
+                        .default_value("ir"),
+                )
+                .arg(block_comments_arg()),
+        ))
         .get_matches();
 
-    let project_path = matches
-        .get_one::<String>("project_path")
-        .unwrap()
-        .to_string();
+    let (name, sub) = matches.subcommand().unwrap();
+
+    let mode = match name {
+        "compile" => Mode::Compile {
+            output: sub.get_one::<String>("output").cloned(),
+            inline_runtime: sub.get_flag("inline-runtime"),
+            emit_runtime: sub.get_flag("emit-runtime"),
+            out_dir: sub.get_one::<String>("out-dir").cloned(),
+            source_map: sub.get_one::<String>("source-map").cloned(),
+        },
+        "build" => Mode::Build {
+            output: match sub.get_one::<String>("output") {
+                Some(output) => output.to_string(),
+                None => project_name(sub.get_one::<String>("project_path").unwrap()),
+            },
+        },
+        "run" => Mode::Run {
+            args: sub
+                .get_many::<String>("args")
+                .map(|x| x.cloned().collect())
+                .unwrap_or_default(),
+        },
+        "info" => Mode::Info {
+            json: sub.get_flag("json"),
+        },
+        "check" => Mode::Check,
+        "dump" => Mode::Dump {
+            stage: match sub.get_one::<String>("stage").unwrap().as_str() {
+                "json" => Stage::Json,
+                "ast" => Stage::Ast,
+                "code" => Stage::Code,
+                _ => Stage::Ir,
+            },
+        },
+        _ => unreachable!(),
+    };
+
+    let unsupported = match sub.try_get_one::<String>("unsupported") {
+        Ok(Some(x)) if x == "stub" => extensions::Policy::Stub,
+        _ => extensions::Policy::Fail,
+    };
 
     Args {
+        project_path: sub.get_one::<String>("project_path").unwrap().to_string(),
         debug: matches.get_flag("debug"),
-        project_path,
-        show_info: matches.get_flag("info"),
+        mode,
+        unsupported,
+        partial: sub.try_get_one::<bool>("partial").ok().flatten() == Some(&true),
+        block_comments: sub.try_get_one::<bool>("block-comments").ok().flatten() == Some(&true),
     }
 }
 
+// Every subcommand exits with 0 when it succeeds and 1 when the project can't
+// be read, compiled or built, or `check` finds unsupported blocks. `run`
+// exits like the program instead, and clap with 2 on bad usage.
 fn exit_with_error<S: Into<String>>(msg: S, error: String) -> ! {
     error!("{}: {}", msg.into(), error);
     std::process::exit(1)
 }
 
+// name of the executable, from the name of the project file
+fn project_name(path: &str) -> String {
+    std::path::Path::new(path)
+        .file_stem()
+        .map(|x| {
+            x.to_string_lossy()
+                .chars()
+                .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+                    true => c,
+                    false => '_',
+                })
+                .collect()
+        })
+        .unwrap_or("project".to_string())
+}
+
+fn write_source_map(args: &Args, mappings: &[codegen::Mapping]) {
+    let Mode::Compile {
+        source_map: Some(path),
+        ..
+    } = &args.mode
+    else {
+        return;
+    };
+
+    if let Err(err) = std::fs::write(path, serde_json::to_string_pretty(mappings).unwrap()) {
+        exit_with_error(format!("Cannot write {}", path), err.to_string());
+    }
+}
+
+// the code to build, as a single file
+fn source_of(
+    proj: &parser::Project,
+) -> impl Fn(&mut dyn std::io::Write) -> std::io::Result<()> + '_ {
+    |sink| codegen::codegen_project(sink, proj, &codegen::Options::default()).map(|_| ())
+}
+
+fn parse(source: &assets::ProjectSource) -> parser::ScratchFile {
+    match parser::parse_scratch_file(source.json.clone()) {
+        Ok(file) => file,
+        Err(err) => exit_with_error("Cannot parse project", err),
+    }
+}
+
+fn lower(args: &Args, mut scratch_file: parser::ScratchFile) -> parser::Project {
+    scratch_file.partial = args.partial;
+    scratch_file.stubbed_extensions =
+        match extensions::check_extensions(&scratch_file, args.unsupported) {
+            Ok(stubbed) => stubbed,
+            Err(err) => exit_with_error("Unsupported extensions", err),
+        };
+
+    let proj = match parser::scratch_file_to_project(scratch_file) {
+        Ok(proj) => proj,
+        Err(err) => exit_with_error(
+            "Unsupported blocks",
+            format!(
+                "{}\nRun `check` to list the supported opcodes, or compile with --partial to stub them",
+                err
+            ),
+        ),
+    };
+
+    debug!("AST:\n {:#?}", proj);
+
+    proj
+}
+
 fn main() {
     let args = get_args();
 
@@ -54,26 +279,138 @@ fn main() {
 
     debug!("program arguments: {:?}", args);
 
-    let path = args.project_path;
-
-    let content = match std::fs::read_to_string(path) {
-        Ok(content) => content,
-        Err(err) => exit_with_error("Cannot read file", err.to_string()),
+    let mut source = match assets::ProjectSource::open(&args.project_path) {
+        Ok(source) => source,
+        Err(err) => exit_with_error("Cannot read file", err),
     };
 
-    let scratch_file = parser::parse_scratch_file(content);
+    match &args.mode {
+        Mode::Info { json } => {
+            match serde_json::from_str::<parser::JsonScratchFile>(&source.json) {
+                Ok(project) => info::show_info(&project, *json),
+                Err(err) => exit_with_error("Cannot parse project", err.to_string()),
+            }
+            return;
+        }
+
+        Mode::Check => {
+            let supported = check::check_opcodes(&parse(&source));
+            std::process::exit(if supported { 0 } else { 1 });
+        }
 
-    if args.show_info {
-        parser::show_info(&scratch_file);
+        Mode::Dump { stage: Stage::Json } => {
+            match serde_json::from_str::<serde_json::Value>(&source.json) {
+                Ok(json) => println!("{}", serde_json::to_string_pretty(&json).unwrap()),
+                Err(err) => exit_with_error("Cannot parse project", err.to_string()),
+            }
+            return;
+        }
+
+        Mode::Dump { stage: Stage::Ast } => {
+            println!("{:#?}", parse(&source));
+            return;
+        }
+
+        _ => (),
     }
 
-    let proj = parser::scratch_file_to_project(scratch_file);
+    let mut scratch_file = parse(&source);
+    assets::load_costumes(&mut scratch_file, &mut source);
+    assets::load_sounds(&mut scratch_file, &mut source);
 
-    debug!("AST:\n {:#?}", proj);
+    if let Mode::Compile {
+        out_dir: Some(dir), ..
+    } = &args.mode
+    {
+        let dir = std::path::Path::new(dir);
+
+        if let Err(err) = assets::copy_assets(&scratch_file, &mut source, &dir.join("assets")) {
+            exit_with_error("Cannot copy assets", err);
+        }
+
+        let (files, mappings) =
+            codegen::codegen_sources(&lower(&args, scratch_file), args.block_comments);
+
+        if let Err(err) = native::write_sources(dir, &project_name(&args.project_path), &files) {
+            exit_with_error(format!("Cannot write {}", dir.display()), err);
+        }
+
+        write_source_map(&args, &mappings);
+        return;
+    }
+
+    let proj = lower(&args, scratch_file);
+
+    if let Mode::Dump { stage: Stage::Ir } = args.mode {
+        println!("{:#?}", proj);
+        return;
+    }
+
+    let options = codegen::Options {
+        comments: args.block_comments,
+        inline_runtime: matches!(
+            args.mode,
+            Mode::Compile {
+                inline_runtime: true,
+                ..
+            }
+        ),
+    };
+
+    match &args.mode {
+        Mode::Compile {
+            output: Some(output),
+            emit_runtime,
+            ..
+        } => {
+            let written = std::fs::File::create(output).and_then(|file| {
+                codegen::codegen_project(&mut std::io::BufWriter::new(file), &proj, &options)
+            });
 
-    let str = codegen::codegen_project(proj);
+            let mut mappings = match written {
+                Ok(mappings) => mappings,
+                Err(err) => exit_with_error(format!("Cannot write {}", output), err.to_string()),
+            };
 
-    println!("{}", str);
+            for mapping in &mut mappings {
+                mapping.file = Some(output.to_string());
+            }
 
-    // codegen::codegen_file(scratch_file);
+            write_source_map(&args, &mappings);
+
+            let dir = std::path::Path::new(output)
+                .parent()
+                .unwrap_or(".".as_ref());
+
+            if *emit_runtime {
+                if let Err(err) = runtime::emit(dir) {
+                    exit_with_error("Cannot write runtime", err);
+                }
+            }
+        }
+
+        Mode::Build { output } => {
+            if let Err(err) = native::build(&source_of(&proj), std::path::Path::new(output)) {
+                exit_with_error("Cannot build project", err);
+            }
+        }
+
+        Mode::Run { args } => match native::run(&source_of(&proj), args) {
+            Ok(code) => std::process::exit(code),
+            Err(err) => exit_with_error("Cannot build project", err),
+        },
+
+        _ => {
+            let stdout = std::io::stdout();
+
+            match codegen::codegen_project(
+                &mut std::io::BufWriter::new(stdout.lock()),
+                &proj,
+                &options,
+            ) {
+                Ok(mappings) => write_source_map(&args, &mappings),
+                Err(err) => exit_with_error("Cannot write code", err.to_string()),
+            }
+        }
+    }
 }
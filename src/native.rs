@@ -0,0 +1,249 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use colored::*;
+use log::debug;
+
+use crate::runtime;
+
+const FLAGS: [&str; 3] = ["-std=c++17", "-O2", "-pthread"];
+
+/// The generated C++, streamed to a file.
+pub type Source<'a> = &'a dyn Fn(&mut dyn Write) -> io::Result<()>;
+
+/// Compiles generated C++ into an executable with the system compiler,
+/// `$CXX`, g++ or clang++. Errors in the generated code are reported with the
+/// sprite they come from.
+pub fn build(source: Source, exe: &Path) -> Result<(), String> {
+    let (compiler, compiler_args) = find_compiler()?;
+    let dir = temp_dir()?;
+    let cpp = dir.join("main.cpp");
+
+    let result = runtime::emit(&dir)
+        .and_then(|_| write_source(&cpp, source))
+        .and_then(|_| {
+            debug!("building {} with {}", cpp.display(), compiler);
+
+            Command::new(&compiler)
+                .args(&compiler_args)
+                .args(FLAGS)
+                .arg("-o")
+                .arg(exe)
+                .arg(&cpp)
+                .output()
+                .map_err(|x| format!("cannot run {}: {}", compiler, x))
+        });
+
+    // the code is read back to point at the lines the compiler complains about
+    if let Ok(output) = &result {
+        let diagnostics = String::from_utf8_lossy(&output.stderr);
+        debug!("{} said:\n{}", compiler, diagnostics);
+
+        if !diagnostics.is_empty() {
+            let code = std::fs::read_to_string(&cpp).unwrap_or_default();
+            report_diagnostics(&code, &dir, &diagnostics);
+        }
+    }
+
+    std::fs::remove_dir_all(&dir).ok();
+    let output = result?;
+
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(format!("{} failed", compiler)),
+    }
+}
+
+fn write_source(path: &Path, source: Source) -> Result<(), String> {
+    let file = std::fs::File::create(path).map_err(|x| x.to_string())?;
+    let mut sink = io::BufWriter::new(file);
+
+    source(&mut sink)
+        .and_then(|_| sink.flush())
+        .map_err(|x| format!("{}: {}", path.display(), x))
+}
+
+/// Writes generated sources to `dir` along with the runtime and the files to
+/// build them with CMake or make, flags other than the required ones being
+/// left to whoever builds them.
+pub fn write_sources(dir: &Path, name: &str, files: &[(String, String)]) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|x| x.to_string())?;
+    runtime::emit(dir)?;
+
+    let sources: Vec<&str> = files
+        .iter()
+        .map(|(x, _)| x.as_str())
+        .filter(|x| x.ends_with(".cpp"))
+        .collect();
+
+    let cmake = format!(
+        "cmake_minimum_required(VERSION 3.10)
+project({name} CXX)
+
+set(CMAKE_CXX_STANDARD 17)
+set(CMAKE_CXX_STANDARD_REQUIRED ON)
+find_package(Threads REQUIRED)
+
+add_executable({name} {sources})
+target_link_libraries({name} Threads::Threads)
+",
+        name = name,
+        sources = sources.join(" ")
+    );
+
+    let make = format!(
+        "CXXFLAGS ?= -O2
+SOURCES = {sources}
+
+{name}: $(SOURCES:.cpp=.o)
+\t$(CXX) $(CXXFLAGS) -pthread -o $@ $^
+
+%.o: %.cpp project.hpp
+\t$(CXX) $(CXXFLAGS) -std=c++17 -pthread -c -o $@ $<
+
+clean:
+\trm -f {name} $(SOURCES:.cpp=.o)
+
+.PHONY: clean
+",
+        name = name,
+        sources = sources.join(" ")
+    );
+
+    let build_files = [
+        ("CMakeLists.txt".to_string(), cmake),
+        ("Makefile".to_string(), make),
+    ];
+
+    for (file, contents) in files.iter().chain(&build_files) {
+        std::fs::write(dir.join(file), contents).map_err(|x| format!("{}: {}", file, x))?;
+    }
+
+    Ok(())
+}
+
+/// Builds generated C++ and runs it with the given arguments, giving its exit
+/// code.
+pub fn run(source: Source, args: &[String]) -> Result<i32, String> {
+    let dir = temp_dir()?;
+    let exe = dir.join("project");
+
+    let result = build(source, &exe).and_then(|_| {
+        Command::new(&exe)
+            .args(args)
+            .status()
+            .map_err(|x| x.to_string())
+    });
+
+    std::fs::remove_dir_all(&dir).ok();
+
+    // killed by a signal, as a shell would tell
+    Ok(result?.code().unwrap_or(128))
+}
+
+// `$CXX` may come with arguments of its own, such as "ccache g++"
+fn find_compiler() -> Result<(String, Vec<String>), String> {
+    if let Ok(cxx) = std::env::var("CXX") {
+        let mut words = cxx.split_whitespace().map(|x| x.to_string());
+
+        if let Some(compiler) = words.next() {
+            return Ok((compiler, words.collect()));
+        }
+    }
+
+    let path = std::env::var_os("PATH").unwrap_or_default();
+
+    for compiler in ["g++", "clang++"] {
+        if std::env::split_paths(&path).any(|x| x.join(compiler).is_file()) {
+            return Ok((compiler.to_string(), vec![]));
+        }
+    }
+
+    Err("no C++ compiler found, install g++ or clang++ or set $CXX".to_string())
+}
+
+// Prints the errors and warnings of the compiler, those about the generated
+// code with the sprite of the script they are in, and its line. Everything
+// else the compiler says is only shown with --debug.
+fn report_diagnostics(source: &str, dir: &Path, diagnostics: &str) {
+    let lines: Vec<&str> = source.lines().collect();
+    let dir = format!("{}/", dir.display());
+
+    for diagnostic in diagnostics.lines() {
+        let Some((location, kind, message)) = parse_diagnostic(diagnostic) else {
+            continue;
+        };
+
+        let kind = match kind {
+            "error" | "fatal error" => kind.red().bold(),
+            "warning" => kind.yellow().bold(),
+            _ => continue,
+        };
+
+        // the build directory is gone by now
+        let location = location.strip_prefix(&dir).unwrap_or(location);
+
+        let Some(location) = location.strip_prefix("main.cpp:") else {
+            eprintln!("{}: {}: {}", location, kind, message);
+            continue;
+        };
+
+        let line: usize = location
+            .split(':')
+            .next()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(0);
+
+        eprintln!("{}: {}: {}", target_at(&lines, line), kind, message);
+
+        if let Some(code) = lines.get(line.wrapping_sub(1)) {
+            eprintln!("    {}", code.trim().dimmed());
+        }
+    }
+}
+
+// "file:line:column: kind: message"
+fn parse_diagnostic(line: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = line.splitn(2, ": ");
+    let location = parts.next()?;
+    let (kind, message) = parts.next()?.split_once(": ")?;
+
+    Some((location, kind, message))
+}
+
+// Scripts and procedures start by binding the target they belong to, whose
+// name is in its declaration.
+fn target_at(lines: &[&str], line: usize) -> String {
+    let binding = "ScratchTarget &sprite = target_";
+
+    let index = lines[..line.min(lines.len())]
+        .iter()
+        .rev()
+        .find_map(|x| x.trim_start().strip_prefix(binding)?.strip_suffix(';'));
+
+    let Some(index) = index else {
+        return "project".to_string();
+    };
+
+    let declaration = format!("ScratchTarget target_{}(\"", index);
+
+    lines
+        .iter()
+        .find_map(|x| x.trim_start().strip_prefix(&declaration)?.split('"').next())
+        .unwrap_or("project")
+        .to_string()
+}
+
+fn temp_dir() -> Result<PathBuf, String> {
+    static COUNT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    let dir = std::env::temp_dir().join(format!(
+        "scratchnative-{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    ));
+
+    std::fs::create_dir_all(&dir).map_err(|x| x.to_string())?;
+    Ok(dir)
+}
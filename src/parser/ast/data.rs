@@ -1,43 +1,53 @@
 use crate::parser::ast::*;
 
-pub fn data_to_statement(file: &ScratchFile, block: ScratchBlock, op: String) -> Stmt {
+pub const OPCODES: &[&str] = &[
+    "addtolist",
+    "deletealloflist",
+    "setvariableto",
+    "changevariableby",
+    "itemoflist",
+];
+
+fn variable_of(file: &ScratchFile, block: &ScratchBlock) -> VarRef {
+    VarRef::variable(file, block, block.fields["VARIABLE"][0].as_str().unwrap())
+}
+
+fn list_of(file: &ScratchFile, block: &ScratchBlock) -> VarRef {
+    VarRef::list(file, block, block.fields["LIST"][0].as_str().unwrap())
+}
+
+pub fn data_to_statement(file: &ScratchFile, block: &ScratchBlock, op: String) -> Stmt {
     match op.as_str() {
         "addtolist" => Stmt::AddToList {
-            name: block.fields["LIST"][0].as_str().unwrap().to_string(),
-            val: scratch_val_to_expr(file, block.inputs["ITEM"].1.clone(), &block),
-            id: block.fields["LIST"][1].as_str().unwrap().to_string(),
+            list: list_of(file, block),
+            val: scratch_val_to_expr(file, &block.inputs["ITEM"].0, block),
         },
 
         "deletealloflist" => Stmt::DeleteAllOfList {
-            name: block.fields["LIST"][0].as_str().unwrap().to_string(),
+            list: list_of(file, block),
         },
 
         "setvariableto" => Stmt::SetVariable {
-            name: block.fields["VARIABLE"][0].as_str().unwrap().to_string(),
-            val: scratch_val_to_expr(file, block.inputs["VALUE"].1.clone(), &block),
-            id: block.fields["VARIABLE"][1].as_str().unwrap().to_string(),
+            var: variable_of(file, block),
+            val: scratch_val_to_expr(file, &block.inputs["VALUE"].0, block),
         },
 
         "changevariableby" => Stmt::ChangeBy {
-            var_name: block.fields["VARIABLE"][0].as_str().unwrap().to_string(),
-            inc: scratch_val_to_expr(file, block.inputs["VALUE"].1.clone(), &block),
+            var: variable_of(file, block),
+            inc: scratch_val_to_expr(file, &block.inputs["VALUE"].0, block),
         },
 
-        _ => todo!("data_{}", op),
+        _ => unsupported_stmt(file, block),
     }
 }
 
-pub fn expr_from_data(file: &ScratchFile, block: ScratchBlock, op: &str) -> Expr {
+pub fn expr_from_data(file: &ScratchFile, block: &ScratchBlock, op: &str) -> Expr {
     match op {
         "itemoflist" => Expr::ItemOf {
-            list_name: block.fields["LIST"][0].as_str().unwrap().to_string(),
-            index: Box::new(scratch_val_to_expr(
-                file,
-                block.inputs["INDEX"].1.clone(),
-                &block,
-            )),
+            list: list_of(file, block),
+            index: Box::new(scratch_val_to_expr(file, &block.inputs["INDEX"].0, block)),
         },
 
-        _ => todo!("data_{}", op),
+        _ => unsupported_expr(file, block),
     }
 }
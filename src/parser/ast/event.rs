@@ -1,15 +1,31 @@
 use crate::parser::ast::*;
 
+pub const OPCODES: &[&str] = &[
+    "whenflagclicked",
+    "whenkeypressed",
+    "whenthisspriteclicked",
+    "whenstageclicked",
+];
+
 pub fn event_to_statement(
     file: &ScratchFile,
-    _block: ScratchBlock,
-    next_block: Option<ScratchBlock>,
+    block: &ScratchBlock,
+    next_block: Option<&ScratchBlock>,
     event: String,
 ) -> Stmt {
+    let body = || BlockStmt {
+        stmts: next_block
+            .map(|next| block_chain_to_vec(file, next))
+            .unwrap_or_default(),
+    };
+
     match event.as_str() {
-        "whenflagclicked" => Stmt::WhenFlagClicked(BlockStmt {
-            stmts: block_chain_to_vec(file, next_block.unwrap()),
-        }),
-        _ => todo!("event_{}", event),
+        "whenflagclicked" => Stmt::WhenFlagClicked(body()),
+        "whenkeypressed" => Stmt::WhenKeyPressed {
+            key: block.fields["KEY_OPTION"][0].as_str().unwrap().to_string(),
+            block: body(),
+        },
+        "whenthisspriteclicked" | "whenstageclicked" => Stmt::WhenClicked(body()),
+        _ => unsupported_stmt(file, block),
     }
 }
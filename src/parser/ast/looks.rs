@@ -0,0 +1,119 @@
+use crate::parser::ast::*;
+
+pub const OPCODES: &[&str] = &[
+    "switchcostumeto",
+    "nextcostume",
+    "switchbackdropto",
+    "switchbackdroptoandwait",
+    "nextbackdrop",
+    "setsizeto",
+    "changesizeby",
+    "seteffectto",
+    "changeeffectby",
+    "cleargraphiceffects",
+    "say",
+    "think",
+    "sayforsecs",
+    "thinkforsecs",
+    "show",
+    "hide",
+    "gotofrontback",
+    "goforwardbackwardlayers",
+    "costume",
+    "backdrops",
+    "costumenumbername",
+    "backdropnumbername",
+    "size",
+];
+
+pub fn looks_to_statement(file: &ScratchFile, block: &ScratchBlock, op: &str) -> Stmt {
+    match op {
+        "switchcostumeto" => Stmt::SwitchCostume {
+            costume: scratch_val_to_expr(file, &block.inputs["COSTUME"].0, block),
+        },
+
+        "nextcostume" => Stmt::NextCostume,
+
+        // nothing listens to "when backdrop switches to" yet, so waiting is the same as switching
+        "switchbackdropto" | "switchbackdroptoandwait" => Stmt::SwitchBackdrop {
+            backdrop: scratch_val_to_expr(file, &block.inputs["BACKDROP"].0, block),
+        },
+
+        "nextbackdrop" => Stmt::NextBackdrop,
+
+        "setsizeto" => Stmt::SetSize {
+            size: scratch_val_to_expr(file, &block.inputs["SIZE"].0, block),
+        },
+
+        "changesizeby" => Stmt::ChangeSize {
+            inc: scratch_val_to_expr(file, &block.inputs["CHANGE"].0, block),
+        },
+
+        "seteffectto" => Stmt::SetEffect {
+            effect: block.fields["EFFECT"][0].as_str().unwrap().to_string(),
+            val: scratch_val_to_expr(file, &block.inputs["VALUE"].0, block),
+        },
+
+        "changeeffectby" => Stmt::ChangeEffect {
+            effect: block.fields["EFFECT"][0].as_str().unwrap().to_string(),
+            inc: scratch_val_to_expr(file, &block.inputs["CHANGE"].0, block),
+        },
+
+        "cleargraphiceffects" => Stmt::ClearEffects,
+
+        "say" | "think" => Stmt::Say {
+            message: scratch_val_to_expr(file, &block.inputs["MESSAGE"].0, block),
+            secs: None,
+            think: op == "think",
+        },
+
+        "sayforsecs" | "thinkforsecs" => Stmt::Say {
+            message: scratch_val_to_expr(file, &block.inputs["MESSAGE"].0, block),
+            secs: Some(scratch_val_to_expr(file, &block.inputs["SECS"].0, block)),
+            think: op == "thinkforsecs",
+        },
+
+        "show" => Stmt::Show,
+        "hide" => Stmt::Hide,
+
+        "gotofrontback" => Stmt::GoToFrontBack {
+            front: block.fields["FRONT_BACK"][0].as_str().unwrap() == "front",
+        },
+
+        "goforwardbackwardlayers" => Stmt::GoForwardBackwardLayers {
+            forward: block.fields["FORWARD_BACKWARD"][0].as_str().unwrap() == "forward",
+            layers: scratch_val_to_expr(file, &block.inputs["NUM"].0, block),
+        },
+
+        _ => unsupported_stmt(file, block),
+    }
+}
+
+pub fn expr_from_looks(file: &ScratchFile, block: &ScratchBlock, op: &str) -> Expr {
+    let is_number = || block.fields["NUMBER_NAME"][0].as_str().unwrap() == "number";
+
+    match op {
+        // menus
+        "costume" => Expr::Val(Value::String(
+            block.fields["COSTUME"][0].as_str().unwrap().to_string(),
+        )),
+
+        "backdrops" => Expr::Val(Value::String(
+            block.fields["BACKDROP"][0].as_str().unwrap().to_string(),
+        )),
+
+        "costumenumbername" => match is_number() {
+            true => Expr::CostumeNumber,
+            false => Expr::CostumeName,
+        },
+
+        "backdropnumbername" => match is_number() {
+            true => Expr::BackdropNumber,
+            false => Expr::BackdropName,
+        },
+
+        "size" => Expr::Size,
+
+        _ => unsupported_expr(file, block),
+    }
+}
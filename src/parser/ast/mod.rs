@@ -1,20 +1,29 @@
 use std::collections::HashMap;
 
-use log::debug;
+use log::{debug, warn};
 
-use crate::parser::{ScratchBlock, ScratchFile, ScratchValue, ScratchValueData};
+use crate::parser::{
+    ScratchBlock, ScratchCostume, ScratchFile, ScratchMonitor, ScratchSound, ScratchTarget,
+    ScratchValue, ScratchValueData, ScratchVariableDecl,
+};
 
 mod control;
 mod data;
 mod event;
+mod looks;
+mod motion;
+mod music;
 mod operator;
+mod pen;
 mod procedures;
+mod sensing;
+mod sound;
 
 use super::ScratchTypes;
 
 #[derive(Debug, Clone)]
 pub enum Value {
-    Number(i64),
+    Number(f64),
     String(String),
 }
 
@@ -38,8 +47,8 @@ pub enum OpType {
 }
 
 impl OpType {
-    fn from_str(str: &str) -> OpType {
-        match str {
+    fn from_str(str: &str) -> Option<OpType> {
+        Some(match str {
             "add" => OpType::Add,
             "subtract" => OpType::Subtract,
             "multiply" => OpType::Multiply,
@@ -56,8 +65,8 @@ impl OpType {
             "letter_of" => OpType::LetterOf,
             "join" => OpType::Join,
 
-            _ => todo!("{}", str),
-        }
+            _ => return None,
+        })
     }
 }
 
@@ -80,27 +89,117 @@ pub enum Expr {
     },
 
     ItemOf {
-        list_name: String,
+        list: VarRef,
         index: Box<Expr>,
     },
 
+    CostumeNumber,
+    CostumeName,
+    BackdropNumber,
+    BackdropName,
+    Size,
+
+    Answer,
+    Timer,
+    Current(String),
+    DaysSince2000,
+    Username,
+    KeyPressed(Box<Expr>),
+    MouseX,
+    MouseY,
+    MouseDown,
+
+    Of {
+        object: Box<Expr>,
+        property: String,
+    },
+    DistanceTo(Box<Expr>),
+    TouchingObject(Box<Expr>),
+    TouchingColor(Box<Expr>),
+
+    XPosition,
+    YPosition,
+    Direction,
+
+    Volume,
+    Tempo,
+
     Val(Value),
-    Var(String),
+    Var(VarRef),
     Param(String),
+
+    // a reporter that isn't supported, see `ScratchFile::partial`
+    Unsupported {
+        opcode: String,
+        block_id: String,
+    },
 }
 
+/// The block a statement is lowered from.
 #[derive(Debug)]
-pub struct EmptyStmt {}
+pub struct Origin {
+    pub block_id: String,
+    pub opcode: String,
+}
+
+impl Origin {
+    fn of(block: &ScratchBlock) -> Origin {
+        Origin {
+            block_id: block.id.to_string(),
+            opcode: block.opcode.to_string(),
+        }
+    }
+}
+
+/// A variable or list by name, along with the target it is local to, as
+/// sprites may each have a local variable of the same name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarRef {
+    pub name: String,
+    // unset for those of the stage, which every target sees
+    pub target: Option<usize>,
+}
+
+impl VarRef {
+    fn declared(target: &ScratchTarget, index: usize, name: &str) -> VarRef {
+        VarRef {
+            name: name.to_string(),
+            target: (!target.is_stage).then_some(index),
+        }
+    }
+
+    // a local variable hides a global one of the same name
+    fn resolve(
+        file: &ScratchFile,
+        block: &ScratchBlock,
+        name: &str,
+        locals: &HashMap<String, ScratchVariableDecl>,
+    ) -> VarRef {
+        let target = &file.targets[block.target];
+        let is_local = !target.is_stage && locals.values().any(|x| x.0 == name);
+
+        VarRef {
+            name: name.to_string(),
+            target: is_local.then_some(block.target),
+        }
+    }
+
+    pub fn variable(file: &ScratchFile, block: &ScratchBlock, name: &str) -> VarRef {
+        VarRef::resolve(file, block, name, &file.targets[block.target].variables)
+    }
+
+    pub fn list(file: &ScratchFile, block: &ScratchBlock, name: &str) -> VarRef {
+        VarRef::resolve(file, block, name, &file.targets[block.target].lists)
+    }
+}
 
 #[derive(Debug)]
 pub struct BlockStmt {
-    pub stmts: Vec<Stmt>,
+    pub stmts: Vec<(Origin, Stmt)>,
 }
 
 #[derive(Debug)]
 pub struct ProcedurePrototype {
-    // id: name
-    pub params: HashMap<String, String>,
     pub param_order: Vec<String>,
     pub name: String,
 }
@@ -108,19 +207,22 @@ pub struct ProcedurePrototype {
 #[derive(Debug)]
 pub enum Stmt {
     WhenFlagClicked(BlockStmt),
+    WhenKeyPressed {
+        key: String,
+        block: BlockStmt,
+    },
+    WhenClicked(BlockStmt),
     SetVariable {
-        name: String,
-        id: String,
+        var: VarRef,
         val: Expr,
     },
     AddToList {
-        name: String,
-        id: String,
+        list: VarRef,
         val: Expr,
     },
 
     DeleteAllOfList {
-        name: String,
+        list: VarRef,
     },
 
     Repeat {
@@ -131,6 +233,9 @@ pub enum Stmt {
         condition: Expr,
         block: BlockStmt,
     },
+    Wait {
+        secs: Expr,
+    },
     If {
         condition: Expr,
         block: BlockStmt,
@@ -142,7 +247,7 @@ pub enum Stmt {
     },
 
     ChangeBy {
-        var_name: String,
+        var: VarRef,
         inc: Expr,
     },
 
@@ -155,25 +260,211 @@ pub enum Stmt {
         body: BlockStmt,
     },
 
+    SwitchCostume {
+        costume: Expr,
+    },
+    NextCostume,
+    SwitchBackdrop {
+        backdrop: Expr,
+    },
+    NextBackdrop,
+
+    SetSize {
+        size: Expr,
+    },
+    ChangeSize {
+        inc: Expr,
+    },
+
+    SetEffect {
+        effect: String,
+        val: Expr,
+    },
+    ChangeEffect {
+        effect: String,
+        inc: Expr,
+    },
+    ClearEffects,
+
+    Say {
+        message: Expr,
+        secs: Option<Expr>,
+        think: bool,
+    },
+
+    Show,
+    Hide,
+
+    AskAndWait {
+        question: Expr,
+    },
+    ResetTimer,
+
+    GoToFrontBack {
+        front: bool,
+    },
+    GoForwardBackwardLayers {
+        forward: bool,
+        layers: Expr,
+    },
+
+    MoveSteps {
+        steps: Expr,
+    },
+    Turn {
+        right: bool,
+        degrees: Expr,
+    },
+    PointInDirection {
+        direction: Expr,
+    },
+    PointTowards {
+        object: Expr,
+    },
+    GoToXY {
+        x: Expr,
+        y: Expr,
+    },
+    GoTo {
+        object: Expr,
+    },
+    GlideToXY {
+        secs: Expr,
+        x: Expr,
+        y: Expr,
+    },
+    GlideTo {
+        secs: Expr,
+        object: Expr,
+    },
+    ChangeX {
+        inc: Expr,
+    },
+    SetX {
+        x: Expr,
+    },
+    ChangeY {
+        inc: Expr,
+    },
+    SetY {
+        y: Expr,
+    },
+    IfOnEdgeBounce,
+    SetRotationStyle {
+        style: String,
+    },
+
+    PenClear,
+    PenStamp,
+    PenDown,
+    PenUp,
+    SetPenColor {
+        color: Expr,
+    },
+    SetPenColorParam {
+        param: Expr,
+        val: Expr,
+        change: bool,
+    },
+    SetPenSize {
+        size: Expr,
+        change: bool,
+    },
+    SetPenHue {
+        hue: Expr,
+        change: bool,
+    },
+    SetPenShade {
+        shade: Expr,
+        change: bool,
+    },
+
+    // sound
+    PlaySound {
+        sound: Expr,
+        until_done: bool,
+    },
+    StopAllSounds,
+    SetSoundEffect {
+        effect: String,
+        val: Expr,
+        change: bool,
+    },
+    ClearSoundEffects,
+    SetVolume {
+        volume: Expr,
+        change: bool,
+    },
+
+    // music
+    PlayNote {
+        note: Expr,
+        beats: Expr,
+    },
+    PlayDrum {
+        drum: Expr,
+        beats: Expr,
+    },
+    Rest {
+        beats: Expr,
+    },
+    SetInstrument {
+        instrument: Expr,
+    },
+    SetTempo {
+        tempo: Expr,
+        change: bool,
+    },
+
+    Unsupported {
+        opcode: String,
+        block_id: String,
+    },
+
     Empty,
 }
 
+#[derive(Debug)]
+pub struct Sprite {
+    pub name: String,
+    pub is_stage: bool,
+    pub costumes: Vec<ScratchCostume>,
+    pub sounds: Vec<ScratchSound>,
+    pub current_costume: i32,
+    pub layer_order: i32,
+    pub volume: f64,
+    pub tempo: f64,
+    pub visible: bool,
+    pub x: f64,
+    pub y: f64,
+    pub size: f64,
+    pub direction: f64,
+    pub rotation_style: String,
+    pub variables: Vec<VarRef>,
+    // with the hat block or definition they start with
+    pub scripts: Vec<(Origin, Stmt)>,
+    pub procedures: Vec<(Origin, Stmt)>,
+}
+
 #[derive(Debug)]
 pub struct Project {
-    pub body: Stmt,
-    pub variables: Vec<String>,
-    pub lists: Vec<String>,
-    pub procedures: Vec<Stmt>,
+    pub sprites: Vec<Sprite>,
+    pub variables: Vec<VarRef>,
+    pub lists: Vec<VarRef>,
+    pub monitors: Vec<ScratchMonitor>,
 }
 
-fn block_chain_to_vec(file: &ScratchFile, root_block: ScratchBlock) -> Vec<Stmt> {
-    let mut curr_block = &root_block;
-    let mut ret: Vec<Stmt> = vec![];
+fn block_chain_to_vec(file: &ScratchFile, root_block: &ScratchBlock) -> Vec<(Origin, Stmt)> {
+    let mut curr_block = root_block;
+    let mut ret: Vec<(Origin, Stmt)> = vec![];
 
     loop {
         let next = &curr_block.next;
 
-        ret.push(scratch_block_to_statement(file.clone(), curr_block.clone()));
+        ret.push((
+            Origin::of(curr_block),
+            scratch_block_to_statement(file, curr_block),
+        ));
 
         if next.is_none() {
             break;
@@ -181,133 +472,512 @@ fn block_chain_to_vec(file: &ScratchFile, root_block: ScratchBlock) -> Vec<Stmt>
 
         curr_block = file.targets[curr_block.target]
             .blocks
-            .get(&next.clone().unwrap().to_string())
+            .get(next.as_ref().unwrap())
             .unwrap();
     }
 
     ret
 }
 
-fn scratch_val_data_to_val(data: &ScratchValueData) -> Value {
-    match &data {
-        ScratchValueData::Int(x) => Value::Number(*x),
-        ScratchValueData::String(x) => Value::String(x.to_string()),
-        _ => todo!(),
+/// Whether blocks of an opcode can be lowered, from the `OPCODES` of their
+/// category. Blocks of stubbed extensions aren't counted.
+pub fn is_supported(opcode: &str) -> bool {
+    let (category, op) = opcode.split_once('_').unwrap_or((opcode, ""));
+
+    let opcodes = match category {
+        "control" => control::OPCODES,
+        "data" => data::OPCODES,
+        "event" => event::OPCODES,
+        "looks" => looks::OPCODES,
+        "motion" => motion::OPCODES,
+        "music" => music::OPCODES,
+        "operator" => operator::OPCODES,
+        "pen" => pen::OPCODES,
+        "procedures" => procedures::OPCODES,
+        "sensing" => sensing::OPCODES,
+        "sound" => sound::OPCODES,
+        "note" => return true,
+        "argument" => return op.starts_with("reporter_"),
+        _ => return false,
+    };
+
+    opcodes.contains(&op)
+}
+
+/// Blocks that start a script, along with procedure definitions.
+pub fn is_hat(opcode: &str) -> bool {
+    opcode.contains("_when")
+        || opcode == "control_start_as_clone"
+        || opcode == "procedures_definition"
+}
+
+// Blocks without a lowering fail the compilation, unless it is partial: they
+// then become a stub that warns once it is run, and reports an empty value.
+fn report_unsupported(file: &ScratchFile, block: &ScratchBlock) {
+    if file.partial {
+        warn!("{} ({}) is not supported", block.opcode, block.id);
+    } else {
+        file.unsupported.borrow_mut().push(format!(
+            "{}: block {} ({})",
+            file.targets[block.target].name, block.id, block.opcode
+        ));
+    }
+}
+
+fn unsupported_stmt(file: &ScratchFile, block: &ScratchBlock) -> Stmt {
+    report_unsupported(file, block);
+
+    Stmt::Unsupported {
+        opcode: block.opcode.to_string(),
+        block_id: block.id.to_string(),
+    }
+}
+
+fn unsupported_expr(file: &ScratchFile, block: &ScratchBlock) -> Expr {
+    report_unsupported(file, block);
+
+    Expr::Unsupported {
+        opcode: block.opcode.to_string(),
+        block_id: block.id.to_string(),
+    }
+}
+
+// reporters of stubbed extensions give nothing useful, but still something
+// that works as a number, a string and a condition
+fn stub_expr(file: &ScratchFile, block: &ScratchBlock) -> Expr {
+    debug!("{} is stubbed", block.opcode);
+
+    match block.opcode.as_str() {
+        // the text is at least better left untranslated than replaced
+        "translate_getTranslate" => scratch_val_to_expr(file, &block.inputs["WORDS"].0, block),
+        _ => Expr::Val(Value::Number(0.0)),
     }
 }
 
-fn expr_from_block(file: &ScratchFile, block: ScratchBlock) -> Expr {
+fn expr_from_block(file: &ScratchFile, block: &ScratchBlock) -> Expr {
     let str_array: Vec<&str> = block.opcode.as_str().splitn(2, '_').collect();
 
+    if file.stubbed_extensions.contains(str_array[0]) {
+        return stub_expr(file, block);
+    }
+
     match str_array[0] {
-        "operator" => operator::expr_from_operator(file, block.clone(), str_array[1]),
-        "data" => data::expr_from_data(file, block.clone(), str_array[1]),
+        "operator" => operator::expr_from_operator(file, block, str_array[1]),
+        "data" => data::expr_from_data(file, block, str_array[1]),
+        "looks" => looks::expr_from_looks(file, block, str_array[1]),
+        "sensing" => sensing::expr_from_sensing(file, block, str_array[1]),
+        "motion" => motion::expr_from_motion(file, block, str_array[1]),
+        "pen" => pen::expr_from_pen(file, block, str_array[1]),
+        "sound" => sound::expr_from_sound(file, block, str_array[1]),
+        "music" => music::expr_from_music(file, block, str_array[1]),
+        // the note picker of the music extension has no category
+        "note" => Expr::Val(Value::String(match &block.fields["NOTE"][0] {
+            serde_json::Value::String(x) => x.to_string(),
+            x => x.to_string(),
+        })),
         "argument" => Expr::Param(
             block.fields["VALUE"].to_vec()[0]
                 .as_str()
                 .unwrap()
                 .to_string(),
         ),
-        _ => todo!("{}", block.opcode.as_str()),
+        _ => unsupported_expr(file, block),
     }
 }
 
-fn scratch_val_to_expr(file: &ScratchFile, val: ScratchValue, orig_block: &ScratchBlock) -> Expr {
-    let block = match val.1.clone() {
-        ScratchValueData::BlockCall(x) => Some(x),
-        _ => None,
-    };
-
+fn scratch_val_to_expr(file: &ScratchFile, val: &ScratchValue, orig_block: &ScratchBlock) -> Expr {
     debug!("{:#?}", val);
-    match val.0 {
-        ScratchTypes::Number => Expr::Val(scratch_val_data_to_val(&val.1)),
-        ScratchTypes::String => Expr::Val(scratch_val_data_to_val(&val.1)),
-        ScratchTypes::BlockCall => expr_from_block(
-            file,
-            file.targets[orig_block.target].blocks[&block.unwrap()].clone(),
-        ),
 
-        ScratchTypes::Variable => {
-            if let ScratchValueData::Variable(x) = val.1 {
-                Expr::Var(x)
-            } else {
-                unreachable!()
-            }
+    if let ScratchTypes::Unknown(_) = val.0 {
+        return unsupported_expr(file, orig_block);
+    }
+
+    match &val.1 {
+        ScratchValueData::Number(x) => Expr::Val(Value::Number(*x)),
+        ScratchValueData::String(x) => Expr::Val(Value::String(x.to_string())),
+        ScratchValueData::BlockCall(x) => {
+            expr_from_block(file, &file.targets[orig_block.target].blocks[x])
         }
+        ScratchValueData::Variable(x) => Expr::Var(VarRef::variable(file, orig_block, x)),
     }
 }
 
-fn scratch_block_to_statement(file: ScratchFile, block: ScratchBlock) -> Stmt {
-    let mut next_block: Option<ScratchBlock> = None;
-    if block.next.is_some() {
-        next_block = Some(
-            file.targets[block.target]
-                .blocks
-                .get(&block.next.clone().unwrap())
-                .unwrap()
-                .clone(),
-        );
+// empty boolean inputs aren't saved, and are false
+fn condition_of(file: &ScratchFile, block: &ScratchBlock, name: &str) -> Expr {
+    match block.inputs.get(name) {
+        Some(input) => scratch_val_to_expr(file, &input.0, block),
+        None => Expr::Val(Value::Number(0.0)),
     }
+}
+
+fn scratch_block_to_statement(file: &ScratchFile, block: &ScratchBlock) -> Stmt {
+    let next_block = block
+        .next
+        .as_ref()
+        .map(|next| &file.targets[block.target].blocks[next]);
 
     let str_array: Vec<&str> = block.opcode.as_str().splitn(2, '_').collect();
 
+    if file.stubbed_extensions.contains(str_array[0]) {
+        debug!("{} is stubbed", block.opcode);
+        return Stmt::Empty;
+    }
+
     match str_array[0] {
-        "event" => {
-            event::event_to_statement(&file, block.clone(), next_block, str_array[1].to_string())
+        "event" => event::event_to_statement(file, block, next_block, str_array[1].to_string()),
+
+        "data" => data::data_to_statement(file, block, str_array[1].to_string()),
+
+        "control" => {
+            control::control_to_statement(file, block, next_block, str_array[1].to_string())
         }
 
-        "data" => data::data_to_statement(&file, block.clone(), str_array[1].to_string()),
+        "procedures" => procedures::procedures_to_statement(file, block, str_array[1]),
 
-        "control" => control::control_to_statement(
-            &file,
-            block.clone(),
-            next_block,
-            str_array[1].to_string(),
-        ),
+        "looks" => looks::looks_to_statement(file, block, str_array[1]),
+
+        "sensing" => sensing::sensing_to_statement(file, block, str_array[1]),
+
+        "motion" => motion::motion_to_statement(file, block, str_array[1]),
+
+        "pen" => pen::pen_to_statement(file, block, str_array[1]),
 
-        "procedures" => procedures::procedures_to_statement(&file, block.clone(), str_array[1]),
+        "sound" => sound::sound_to_statement(file, block, str_array[1]),
 
-        _ => todo!("{}", block.opcode.as_str()),
+        "music" => music::music_to_statement(file, block, str_array[1]),
+
+        _ => unsupported_stmt(file, block),
     }
 }
 
-pub fn scratch_file_to_project(mut file: ScratchFile) -> Project {
-    let mut root_block: Option<ScratchBlock> = None;
+/// Lowers every script of the project, or lists the blocks that can't be.
+pub fn scratch_file_to_project(file: ScratchFile) -> Result<Project, String> {
+    let mut vars: Vec<VarRef> = vec![];
+    let mut lists: Vec<VarRef> = vec![];
+    let mut sprites: Vec<Sprite> = vec![];
+
+    for (index, target) in file.targets.iter().enumerate() {
+        let declared: Vec<VarRef> = target
+            .variables
+            .values()
+            .map(|x| VarRef::declared(target, index, &x.0))
+            .collect();
+        vars.extend(declared.iter().cloned());
+
+        for list in target.lists.values() {
+            lists.push(VarRef::declared(target, index, &list.0));
+        }
 
-    let mut vars: Vec<String> = vec![];
-    let mut lists: Vec<String> = vec![];
-    let mut procedures: Vec<Stmt> = vec![];
+        let mut scripts: Vec<(Origin, Stmt)> = vec![];
+        let mut procedures: Vec<(Origin, Stmt)> = vec![];
 
-    for (i, target) in file.targets.iter_mut().enumerate() {
-        for var in &target.variables {
-            vars.push(var.1 .0.to_string());
+        // sort top level blocks so that the generated code is stable between runs
+        let mut top_level: Vec<_> = target
+            .blocks
+            .iter()
+            .filter(|(_, block)| block.parent.is_none())
+            .collect();
+        top_level.sort_by_key(|(id, _)| id.to_string());
+
+        for (_, block) in top_level {
+            match block.opcode.as_str() {
+                "event_whenflagclicked"
+                | "event_whenkeypressed"
+                | "event_whenthisspriteclicked"
+                | "event_whenstageclicked" => {
+                    scripts.push((Origin::of(block), scratch_block_to_statement(&file, block)))
+                }
+
+                "procedures_definition" => {
+                    procedures.push((Origin::of(block), scratch_block_to_statement(&file, block)))
+                }
+
+                // the hats of stubbed extensions never fire
+                opcode
+                    if is_hat(opcode)
+                        && !file
+                            .stubbed_extensions
+                            .contains(opcode.split('_').next().unwrap_or_default()) =>
+                {
+                    // the script is dropped, its stub warns once the project starts
+                    let stub = (Origin::of(block), unsupported_stmt(&file, block));
+                    scripts.push((
+                        Origin::of(block),
+                        Stmt::WhenFlagClicked(BlockStmt { stmts: vec![stub] }),
+                    ))
+                }
+
+                // loose blocks without a hat never run
+                _ => (),
+            }
         }
 
-        for list in &target.lists {
-            lists.push(list.1 .0.to_string());
+        debug!("{} has {} scripts", target.name, scripts.len());
+
+        sprites.push(Sprite {
+            name: target.name.to_string(),
+            is_stage: target.is_stage,
+            costumes: target.costumes.clone(),
+            sounds: target.sounds.clone(),
+            current_costume: target.current_costume,
+            layer_order: target.layer_order,
+            volume: target.volume,
+            tempo: target.tempo,
+            visible: target.visible,
+            x: target.x,
+            y: target.y,
+            size: target.size,
+            direction: target.direction,
+            rotation_style: target.rotation_style.to_string(),
+            variables: declared,
+            scripts,
+            procedures,
+        });
+    }
+
+    let unsupported = file.unsupported.take();
+    if !unsupported.is_empty() {
+        return Err(unsupported.join(", "));
+    }
+
+    Ok(Project {
+        sprites,
+        variables: vars,
+        lists,
+        monitors: file.monitors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map};
+
+    use super::*;
+    use crate::codegen;
+    use crate::parser::parse_scratch_file;
+
+    const CATEGORIES: &[(&str, &[&str])] = &[
+        ("control", control::OPCODES),
+        ("data", data::OPCODES),
+        ("event", event::OPCODES),
+        ("looks", looks::OPCODES),
+        ("motion", motion::OPCODES),
+        ("music", music::OPCODES),
+        ("operator", operator::OPCODES),
+        ("pen", pen::OPCODES),
+        ("procedures", procedures::OPCODES),
+        ("sensing", sensing::OPCODES),
+        ("sound", sound::OPCODES),
+    ];
+
+    // every input and field some lowering reads, given to every block
+    const INPUTS: &[&str] = &[
+        "BACKDROP",
+        "BEATS",
+        "CHANGE",
+        "COLOR",
+        "COLOR_PARAM",
+        "COSTUME",
+        "DEGREES",
+        "DIRECTION",
+        "DISTANCETOMENU",
+        "DRUM",
+        "DURATION",
+        "DX",
+        "DY",
+        "FROM",
+        "HUE",
+        "INDEX",
+        "INSTRUMENT",
+        "ITEM",
+        "KEY_OPTION",
+        "LETTER",
+        "MESSAGE",
+        "NOTE",
+        "NUM",
+        "NUM1",
+        "NUM2",
+        "OBJECT",
+        "OPERAND1",
+        "OPERAND2",
+        "QUESTION",
+        "SECS",
+        "SHADE",
+        "SIZE",
+        "SOUND_MENU",
+        "STEPS",
+        "STRING",
+        "STRING1",
+        "STRING2",
+        "TEMPO",
+        "TIMES",
+        "TO",
+        "TOUCHINGOBJECTMENU",
+        "TOWARDS",
+        "VALUE",
+        "VOLUME",
+        "WORDS",
+        "X",
+        "Y",
+    ];
+    const FIELDS: &[&str] = &[
+        "BACKDROP",
+        "COSTUME",
+        "CURRENTMENU",
+        "DISTANCETOMENU",
+        "DRUM",
+        "EFFECT",
+        "FORWARD_BACKWARD",
+        "FRONT_BACK",
+        "INSTRUMENT",
+        "KEY_OPTION",
+        "LIST",
+        "NOTE",
+        "NUMBER_NAME",
+        "OBJECT",
+        "PROPERTY",
+        "SOUND_MENU",
+        "STYLE",
+        "TO",
+        "TOUCHINGOBJECTMENU",
+        "TOWARDS",
+        "VALUE",
+        "VARIABLE",
+        "colorParam",
+    ];
+
+    fn block(opcode: &str, parent: Option<&str>, next: Option<&str>) -> serde_json::Value {
+        let inputs: Map<_, _> = INPUTS
+            .iter()
+            .map(|x| (x.to_string(), json!([1, [10, "1"]])))
+            .collect();
+        let fields: Map<_, _> = FIELDS
+            .iter()
+            .map(|x| (x.to_string(), json!(["v", "v"])))
+            .collect();
+
+        json!({
+            "opcode": opcode,
+            "next": next,
+            "parent": parent,
+            "inputs": inputs,
+            "fields": fields,
+            "shadow": false,
+            "topLevel": parent.is_none(),
+        })
+    }
+
+    fn compile(blocks: serde_json::Value) -> String {
+        let target = json!({
+            "isStage": false,
+            "name": "Sprite1",
+            "variables": { "v": ["v", 0] },
+            "lists": { "v": ["v", []] },
+            "broadcasts": {},
+            "blocks": blocks,
+            "comments": {},
+            "currentCostume": 0,
+            "costumes": [],
+            "sounds": [],
+            "volume": 100,
+            "layerOrder": 1,
+        });
+        let project = json!({
+            "targets": [target],
+            "monitors": [],
+            "extensions": [],
+            "meta": { "semver": "3.0.0", "vm": "0.2.0", "agent": "test" },
+        });
+
+        let mut file = parse_scratch_file(project.to_string()).unwrap();
+        file.partial = true;
+
+        let mut code = vec![];
+        codegen::codegen_project(
+            &mut code,
+            &scratch_file_to_project(file).unwrap(),
+            &codegen::Options::default(),
+        )
+        .unwrap();
+        String::from_utf8(code).unwrap()
+    }
+
+    // a block under a hat, or else a reporter in a say block
+    fn compile_block(opcode: &str) -> String {
+        if opcode.starts_with("event_when") {
+            return compile(json!({
+                "x": block(opcode, None, Some("b")),
+                "b": block("looks_hide", Some("x"), None),
+            }));
         }
 
-        for block in &mut target.blocks {
-            block.1.target = i;
+        let code = compile(json!({
+            "h": block("event_whenflagclicked", None, Some("x")),
+            "x": block(opcode, Some("h"), None),
+        }));
+        if !code.contains(&format!("unsupported(sprite, \"{}\"", opcode)) {
+            return code;
+        }
 
-            log::info!("{}", i);
+        let mut say = block("looks_say", Some("h"), None);
+        say["inputs"]["MESSAGE"] = json!([3, "x", [10, ""]]);
+        compile(json!({
+            "h": block("event_whenflagclicked", None, Some("s")),
+            "s": say,
+            "x": block(opcode, Some("s"), None),
+        }))
+    }
 
-            if block.1.parent.is_none() && block.1.opcode == *"event_whenflagclicked" {
-                root_block = Some(block.1.clone());
+    #[test]
+    fn supported_opcodes_lower_and_generate_code() {
+        for (category, opcodes) in CATEGORIES {
+            // custom blocks need a prototype
+            if *category == "procedures" {
+                continue;
             }
 
-            if block.1.parent.is_none() && block.1.opcode == *"procedures_definition" {
-                procedures.push(scratch_block_to_statement(file, block.1.clone()));
+            for op in *opcodes {
+                let opcode = format!("{}_{}", category, op);
+                assert!(is_supported(&opcode));
+
+                let code = compile_block(&opcode);
+                assert!(
+                    !code.contains(&format!("unsupported(sprite, \"{}\"", opcode)),
+                    "{} is listed as supported but doesn't lower",
+                    opcode
+                );
             }
         }
     }
 
-    debug!("root block is {:#?}", root_block);
+    #[test]
+    fn custom_blocks_lower_and_generate_code() {
+        let mut definition = block("procedures_definition", None, Some("b"));
+        definition["inputs"] = json!({ "custom_block": [1, "p"] });
+        let mut prototype = block("procedures_prototype", Some("d"), None);
+        prototype["mutation"] = json!({
+            "proccode": "greet %s",
+            "argumentids": "[\"a\"]",
+            "argumentnames": "[\"who\"]",
+        });
+        let mut call = block("procedures_call", Some("h"), None);
+        call["inputs"] = json!({ "a": [1, [10, "you"]] });
+        call["mutation"] = json!({ "proccode": "greet %s", "argumentids": "[\"a\"]" });
+
+        let code = compile(json!({
+            "d": definition,
+            "p": prototype,
+            "b": block("looks_hide", Some("d"), None),
+            "h": block("event_whenflagclicked", None, Some("c")),
+            "c": call,
+        }));
+        assert!(!code.contains("unsupported("), "{}", code);
+    }
 
-    Project {
-        body: scratch_block_to_statement(file, root_block.unwrap()),
-        variables: vars,
-        lists,
-        procedures,
+    #[test]
+    fn unlisted_opcodes_are_unsupported() {
+        assert!(!is_supported("operator_round"));
+        let code = compile_block("operator_round");
+        assert!(code.contains("unsupported(sprite, \"operator_round\""));
     }
 }
@@ -0,0 +1,109 @@
+use crate::parser::ast::*;
+
+pub const OPCODES: &[&str] = &[
+    "movesteps",
+    "turnright",
+    "turnleft",
+    "pointindirection",
+    "pointtowards",
+    "gotoxy",
+    "goto",
+    "glidesecstoxy",
+    "glideto",
+    "changexby",
+    "setx",
+    "changeyby",
+    "sety",
+    "ifonedgebounce",
+    "setrotationstyle",
+    "goto_menu",
+    "glideto_menu",
+    "pointtowards_menu",
+    "xposition",
+    "yposition",
+    "direction",
+];
+
+pub fn motion_to_statement(file: &ScratchFile, block: &ScratchBlock, op: &str) -> Stmt {
+    match op {
+        "movesteps" => Stmt::MoveSteps {
+            steps: scratch_val_to_expr(file, &block.inputs["STEPS"].0, block),
+        },
+
+        "turnright" | "turnleft" => Stmt::Turn {
+            right: op == "turnright",
+            degrees: scratch_val_to_expr(file, &block.inputs["DEGREES"].0, block),
+        },
+
+        "pointindirection" => Stmt::PointInDirection {
+            direction: scratch_val_to_expr(file, &block.inputs["DIRECTION"].0, block),
+        },
+
+        "pointtowards" => Stmt::PointTowards {
+            object: scratch_val_to_expr(file, &block.inputs["TOWARDS"].0, block),
+        },
+
+        "gotoxy" => Stmt::GoToXY {
+            x: scratch_val_to_expr(file, &block.inputs["X"].0, block),
+            y: scratch_val_to_expr(file, &block.inputs["Y"].0, block),
+        },
+
+        "goto" => Stmt::GoTo {
+            object: scratch_val_to_expr(file, &block.inputs["TO"].0, block),
+        },
+
+        "glidesecstoxy" => Stmt::GlideToXY {
+            secs: scratch_val_to_expr(file, &block.inputs["SECS"].0, block),
+            x: scratch_val_to_expr(file, &block.inputs["X"].0, block),
+            y: scratch_val_to_expr(file, &block.inputs["Y"].0, block),
+        },
+
+        "glideto" => Stmt::GlideTo {
+            secs: scratch_val_to_expr(file, &block.inputs["SECS"].0, block),
+            object: scratch_val_to_expr(file, &block.inputs["TO"].0, block),
+        },
+
+        "changexby" => Stmt::ChangeX {
+            inc: scratch_val_to_expr(file, &block.inputs["DX"].0, block),
+        },
+
+        "setx" => Stmt::SetX {
+            x: scratch_val_to_expr(file, &block.inputs["X"].0, block),
+        },
+
+        "changeyby" => Stmt::ChangeY {
+            inc: scratch_val_to_expr(file, &block.inputs["DY"].0, block),
+        },
+
+        "sety" => Stmt::SetY {
+            y: scratch_val_to_expr(file, &block.inputs["Y"].0, block),
+        },
+
+        "ifonedgebounce" => Stmt::IfOnEdgeBounce,
+
+        "setrotationstyle" => Stmt::SetRotationStyle {
+            style: block.fields["STYLE"][0].as_str().unwrap().to_string(),
+        },
+
+        _ => unsupported_stmt(file, block),
+    }
+}
+
+pub fn expr_from_motion(file: &ScratchFile, block: &ScratchBlock, op: &str) -> Expr {
+    match op {
+        // menus
+        "goto_menu" | "glideto_menu" => Expr::Val(Value::String(
+            block.fields["TO"][0].as_str().unwrap().to_string(),
+        )),
+
+        "pointtowards_menu" => Expr::Val(Value::String(
+            block.fields["TOWARDS"][0].as_str().unwrap().to_string(),
+        )),
+
+        "xposition" => Expr::XPosition,
+        "yposition" => Expr::YPosition,
+        "direction" => Expr::Direction,
+
+        _ => unsupported_expr(file, block),
+    }
+}
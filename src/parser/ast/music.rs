@@ -0,0 +1,59 @@
+use crate::parser::ast::*;
+
+pub const OPCODES: &[&str] = &[
+    "playNoteForBeats",
+    "playDrumForBeats",
+    "restForBeats",
+    "setInstrument",
+    "setTempo",
+    "changeTempo",
+    "menu_DRUM",
+    "menu_INSTRUMENT",
+    "getTempo",
+];
+
+pub fn music_to_statement(file: &ScratchFile, block: &ScratchBlock, op: &str) -> Stmt {
+    match op {
+        "playNoteForBeats" => Stmt::PlayNote {
+            note: scratch_val_to_expr(file, &block.inputs["NOTE"].0, block),
+            beats: scratch_val_to_expr(file, &block.inputs["BEATS"].0, block),
+        },
+
+        "playDrumForBeats" => Stmt::PlayDrum {
+            drum: scratch_val_to_expr(file, &block.inputs["DRUM"].0, block),
+            beats: scratch_val_to_expr(file, &block.inputs["BEATS"].0, block),
+        },
+
+        "restForBeats" => Stmt::Rest {
+            beats: scratch_val_to_expr(file, &block.inputs["BEATS"].0, block),
+        },
+
+        "setInstrument" => Stmt::SetInstrument {
+            instrument: scratch_val_to_expr(file, &block.inputs["INSTRUMENT"].0, block),
+        },
+
+        "setTempo" | "changeTempo" => Stmt::SetTempo {
+            tempo: scratch_val_to_expr(file, &block.inputs["TEMPO"].0, block),
+            change: op == "changeTempo",
+        },
+
+        _ => unsupported_stmt(file, block),
+    }
+}
+
+pub fn expr_from_music(file: &ScratchFile, block: &ScratchBlock, op: &str) -> Expr {
+    match op {
+        // menus
+        "menu_DRUM" => Expr::Val(Value::String(
+            block.fields["DRUM"][0].as_str().unwrap().to_string(),
+        )),
+
+        "menu_INSTRUMENT" => Expr::Val(Value::String(
+            block.fields["INSTRUMENT"][0].as_str().unwrap().to_string(),
+        )),
+
+        "getTempo" => Expr::Tempo,
+
+        _ => unsupported_expr(file, block),
+    }
+}
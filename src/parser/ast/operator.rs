@@ -1,80 +1,83 @@
 use crate::parser::ast::*;
 
-pub fn expr_from_operator(file: &ScratchFile, block: ScratchBlock, operator: &str) -> Expr {
+pub const OPCODES: &[&str] = &[
+    "add",
+    "subtract",
+    "multiply",
+    "divide",
+    "and",
+    "random",
+    "mod",
+    "join",
+    "gt",
+    "lt",
+    "equals",
+    "or",
+    "not",
+    "length",
+    "letter_of",
+];
+
+pub fn expr_from_operator(file: &ScratchFile, block: &ScratchBlock, operator: &str) -> Expr {
     debug!("{:#?} {}", block, operator);
-    match operator {
-        "add" | "subtract" | "multiply" | "divide" | "and" | "random" | "mod" => Expr::BinOp {
-            lhs: Box::new(scratch_val_to_expr(
-                file,
-                block.inputs["NUM1"].1.clone(),
-                &block,
-            )),
-            rhs: Box::new(scratch_val_to_expr(
-                file,
-                block.inputs["NUM2"].1.clone(),
-                &block,
-            )),
-            op: OpType::from_str(operator),
+    let Some(op) = OpType::from_str(operator) else {
+        return unsupported_expr(file, block);
+    };
+
+    match op {
+        OpType::Add | OpType::Subtract | OpType::Multiply | OpType::Divide | OpType::Modulo => {
+            Expr::BinOp {
+                lhs: Box::new(scratch_val_to_expr(file, &block.inputs["NUM1"].0, block)),
+                rhs: Box::new(scratch_val_to_expr(file, &block.inputs["NUM2"].0, block)),
+                op,
+            }
+        }
+
+        OpType::Random => Expr::BinOp {
+            lhs: Box::new(scratch_val_to_expr(file, &block.inputs["FROM"].0, block)),
+            rhs: Box::new(scratch_val_to_expr(file, &block.inputs["TO"].0, block)),
+            op,
         },
 
-        "join" => Expr::BinOp {
-            lhs: Box::new(scratch_val_to_expr(
-                file,
-                block.inputs["STRING1"].1.clone(),
-                &block,
-            )),
-            rhs: Box::new(scratch_val_to_expr(
-                file,
-                block.inputs["STRING2"].1.clone(),
-                &block,
-            )),
-            op: OpType::from_str(operator),
+        OpType::Join => Expr::BinOp {
+            lhs: Box::new(scratch_val_to_expr(file, &block.inputs["STRING1"].0, block)),
+            rhs: Box::new(scratch_val_to_expr(file, &block.inputs["STRING2"].0, block)),
+            op,
         },
 
-        "gt" | "lt" | "equals" | "or" => Expr::BinOp {
+        OpType::GreaterThan | OpType::LessThan | OpType::Equals => Expr::BinOp {
             lhs: Box::new(scratch_val_to_expr(
                 file,
-                block.inputs["OPERAND1"].1.clone(),
-                &block,
+                &block.inputs["OPERAND1"].0,
+                block,
             )),
             rhs: Box::new(scratch_val_to_expr(
                 file,
-                block.inputs["OPERAND2"].1.clone(),
-                &block,
+                &block.inputs["OPERAND2"].0,
+                block,
             )),
-            op: OpType::from_str(operator),
+            op,
         },
 
-        "not" => Expr::SingleOp {
-            expr: Box::new(scratch_val_to_expr(
-                file,
-                block.inputs["OPERAND"].1.clone(),
-                &block,
-            )),
-            op: OpType::from_str(operator),
+        OpType::And | OpType::Or => Expr::BinOp {
+            lhs: Box::new(condition_of(file, block, "OPERAND1")),
+            rhs: Box::new(condition_of(file, block, "OPERAND2")),
+            op,
         },
 
-        "length" => Expr::SingleOp {
-            op: OpType::from_str(operator),
-            expr: {
-                let val = block.inputs.iter().collect::<Vec<_>>()[0].1 .1.clone();
-                Box::new(scratch_val_to_expr(file, val, &block))
-            },
+        OpType::Not => Expr::SingleOp {
+            expr: Box::new(condition_of(file, block, "OPERAND")),
+            op,
         },
 
-        "letter_of" => Expr::LetterOf {
-            val: Box::new(scratch_val_to_expr(
-                file,
-                block.inputs["STRING"].1.clone(),
-                &block,
-            )),
-            index: Box::new(scratch_val_to_expr(
-                file,
-                block.inputs["LETTER"].1.clone(),
-                &block,
-            )),
+        OpType::Length => Expr::SingleOp {
+            op,
+            expr: Box::new(scratch_val_to_expr(file, &block.inputs["STRING"].0, block)),
         },
 
-        _ => todo!("{}", operator),
+        OpType::LetterOf => Expr::LetterOf {
+            val: Box::new(scratch_val_to_expr(file, &block.inputs["STRING"].0, block)),
+            index: Box::new(scratch_val_to_expr(file, &block.inputs["LETTER"].0, block)),
+        },
     }
 }
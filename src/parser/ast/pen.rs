@@ -0,0 +1,66 @@
+use crate::parser::ast::*;
+
+pub const OPCODES: &[&str] = &[
+    "clear",
+    "stamp",
+    "penDown",
+    "penUp",
+    "setPenColorToColor",
+    "setPenColorParamTo",
+    "changePenColorParamBy",
+    "setPenSizeTo",
+    "changePenSizeBy",
+    "setPenHueToNumber",
+    "changePenHueBy",
+    "setPenShadeToNumber",
+    "changePenShadeBy",
+    "menu_colorParam",
+];
+
+pub fn pen_to_statement(file: &ScratchFile, block: &ScratchBlock, op: &str) -> Stmt {
+    match op {
+        "clear" => Stmt::PenClear,
+        "stamp" => Stmt::PenStamp,
+        "penDown" => Stmt::PenDown,
+        "penUp" => Stmt::PenUp,
+
+        "setPenColorToColor" => Stmt::SetPenColor {
+            color: scratch_val_to_expr(file, &block.inputs["COLOR"].0, block),
+        },
+
+        "setPenColorParamTo" | "changePenColorParamBy" => Stmt::SetPenColorParam {
+            param: scratch_val_to_expr(file, &block.inputs["COLOR_PARAM"].0, block),
+            val: scratch_val_to_expr(file, &block.inputs["VALUE"].0, block),
+            change: op == "changePenColorParamBy",
+        },
+
+        "setPenSizeTo" | "changePenSizeBy" => Stmt::SetPenSize {
+            size: scratch_val_to_expr(file, &block.inputs["SIZE"].0, block),
+            change: op == "changePenSizeBy",
+        },
+
+        // Scratch 2 blocks, still found in converted projects
+        "setPenHueToNumber" | "changePenHueBy" => Stmt::SetPenHue {
+            hue: scratch_val_to_expr(file, &block.inputs["HUE"].0, block),
+            change: op == "changePenHueBy",
+        },
+
+        "setPenShadeToNumber" | "changePenShadeBy" => Stmt::SetPenShade {
+            shade: scratch_val_to_expr(file, &block.inputs["SHADE"].0, block),
+            change: op == "changePenShadeBy",
+        },
+
+        _ => unsupported_stmt(file, block),
+    }
+}
+
+pub fn expr_from_pen(file: &ScratchFile, block: &ScratchBlock, op: &str) -> Expr {
+    match op {
+        // menus
+        "menu_colorParam" => Expr::Val(Value::String(
+            block.fields["colorParam"][0].as_str().unwrap().to_string(),
+        )),
+
+        _ => unsupported_expr(file, block),
+    }
+}
@@ -1,13 +1,25 @@
 use crate::parser::*;
 
-use super::{block_chain_to_vec, scratch_val_to_expr};
+use super::{block_chain_to_vec, scratch_val_to_expr, unsupported_stmt};
 
-pub fn procedures_to_statement(file: &ScratchFile, block: ScratchBlock, op: &str) -> Stmt {
+// prototypes are lowered along with their definition
+pub const OPCODES: &[&str] = &["definition", "prototype", "call"];
+
+// custom blocks are local to their sprite, so two sprites may define the same proccode
+fn procedure_name(file: &ScratchFile, block: &ScratchBlock, proto_block: &ScratchBlock) -> String {
+    format!(
+        "{} {}",
+        file.targets[block.target].name,
+        proto_block.mutation["proccode"].as_str().unwrap()
+    )
+}
+
+pub fn procedures_to_statement(file: &ScratchFile, block: &ScratchBlock, op: &str) -> Stmt {
     match op {
         "definition" => Stmt::ProcedureDefinition {
             prototype: {
-                let proto_block_name = if block.inputs.get("custom_block").is_some() {
-                    if let ScratchValueData::BlockCall(x) = &block.inputs["custom_block"].1 .1 {
+                let proto_block_name = if block.inputs.contains_key("custom_block") {
+                    if let ScratchValueData::BlockCall(x) = &block.inputs["custom_block"].0 .1 {
                         Some(x.to_string())
                     } else {
                         None
@@ -16,50 +28,28 @@ pub fn procedures_to_statement(file: &ScratchFile, block: ScratchBlock, op: &str
                     None
                 };
 
-                let proto_block =
-                    file.targets[block.target].blocks[&proto_block_name.unwrap()].clone();
+                let proto_block = &file.targets[block.target].blocks[&proto_block_name.unwrap()];
 
                 ProcedurePrototype {
-                    params: {
-                        let mut ret: HashMap<String, String> = Default::default();
-                        let ids_arr: Vec<String> = serde_json::from_str(
-                            proto_block.mutation["argumentids"].as_str().unwrap(),
-                        )
-                        .unwrap();
-                        let names_arr: Vec<String> = serde_json::from_str(
-                            proto_block.mutation["argumentnames"].as_str().unwrap(),
-                        )
-                        .unwrap();
-                        for x in 0..ids_arr.len() {
-                            ret.insert(ids_arr[x].to_string(), names_arr[x].to_string());
-                        }
-                        ret
-                    },
                     param_order: serde_json::from_str(
                         proto_block.mutation["argumentnames"].as_str().unwrap(),
                     )
                     .unwrap(),
-                    name: proto_block.mutation["proccode"]
-                        .as_str()
-                        .unwrap()
-                        .replace(' ', "_")
-                        .to_string(),
+                    name: procedure_name(file, block, proto_block),
                 }
             },
             body: BlockStmt {
-                stmts: block_chain_to_vec(
-                    file,
-                    file.targets[block.target].blocks[&block.next.unwrap()].clone(),
-                ),
+                stmts: match &block.next {
+                    Some(next) => {
+                        block_chain_to_vec(file, &file.targets[block.target].blocks[next])
+                    }
+                    None => vec![],
+                },
             },
         },
 
         "call" => Stmt::ProcedureCall {
-            proc: block.mutation["proccode"]
-                .as_str()
-                .unwrap()
-                .replace(' ', "_")
-                .to_string(),
+            proc: procedure_name(file, block, block),
             params: {
                 let ids_arr: Vec<String> =
                     serde_json::from_str(block.mutation["argumentids"].as_str().unwrap()).unwrap();
@@ -67,13 +57,13 @@ pub fn procedures_to_statement(file: &ScratchFile, block: ScratchBlock, op: &str
                 let mut params: Vec<Expr> = vec![];
 
                 for x in ids_arr.iter() {
-                    params.push(scratch_val_to_expr(file, block.inputs[x].clone().1, &block));
+                    params.push(scratch_val_to_expr(file, &block.inputs[x].0, block));
                 }
 
                 params
             },
         },
 
-        _ => todo!("procedures_{}", op),
+        _ => unsupported_stmt(file, block),
     }
 }
@@ -0,0 +1,105 @@
+use crate::parser::ast::*;
+
+pub const OPCODES: &[&str] = &[
+    "askandwait",
+    "resettimer",
+    "answer",
+    "timer",
+    "current",
+    "dayssince2000",
+    "username",
+    "keypressed",
+    "mousex",
+    "mousey",
+    "mousedown",
+    "of",
+    "distanceto",
+    "touchingobject",
+    "touchingcolor",
+    "keyoptions",
+    "of_object_menu",
+    "distancetomenu",
+    "touchingobjectmenu",
+];
+
+pub fn sensing_to_statement(file: &ScratchFile, block: &ScratchBlock, op: &str) -> Stmt {
+    match op {
+        "askandwait" => Stmt::AskAndWait {
+            question: scratch_val_to_expr(file, &block.inputs["QUESTION"].0, block),
+        },
+
+        "resettimer" => Stmt::ResetTimer,
+
+        _ => unsupported_stmt(file, block),
+    }
+}
+
+pub fn expr_from_sensing(file: &ScratchFile, block: &ScratchBlock, op: &str) -> Expr {
+    match op {
+        "answer" => Expr::Answer,
+        "timer" => Expr::Timer,
+
+        "current" => Expr::Current(block.fields["CURRENTMENU"][0].as_str().unwrap().to_string()),
+
+        "dayssince2000" => Expr::DaysSince2000,
+        "username" => Expr::Username,
+
+        "keypressed" => Expr::KeyPressed(Box::new(scratch_val_to_expr(
+            file,
+            &block.inputs["KEY_OPTION"].0,
+            block,
+        ))),
+
+        "mousex" => Expr::MouseX,
+        "mousey" => Expr::MouseY,
+        "mousedown" => Expr::MouseDown,
+
+        "of" => Expr::Of {
+            object: Box::new(scratch_val_to_expr(file, &block.inputs["OBJECT"].0, block)),
+            property: block.fields["PROPERTY"][0].as_str().unwrap().to_string(),
+        },
+
+        "distanceto" => Expr::DistanceTo(Box::new(scratch_val_to_expr(
+            file,
+            &block.inputs["DISTANCETOMENU"].0,
+            block,
+        ))),
+
+        "touchingobject" => Expr::TouchingObject(Box::new(scratch_val_to_expr(
+            file,
+            &block.inputs["TOUCHINGOBJECTMENU"].0,
+            block,
+        ))),
+
+        "touchingcolor" => Expr::TouchingColor(Box::new(scratch_val_to_expr(
+            file,
+            &block.inputs["COLOR"].0,
+            block,
+        ))),
+
+        // menus
+        "keyoptions" => Expr::Val(Value::String(
+            block.fields["KEY_OPTION"][0].as_str().unwrap().to_string(),
+        )),
+
+        "of_object_menu" => Expr::Val(Value::String(
+            block.fields["OBJECT"][0].as_str().unwrap().to_string(),
+        )),
+
+        "distancetomenu" => Expr::Val(Value::String(
+            block.fields["DISTANCETOMENU"][0]
+                .as_str()
+                .unwrap()
+                .to_string(),
+        )),
+
+        "touchingobjectmenu" => Expr::Val(Value::String(
+            block.fields["TOUCHINGOBJECTMENU"][0]
+                .as_str()
+                .unwrap()
+                .to_string(),
+        )),
+
+        _ => unsupported_expr(file, block),
+    }
+}
@@ -0,0 +1,53 @@
+use crate::parser::ast::*;
+
+pub const OPCODES: &[&str] = &[
+    "play",
+    "playuntildone",
+    "stopallsounds",
+    "seteffectto",
+    "changeeffectby",
+    "cleareffects",
+    "setvolumeto",
+    "changevolumeby",
+    "sounds_menu",
+    "volume",
+];
+
+pub fn sound_to_statement(file: &ScratchFile, block: &ScratchBlock, op: &str) -> Stmt {
+    match op {
+        "play" | "playuntildone" => Stmt::PlaySound {
+            sound: scratch_val_to_expr(file, &block.inputs["SOUND_MENU"].0, block),
+            until_done: op == "playuntildone",
+        },
+
+        "stopallsounds" => Stmt::StopAllSounds,
+
+        "seteffectto" | "changeeffectby" => Stmt::SetSoundEffect {
+            effect: block.fields["EFFECT"][0].as_str().unwrap().to_string(),
+            val: scratch_val_to_expr(file, &block.inputs["VALUE"].0, block),
+            change: op == "changeeffectby",
+        },
+
+        "cleareffects" => Stmt::ClearSoundEffects,
+
+        "setvolumeto" | "changevolumeby" => Stmt::SetVolume {
+            volume: scratch_val_to_expr(file, &block.inputs["VOLUME"].0, block),
+            change: op == "changevolumeby",
+        },
+
+        _ => unsupported_stmt(file, block),
+    }
+}
+
+pub fn expr_from_sound(file: &ScratchFile, block: &ScratchBlock, op: &str) -> Expr {
+    match op {
+        // menus
+        "sounds_menu" => Expr::Val(Value::String(
+            block.fields["SOUND_MENU"][0].as_str().unwrap().to_string(),
+        )),
+
+        "volume" => Expr::Volume,
+
+        _ => unsupported_expr(file, block),
+    }
+}
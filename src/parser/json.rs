@@ -28,19 +28,28 @@ pub struct JsonScratchTarget {
     pub name: String,
     pub variables: HashMap<String, Vec<serde_json::Value>>,
     pub lists: HashMap<String, Vec<serde_json::Value>>,
-    pub broadcasts: HashMap<String, Vec<serde_json::Value>>,
+    pub broadcasts: HashMap<String, serde_json::Value>,
     pub blocks: HashMap<String, JsonScratchBlock>,
     pub comments: HashMap<String, Vec<serde_json::Value>>,
 
     #[serde(alias = "currentCostume")]
     pub current_costume: i32,
     pub costumes: Vec<HashMap<String, serde_json::Value>>,
-    pub sounds: Vec<serde_json::Value>,
-    pub volume: i32,
+    pub sounds: Vec<HashMap<String, serde_json::Value>>,
+    pub volume: f64,
 
     #[serde(alias = "layerOrder")]
     pub layer_order: i32,
-    pub tempo: Option<i32>,
+    pub tempo: Option<f64>,
+
+    pub visible: Option<bool>,
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub size: Option<f64>,
+    pub direction: Option<f64>,
+
+    #[serde(alias = "rotationStyle")]
+    pub rotation_style: Option<String>,
 
     #[serde(alias = "videoTransparency")]
     pub video_transparency: Option<i32>,
@@ -1,33 +1,21 @@
-use colored::*;
-use log::debug;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
 
 pub use self::ast::*;
 pub use self::json::*;
 mod ast;
 mod json;
 
-#[derive(Debug, Clone)]
-pub struct ScratchMetadata {
-    pub user_agent: String,
-    pub semantic_version: String,
-    pub vm_version: String,
-}
-
-#[derive(Debug, Clone)]
-pub enum ScratchInitializer {
-    List(Vec<()>),
-    Int(i64),
-    String(String),
-}
-
 #[derive(Debug, Clone)]
 //  "variables": { "`jEk@4|i[#Fk?(8x)AV.-my variable": ["my variable", 0] },
-pub struct ScratchVariableDecl(String, ScratchInitializer);
+// the initial value isn't kept
+pub struct ScratchVariableDecl(String);
 
 #[derive(Debug, Clone)]
 pub enum ScratchValueData {
-    Int(i64),
+    Number(f64),
     String(String),
     BlockCall(String),
     Variable(String),
@@ -35,20 +23,24 @@ pub enum ScratchValueData {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ScratchTypes {
-    String = 10,
-    Number = 4,
-    BlockCall = 3,
-    Variable = 12,
+    String,
+    Number,
+    BlockCall,
+    Variable,
+    // lists and anything newer, left to the lowering to report
+    Unknown(i64),
 }
 
 impl ScratchTypes {
     fn from_i64(value: i64) -> ScratchTypes {
         match value {
-            10 => ScratchTypes::String,
-            4 | 6 => ScratchTypes::Number,
-            2 | 3 | 1 => ScratchTypes::BlockCall,
+            // colors are written as "#rrggbb", and broadcasts are picked by name
+            9..=11 => ScratchTypes::String,
+            // positive, whole, integer and angle inputs are plain numbers
+            4..=8 => ScratchTypes::Number,
+            1..=3 => ScratchTypes::BlockCall,
             12 => ScratchTypes::Variable,
-            _ => todo!("ScratchType {}", value),
+            _ => ScratchTypes::Unknown(value),
         }
     }
 }
@@ -58,11 +50,12 @@ impl ScratchTypes {
 pub struct ScratchValue(ScratchTypes, ScratchValueData);
 
 #[derive(Debug, Clone)]
-// [1, [10, "1"]]
-pub struct ScratchInput(i64, ScratchValue);
+// [1, [10, "1"]], whether it has a shadow isn't kept
+pub struct ScratchInput(ScratchValue);
 
 #[derive(Debug, Clone)]
 pub struct ScratchBlock {
+    pub id: String,
     pub opcode: String,
     pub next: Option<String>,
     pub parent: Option<String>,
@@ -72,6 +65,52 @@ pub struct ScratchBlock {
     pub target: usize,
 }
 
+#[derive(Debug, Clone)]
+pub struct ScratchCostume {
+    pub name: String,
+    pub md5ext: String,
+    pub data_format: String,
+    pub rotation_center_x: f64,
+    pub rotation_center_y: f64,
+    pub bitmap_resolution: i64,
+    // only known once the asset is read, zero until then
+    pub width: f64,
+    pub height: f64,
+    pub pixels: Option<CostumePixels>,
+}
+
+/// Decoded RGBA rows of a costume, shared by every clone of the file.
+#[derive(Clone)]
+pub struct CostumePixels(pub Rc<Vec<u8>>);
+
+impl fmt::Debug for CostumePixels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<{} bytes>", self.0.len())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScratchSound {
+    pub name: String,
+    pub md5ext: String,
+    pub data_format: String,
+    // as saved by the editor, replaced by those of the decoded samples
+    pub rate: u32,
+    pub sample_count: u64,
+    pub samples: Option<SoundSamples>,
+}
+
+/// Decoded mono samples of a sound as 16 bit little endian, the way the
+/// runtime reads them, shared by every clone of the file.
+#[derive(Clone)]
+pub struct SoundSamples(pub Rc<Vec<u8>>);
+
+impl fmt::Debug for SoundSamples {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<{} samples>", self.0.len() / 2)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ScratchTarget {
     pub is_stage: bool,
@@ -79,118 +118,210 @@ pub struct ScratchTarget {
     pub variables: HashMap<String, ScratchVariableDecl>,
     pub lists: HashMap<String, ScratchVariableDecl>,
     pub blocks: HashMap<String, ScratchBlock>,
+    pub costumes: Vec<ScratchCostume>,
+    pub sounds: Vec<ScratchSound>,
+    pub current_costume: i32,
+    pub layer_order: i32,
+    pub volume: f64,
+    // only used on the stage
+    pub tempo: f64,
+    pub visible: bool,
+    pub x: f64,
+    pub y: f64,
+    pub size: f64,
+    pub direction: f64,
+    pub rotation_style: String,
 }
 
 #[derive(Debug, Clone)]
-pub struct ScratchFile {
-    pub metadata: ScratchMetadata,
-    pub targets: Vec<ScratchTarget>,
+pub struct ScratchMonitor {
+    pub variable: String,
+    // only set for variables that belong to a sprite
+    pub sprite_name: Option<String>,
+    pub mode: String,
+    pub x: f64,
+    pub y: f64,
+    pub visible: bool,
 }
 
-pub fn show_info(file: &ScratchFile) {
-    println!("{}", "File metadata: ".white().bold());
-
-    let print_val =
-        |name: String, val: &String| println!("  {} : {}", name.white(), val.cyan().bold());
-
-    print_val("User Agent".to_string(), &file.metadata.user_agent);
-    print_val(
-        "Semantic version".to_string(),
-        &file.metadata.semantic_version,
-    );
-    print_val("Virtual machine".to_string(), &file.metadata.vm_version);
+#[derive(Debug, Clone)]
+pub struct ScratchFile {
+    pub targets: Vec<ScratchTarget>,
+    pub monitors: Vec<ScratchMonitor>,
+    pub extensions: Vec<String>,
+    // extensions whose blocks are lowered to no-ops and default values
+    pub stubbed_extensions: HashSet<String>,
+    // lower blocks that aren't supported to stubs warning when they are run,
+    // instead of giving up
+    pub partial: bool,
+    // blocks found unsupported while lowering a compilation that isn't partial
+    pub unsupported: RefCell<Vec<String>>,
 }
 
 fn scratch_variable_decl_of_json(vec: Vec<serde_json::Value>) -> ScratchVariableDecl {
-    let var_type = match (vec[1].is_array(), vec[1].is_i64(), vec[1].is_string()) {
-        (true, false, false) => ScratchInitializer::List(vec![]),
-        (false, true, false) => ScratchInitializer::Int(vec[1].as_i64().unwrap()),
-        (false, false, true) => ScratchInitializer::String(vec[1].as_str().unwrap().to_string()),
-        (_, _, _) => unreachable!(),
-    };
-
-    ScratchVariableDecl(vec[0].as_str().unwrap().to_string(), var_type)
+    ScratchVariableDecl(vec[0].as_str().unwrap().to_string())
 }
 
-fn scratch_value_of_array(array: Vec<serde_json::Value>) -> ScratchValue {
-    let mut val_type: ScratchTypes = ScratchTypes::from_i64(array[0].as_i64().unwrap());
+fn scratch_value_of_array(array: Vec<serde_json::Value>) -> Result<ScratchValue, String> {
+    let mut val_type: ScratchTypes = match array.first().and_then(|x| x.as_i64()) {
+        Some(x) => ScratchTypes::from_i64(x),
+        None => return Err(format!("expected an input type in {:?}", array)),
+    };
 
-    let val = match &array[1] {
-        serde_json::Value::String(x) => x.to_string(),
-        serde_json::Value::Number(x) => x.to_string(),
-        _ => panic!("Expected number or string, not {:#?}", array[1]),
+    let val = match array.get(1) {
+        Some(serde_json::Value::String(x)) => x.to_string(),
+        Some(serde_json::Value::Number(x)) => x.to_string(),
+        _ => return Err(format!("expected a number or a string in {:?}", array)),
     };
 
     if val_type == ScratchTypes::String && val.parse::<i64>().is_ok() {
         val_type = ScratchTypes::Number;
     }
 
+    // number fields can be left empty or hold any text typed into them
+    let number = val.parse::<f64>().ok().filter(|x| x.is_finite());
+    if val_type == ScratchTypes::Number && number.is_none() {
+        val_type = ScratchTypes::String;
+    }
+
     let data: ScratchValueData = match val_type {
         ScratchTypes::String => ScratchValueData::String(val),
-        ScratchTypes::Number => ScratchValueData::Int(val.parse::<i64>().unwrap()),
+        ScratchTypes::Number => ScratchValueData::Number(number.unwrap()),
         ScratchTypes::BlockCall => ScratchValueData::BlockCall(val),
-        ScratchTypes::Variable => {
-            ScratchValueData::Variable(array[1].as_str().unwrap().to_string())
-        }
+        ScratchTypes::Variable => ScratchValueData::Variable(val),
+        ScratchTypes::Unknown(_) => ScratchValueData::String(val),
+    };
+
+    Ok(ScratchValue(val_type, data))
+}
+
+fn json_str(asset: &HashMap<String, serde_json::Value>, key: &str) -> String {
+    asset
+        .get(key)
+        .and_then(|x| x.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+// older projects omit md5ext, it is always <assetId>.<dataFormat>
+fn asset_md5ext(asset: &HashMap<String, serde_json::Value>) -> Result<String, String> {
+    let md5ext = match asset.get("md5ext").and_then(|x| x.as_str()) {
+        Some(x) => x.to_string(),
+        None => format!(
+            "{}.{}",
+            json_str(asset, "assetId"),
+            json_str(asset, "dataFormat")
+        ),
     };
 
-    ScratchValue(val_type, data)
+    // assets are read from and copied into directories by this name, anything
+    // but a plain file name could reach outside of them
+    let is_plain = md5ext.split_once('.').is_some_and(|(hash, ext)| {
+        !hash.is_empty()
+            && hash.chars().all(|c| c.is_ascii_hexdigit())
+            && !ext.is_empty()
+            && ext.chars().all(|c| c.is_ascii_alphanumeric())
+    });
+
+    if !is_plain {
+        return Err(format!("{} is not a <hash>.<ext> file name", md5ext));
+    }
+
+    Ok(md5ext)
 }
 
-fn scratch_block_of_json(block: &JsonScratchBlock) -> ScratchBlock {
+fn scratch_costume_of_json(
+    costume: &HashMap<String, serde_json::Value>,
+) -> Result<ScratchCostume, String> {
+    let get_str = |key: &str| json_str(costume, key);
+    let get_f64 = |key: &str| costume.get(key).and_then(|x| x.as_f64()).unwrap_or(0.0);
+
+    Ok(ScratchCostume {
+        name: get_str("name"),
+        md5ext: asset_md5ext(costume)
+            .map_err(|err| format!("costume {}: {}", get_str("name"), err))?,
+        data_format: get_str("dataFormat"),
+        rotation_center_x: get_f64("rotationCenterX"),
+        rotation_center_y: get_f64("rotationCenterY"),
+        bitmap_resolution: costume
+            .get("bitmapResolution")
+            .and_then(|x| x.as_i64())
+            .unwrap_or(1),
+        width: 0.0,
+        height: 0.0,
+        pixels: None,
+    })
+}
+
+fn scratch_sound_of_json(
+    sound: &HashMap<String, serde_json::Value>,
+) -> Result<ScratchSound, String> {
+    let get_u64 = |key: &str| sound.get(key).and_then(|x| x.as_u64()).unwrap_or(0);
+
+    Ok(ScratchSound {
+        name: json_str(sound, "name"),
+        md5ext: asset_md5ext(sound)
+            .map_err(|err| format!("sound {}: {}", json_str(sound, "name"), err))?,
+        data_format: json_str(sound, "dataFormat"),
+        rate: get_u64("rate") as u32,
+        sample_count: get_u64("sampleCount"),
+        samples: None,
+    })
+}
+
+fn scratch_block_of_json(
+    id: &str,
+    block: &JsonScratchBlock,
+    target: usize,
+) -> Result<ScratchBlock, String> {
     let mut inputs: HashMap<String, ScratchInput> = Default::default();
     let mut fields: HashMap<String, Vec<serde_json::Value>> = Default::default();
     let mut mutations: HashMap<String, serde_json::Value> = Default::default();
 
-    let next = if block.next.is_null() {
-        None
-    } else {
-        Some(block.next.as_str().unwrap().to_string())
-    };
-
-    let parent = if block.parent.is_null() {
-        None
-    } else {
-        Some(block.parent.as_str().unwrap().to_string())
-    };
+    let next = block.next.as_str().map(|x| x.to_string());
+    let parent = block.parent.as_str().map(|x| x.to_string());
 
     for field in &block.fields {
         fields.insert(field.0.to_string(), field.1.to_vec());
     }
 
-    if block.mutation.is_some() {
-        for mutation in block.mutation.as_ref().unwrap() {
+    if let Some(mutation) = &block.mutation {
+        for mutation in mutation {
             mutations.insert(mutation.0.to_string(), mutation.1.clone());
         }
     }
 
     for input in &block.inputs {
-        let array = input.1.as_array().unwrap().to_vec();
-        let val: ScratchValue = if array[1].is_string() {
-            scratch_value_of_array(array.clone())
-        } else if !array[1].is_null() {
-            scratch_value_of_array(array[1].as_array().unwrap().to_vec())
-        } else {
-            continue;
+        let array = match input.1.as_array() {
+            Some(x) if x.len() >= 2 => x.to_vec(),
+            _ => return Err(format!("input {} is {}", input.0, input.1)),
         };
+        let val: ScratchValue = match &array[1] {
+            serde_json::Value::Null => continue,
+            serde_json::Value::Array(x) => scratch_value_of_array(x.to_vec()),
+            _ => scratch_value_of_array(array.clone()),
+        }
+        .map_err(|err| format!("input {}: {}", input.0, err))?;
 
-        let _input = ScratchInput(array[0].as_i64().unwrap(), val);
-
-        inputs.insert(input.0.to_string(), _input);
+        inputs.insert(input.0.to_string(), ScratchInput(val));
     }
 
-    ScratchBlock {
+    Ok(ScratchBlock {
+        id: id.to_string(),
         opcode: block.opcode.to_string(),
         next,
         parent,
         inputs,
         fields,
         mutation: mutations,
-        target: 0,
-    }
+        target,
+    })
 }
 
-fn scratch_target_of_json(target: &JsonScratchTarget) -> ScratchTarget {
+fn scratch_target_of_json(
+    target: &JsonScratchTarget,
+    index: usize,
+) -> Result<ScratchTarget, String> {
     let mut variables: HashMap<String, ScratchVariableDecl> = Default::default();
     let mut lists: HashMap<String, ScratchVariableDecl> = Default::default();
     let mut blocks: HashMap<String, ScratchBlock> = Default::default();
@@ -217,31 +348,202 @@ fn scratch_target_of_json(target: &JsonScratchTarget) -> ScratchTarget {
             continue;
         }
 
-        blocks.insert(block.0.to_string(), scratch_block_of_json(block.1));
+        let parsed = scratch_block_of_json(block.0, block.1, index).map_err(|err| {
+            format!(
+                "{}: block {} ({}): {}",
+                target.name, block.0, block.1.opcode, err
+            )
+        })?;
+        blocks.insert(block.0.to_string(), parsed);
     }
 
-    ScratchTarget {
+    let costumes = target
+        .costumes
+        .iter()
+        .map(scratch_costume_of_json)
+        .collect::<Result<_, _>>()
+        .map_err(|err| format!("{}: {}", target.name, err))?;
+    let sounds = target
+        .sounds
+        .iter()
+        .map(scratch_sound_of_json)
+        .collect::<Result<_, _>>()
+        .map_err(|err| format!("{}: {}", target.name, err))?;
+
+    Ok(ScratchTarget {
         is_stage: target.is_stage,
         name: target.name.to_string(),
         variables,
         lists,
         blocks,
+        costumes,
+        sounds,
+        current_costume: target.current_costume,
+        layer_order: target.layer_order,
+        volume: target.volume,
+        tempo: target.tempo.unwrap_or(60.0),
+        visible: target.visible.unwrap_or(true),
+        x: target.x.unwrap_or(0.0),
+        y: target.y.unwrap_or(0.0),
+        size: target.size.unwrap_or(100.0),
+        direction: target.direction.unwrap_or(90.0),
+        rotation_style: target
+            .rotation_style
+            .clone()
+            .unwrap_or_else(|| "all around".to_string()),
+    })
+}
+
+// only variable monitors are kept, lists are never shown
+fn scratch_monitor_of_json(monitor: &HashMap<String, serde_json::Value>) -> Option<ScratchMonitor> {
+    if monitor.get("opcode")?.as_str()? != "data_variable" {
+        return None;
     }
+
+    let get_f64 = |name: &str| monitor.get(name).and_then(|x| x.as_f64()).unwrap_or(0.0);
+
+    Some(ScratchMonitor {
+        variable: monitor
+            .get("params")?
+            .get("VARIABLE")?
+            .as_str()?
+            .to_string(),
+        sprite_name: monitor
+            .get("spriteName")
+            .and_then(|x| x.as_str())
+            .map(|x| x.to_string()),
+        mode: monitor
+            .get("mode")
+            .and_then(|x| x.as_str())
+            .unwrap_or("default")
+            .to_string(),
+        x: get_f64("x"),
+        y: get_f64("y"),
+        visible: monitor
+            .get("visible")
+            .and_then(|x| x.as_bool())
+            .unwrap_or(false),
+    })
 }
 
-pub fn parse_scratch_file(contents: String) -> ScratchFile {
-    let json: JsonScratchFile = serde_json::from_str(&contents).unwrap();
-    let metadata = ScratchMetadata {
-        semantic_version: json.meta.semver,
-        user_agent: json.meta.agent,
-        vm_version: json.meta.vm,
-    };
+pub fn parse_scratch_file(contents: String) -> Result<ScratchFile, String> {
+    let json: JsonScratchFile = serde_json::from_str(&contents).map_err(|x| x.to_string())?;
 
     let mut targets: Vec<ScratchTarget> = vec![];
 
-    for val in json.targets.iter() {
-        targets.push(scratch_target_of_json(val));
+    for (i, val) in json.targets.iter().enumerate() {
+        targets.push(scratch_target_of_json(val, i)?);
     }
 
-    ScratchFile { metadata, targets }
+    let monitors = json
+        .monitors
+        .iter()
+        .filter_map(scratch_monitor_of_json)
+        .collect();
+
+    Ok(ScratchFile {
+        targets,
+        monitors,
+        extensions: json.extensions,
+        stubbed_extensions: HashSet::new(),
+        partial: false,
+        unsupported: RefCell::new(vec![]),
+    })
+}
+
+/// The costumes of every target that can be shown: the current one, those
+/// named in the menus of the blocks switching to them, or all of them once a
+/// block goes to the next one or picks one with a reporter.
+pub fn reachable_costumes(file: &ScratchFile) -> Vec<Vec<bool>> {
+    let mut reachable: Vec<Vec<bool>> = file
+        .targets
+        .iter()
+        .map(|target| {
+            (0..target.costumes.len())
+                .map(|i| i as i32 == target.current_costume)
+                .collect()
+        })
+        .collect();
+    let stage = file.targets.iter().position(|x| x.is_stage);
+
+    for (index, target) in file.targets.iter().enumerate() {
+        for block in target.blocks.values() {
+            let (switched, input, menu) = match block.opcode.as_str() {
+                "looks_switchcostumeto" => (Some(index), "COSTUME", "looks_costume"),
+                "looks_nextcostume" => (Some(index), "", ""),
+                "looks_switchbackdropto" | "looks_switchbackdroptoandwait" => {
+                    (stage, "BACKDROP", "looks_backdrops")
+                }
+                "looks_nextbackdrop" => (stage, "", ""),
+                _ => continue,
+            };
+            let Some(switched) = switched else {
+                continue;
+            };
+
+            // the name in the menu, unless a reporter covers it
+            let name = match block.inputs.get(input).map(|x| &x.0 .1) {
+                Some(ScratchValueData::BlockCall(id)) => target
+                    .blocks
+                    .get(id)
+                    .filter(|x| x.opcode == menu)
+                    .and_then(|x| x.fields.get(input)?.first()?.as_str()),
+                _ => None,
+            };
+
+            let costumes = &file.targets[switched].costumes;
+            match name.and_then(|name| costumes.iter().position(|x| x.name == name)) {
+                Some(i) => reachable[switched][i] = true,
+                // also "next costume", "random backdrop" and numbers
+                None => reachable[switched].fill(true),
+            }
+        }
+    }
+
+    reachable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn value(input: serde_json::Value) -> ScratchValue {
+        scratch_value_of_array(input.as_array().unwrap().clone()).unwrap()
+    }
+
+    #[test]
+    fn number_inputs_of_every_kind() {
+        for kind in 4..=8 {
+            let ScratchValue(val_type, data) = value(json!([kind, "3"]));
+            assert_eq!(val_type, ScratchTypes::Number, "type {}", kind);
+            assert!(
+                matches!(data, ScratchValueData::Number(x) if x == 3.0),
+                "type {}",
+                kind
+            );
+        }
+    }
+
+    #[test]
+    fn inputs_of_unknown_types() {
+        let ScratchValue(val_type, _) = value(json!([13, "my list", "id"]));
+        assert_eq!(val_type, ScratchTypes::Unknown(13));
+    }
+
+    #[test]
+    fn decimal_number_inputs() {
+        let ScratchValue(val_type, data) = value(json!([4, "0.25"]));
+        assert_eq!(val_type, ScratchTypes::Number);
+        assert!(matches!(data, ScratchValueData::Number(x) if x == 0.25));
+    }
+
+    #[test]
+    fn number_inputs_that_are_not_numbers() {
+        for text in ["", "abc", "Infinity"] {
+            let ScratchValue(val_type, data) = value(json!([4, text]));
+            assert_eq!(val_type, ScratchTypes::String);
+            assert!(matches!(data, ScratchValueData::String(x) if x == text));
+        }
+    }
 }
@@ -0,0 +1,73 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Line of the generated code that brings in the runtime. Quoted so that a
+/// runtime written next to the code is found first.
+pub const INCLUDE: &str = "#include \"runtime/scratchnative.hpp\"";
+
+// the headers of the runtime, built into the compiler so that the generated
+// code doesn't depend on this checkout
+const FILES: [(&str, &str); 13] = [
+    (
+        "scratchnative.hpp",
+        include_str!("../runtime/scratchnative.hpp"),
+    ),
+    ("options.hpp", include_str!("../runtime/options.hpp")),
+    ("scheduler.hpp", include_str!("../runtime/scheduler.hpp")),
+    ("target.hpp", include_str!("../runtime/target.hpp")),
+    ("canvas.hpp", include_str!("../runtime/canvas.hpp")),
+    ("keyboard.hpp", include_str!("../runtime/keyboard.hpp")),
+    ("audio.hpp", include_str!("../runtime/audio.hpp")),
+    ("music.hpp", include_str!("../runtime/music.hpp")),
+    ("pen.hpp", include_str!("../runtime/pen.hpp")),
+    ("renderer.hpp", include_str!("../runtime/renderer.hpp")),
+    ("terminal.hpp", include_str!("../runtime/terminal.hpp")),
+    ("runtime.hpp", include_str!("../runtime/runtime.hpp")),
+    ("input.hpp", include_str!("../runtime/input.hpp")),
+];
+
+/// Writes the runtime to a `runtime` directory in `dir`, where the include of
+/// generated code placed in `dir` finds it.
+pub fn emit(dir: &Path) -> Result<(), String> {
+    let runtime_dir = dir.join("runtime");
+    std::fs::create_dir_all(&runtime_dir).map_err(|x| x.to_string())?;
+
+    for (name, contents) in FILES {
+        std::fs::write(runtime_dir.join(name), contents).map_err(|x| format!("{}: {}", name, x))?;
+    }
+
+    Ok(())
+}
+
+/// The runtime as a single piece of code, to put in place of `INCLUDE` for a
+/// single file that builds anywhere.
+pub fn inline() -> String {
+    let mut out = String::new();
+    expand("scratchnative.hpp", &mut HashSet::new(), &mut out);
+
+    out
+}
+
+// Follows the includes between the headers, once each like `#pragma once`.
+fn expand(name: &str, included: &mut HashSet<String>, out: &mut String) {
+    if !included.insert(name.to_string()) {
+        return;
+    }
+
+    let (_, contents) = FILES.iter().find(|(x, _)| *x == name).unwrap();
+
+    for line in contents.lines() {
+        let header = line
+            .strip_prefix("#include \"")
+            .and_then(|x| x.strip_suffix('"'));
+
+        match header {
+            Some(header) => expand(header, included, out),
+            None if line == "#pragma once" => (),
+            None => {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use std::fs;
+
+use common::*;
+use serde_json::json;
+
+// the C++ of a project whose costumes are all 2x2 PNGs
+fn compile_with_costumes(project: &serde_json::Value) -> String {
+    let path = write_project("reachable", project);
+    for file in ["a.png", "b.png", "c.png", "d.png", "e.png", "f.png"] {
+        fs::write(path.with_file_name(file), png(2, 2)).unwrap();
+    }
+
+    let output = scratchnative(&["compile", path.to_str().unwrap()]);
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn only_costumes_that_can_be_shown_are_embedded() {
+    let mut blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![
+            (
+                "looks_switchcostumeto",
+                json!({ "COSTUME": [1, "m"] }),
+                json!({}),
+            ),
+            (
+                "looks_switchbackdropto",
+                json!({ "BACKDROP": [1, "n"] }),
+                json!({}),
+            ),
+        ],
+    );
+    blocks.insert(
+        "m".to_string(),
+        block(
+            "looks_costume",
+            Some("s0"),
+            json!({}),
+            json!({ "COSTUME": ["b", null] }),
+        ),
+    );
+    blocks.insert(
+        "n".to_string(),
+        block(
+            "looks_backdrops",
+            Some("s1"),
+            json!({}),
+            json!({ "BACKDROP": ["e", null] }),
+        ),
+    );
+    let mut project = project(blocks, &[]);
+    project["targets"][0]["costumes"] = costumes(&["d.png", "e.png", "f.png"]);
+    project["targets"][1]["costumes"] = costumes(&["a.png", "b.png", "c.png"]);
+
+    let code = compile_with_costumes(&project);
+    for costume in ["a", "b", "d", "e"] {
+        assert!(code.contains(&format!("unsigned char costume_{}_png[]", costume)));
+    }
+    for costume in ["c", "f"] {
+        assert!(!code.contains(&format!("unsigned char costume_{}_png[]", costume)));
+        assert!(code.contains(&format!("{{\"{}\", \"{}.png\", \"png\"", costume, costume)));
+    }
+
+    // with a next costume block, any of them may be shown
+    project["targets"][1]["blocks"]["s0"]["opcode"] = json!("looks_nextcostume");
+    let code = compile_with_costumes(&project);
+    assert!(code.contains("unsigned char costume_c_png[]"));
+    assert!(!code.contains("unsigned char costume_f_png[]"));
+}
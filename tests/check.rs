@@ -0,0 +1,101 @@
+mod common;
+
+use common::*;
+use serde_json::json;
+
+#[test]
+fn lists_opcodes_with_their_support() {
+    let mut blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![
+            (
+                "looks_say",
+                json!({ "MESSAGE": reporter("r", 10) }),
+                json!({}),
+            ),
+            (
+                "looks_changestretchby",
+                json!({ "CHANGE": num("1") }),
+                json!({}),
+            ),
+        ],
+    );
+    blocks.insert(
+        "r".to_string(),
+        block(
+            "operator_random",
+            Some("s0"),
+            json!({ "FROM": num("1"), "TO": num("10") }),
+            json!({}),
+        ),
+    );
+    let path = write_project("check", &project(blocks, &[]));
+    let output = scratchnative(&["check", path.to_str().unwrap()]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert_eq!(output.status.code(), Some(1));
+    let status = |opcode: &str| {
+        let line = stdout.lines().find(|x| x.contains(opcode)).unwrap();
+        line.split_whitespace().last().unwrap().to_string()
+    };
+    assert_eq!(status("operator_random"), "supported");
+    assert_eq!(status("looks_say"), "supported");
+    assert_eq!(status("looks_changestretchby"), "unsupported");
+}
+
+#[test]
+fn malformed_blocks_are_reported() {
+    let blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![(
+            "looks_say",
+            json!({ "MESSAGE": [1, { "oops": 1 }] }),
+            json!({}),
+        )],
+    );
+    let path = write_project("malformed", &project(blocks, &[]));
+    let output = scratchnative(&["check", path.to_str().unwrap()]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr.contains("Sprite1: block s0 (looks_say): input MESSAGE"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn unsupported_blocks_fail_to_compile_with_exit_code_1() {
+    let blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![(
+            "looks_changestretchby",
+            json!({ "CHANGE": num("1") }),
+            json!({}),
+        )],
+    );
+    let path = write_project("unsupported", &project(blocks, &[]));
+    let exe = path.with_file_name("unsupported");
+
+    for args in [
+        vec!["compile", path.to_str().unwrap()],
+        vec!["build", path.to_str().unwrap(), "-o", exe.to_str().unwrap()],
+        vec!["run", path.to_str().unwrap()],
+    ] {
+        let output = scratchnative(&args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        assert_eq!(output.status.code(), Some(1), "{:?}: {}", args, stderr);
+        assert!(
+            stderr.contains("Unsupported blocks: Sprite1: block s0 (looks_changestretchby)"),
+            "{}",
+            stderr
+        );
+        assert!(stderr.contains("--partial"), "{}", stderr);
+        assert!(!stderr.contains("panicked"), "{}", stderr);
+    }
+}
@@ -0,0 +1,298 @@
+//! Small projects built in code, and the compiler run on them.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use serde_json::{json, Map, Value};
+
+pub fn num(value: &str) -> Value {
+    json!([1, [4, value]])
+}
+
+pub fn text(value: &str) -> Value {
+    json!([1, [10, value]])
+}
+
+/// An input reading the variable `name`.
+pub fn variable(name: &str, id: &str) -> Value {
+    json!([3, [12, name, id], [10, ""]])
+}
+
+/// An input holding the reporter `id`, over a shadow of `kind`.
+pub fn reporter(id: &str, kind: i64) -> Value {
+    json!([3, id, [kind, ""]])
+}
+
+pub fn block(opcode: &str, parent: Option<&str>, inputs: Value, fields: Value) -> Value {
+    json!({
+        "opcode": opcode,
+        "next": null,
+        "parent": parent,
+        "inputs": inputs,
+        "fields": fields,
+        "shadow": false,
+        "topLevel": parent.is_none(),
+    })
+}
+
+/// A script of `hat` followed by `stmts`, as (opcode, inputs, fields), with
+/// ids `<prefix>h`, `<prefix>0`, `<prefix>1`...
+pub fn script(prefix: &str, hat: &str, stmts: Vec<(&str, Value, Value)>) -> Map<String, Value> {
+    let ids: Vec<String> = std::iter::once(format!("{}h", prefix))
+        .chain((0..stmts.len()).map(|i| format!("{}{}", prefix, i)))
+        .collect();
+    let mut blocks = Map::new();
+    blocks.insert(ids[0].clone(), block(hat, None, json!({}), json!({})));
+    for (i, (opcode, inputs, fields)) in stmts.into_iter().enumerate() {
+        blocks.insert(
+            ids[i + 1].clone(),
+            block(opcode, Some(&ids[i]), inputs, fields),
+        );
+    }
+    for i in 0..ids.len() - 1 {
+        blocks[&ids[i]]["next"] = json!(ids[i + 1]);
+    }
+    blocks
+}
+
+fn target(name: &str, is_stage: bool, blocks: Map<String, Value>) -> Value {
+    let mut target = json!({
+        "isStage": is_stage,
+        "name": name,
+        "variables": {},
+        "lists": {},
+        "broadcasts": {},
+        "blocks": blocks,
+        "comments": {},
+        "currentCostume": 0,
+        "costumes": [{
+            "assetId": "a0",
+            "name": "costume1",
+            "md5ext": "a0.svg",
+            "dataFormat": "svg",
+            "rotationCenterX": 0,
+            "rotationCenterY": 0,
+        }],
+        "sounds": [],
+        "volume": 100,
+        "layerOrder": if is_stage { 0 } else { 1 },
+    });
+    if !is_stage {
+        let sprite = json!({
+            "visible": true,
+            "x": 0,
+            "y": 0,
+            "size": 100,
+            "direction": 90,
+            "draggable": false,
+            "rotationStyle": "all around",
+        });
+        target
+            .as_object_mut()
+            .unwrap()
+            .extend(sprite.as_object().unwrap().clone());
+    }
+    target
+}
+
+/// Another sprite, to push onto the targets of a `project`.
+pub fn sprite(name: &str, blocks: Map<String, Value>) -> Value {
+    let mut sprite = target(name, false, blocks);
+    sprite["layerOrder"] = json!(2);
+    sprite
+}
+
+/// A project with an empty stage and a sprite named Sprite1 holding `blocks`.
+pub fn project(blocks: Map<String, Value>, extensions: &[&str]) -> Value {
+    json!({
+        "targets": [target("Stage", true, Map::new()), target("Sprite1", false, blocks)],
+        "monitors": [],
+        "extensions": extensions,
+        "meta": { "semver": "3.0.0", "vm": "0.2.0", "agent": "test" },
+    })
+}
+
+/// A scratch directory for the test `name`, empty on every run.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "scratchnative-test-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn write_project(name: &str, project: &Value) -> PathBuf {
+    let path = temp_dir(name).join("project.json");
+    fs::write(&path, project.to_string()).unwrap();
+    path
+}
+
+pub fn scratchnative(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_scratchnative"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn expect_success(output: Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// The C++ of `project`, compiled with the extra `args`.
+pub fn compile(name: &str, project: &Value, args: &[&str]) -> String {
+    let path = write_project(name, project);
+    let mut all = vec!["compile", path.to_str().unwrap()];
+    all.extend(args);
+    expect_success(scratchnative(&all))
+}
+
+/// What `project` prints when built and run, `args` being those of `run`,
+/// followed by `--` and those of the program.
+pub fn run(name: &str, project: &Value, args: &[&str]) -> String {
+    let path = write_project(name, project);
+    let mut all = vec!["run", path.to_str().unwrap()];
+    all.extend(args);
+    expect_success(scratchnative(&all))
+}
+
+/// A `width` by `height` PNG of a single opaque color.
+pub fn png(width: u32, height: u32) -> Vec<u8> {
+    let mut data = vec![];
+    let mut encoder = png::Encoder::new(&mut data, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().unwrap();
+    writer
+        .write_image_data(&[255, 0, 0, 255].repeat((width * height) as usize))
+        .unwrap();
+    writer.finish().unwrap();
+    data
+}
+
+/// Bitmap costumes named after their files.
+pub fn costumes(files: &[&str]) -> Value {
+    files
+        .iter()
+        .map(|file| {
+            json!({
+                "assetId": file.split('.').next().unwrap(),
+                "name": file.split('.').next().unwrap(),
+                "md5ext": file,
+                "dataFormat": "png",
+                "bitmapResolution": 1,
+                "rotationCenterX": 1,
+                "rotationCenterY": 1,
+            })
+        })
+        .collect()
+}
+
+/// The executable of `project`, built next to it.
+pub fn build(name: &str, project: &Value) -> PathBuf {
+    let path = write_project(name, project);
+    let exe = path.with_file_name(name);
+    expect_success(scratchnative(&[
+        "build",
+        path.to_str().unwrap(),
+        "-o",
+        exe.to_str().unwrap(),
+    ]));
+    exe
+}
+
+/// A mono 16 bit WAV file of `samples`.
+pub fn wav(rate: u32, samples: &[i16]) -> Vec<u8> {
+    let data: Vec<u8> = samples.iter().flat_map(|x| x.to_le_bytes()).collect();
+    let mut out = b"RIFF".to_vec();
+    out.extend((36 + data.len() as u32).to_le_bytes());
+    out.extend(b"WAVEfmt ");
+    out.extend(16u32.to_le_bytes());
+    out.extend(1u16.to_le_bytes());
+    out.extend(1u16.to_le_bytes());
+    out.extend(rate.to_le_bytes());
+    out.extend((rate * 2).to_le_bytes());
+    out.extend(2u16.to_le_bytes());
+    out.extend(16u16.to_le_bytes());
+    out.extend(b"data");
+    out.extend((data.len() as u32).to_le_bytes());
+    out.extend(data);
+    out
+}
+
+/// A baseline JPEG of a single color. Every block only has a DC coefficient,
+/// so the Huffman tables are tiny and the quantization is lossless.
+pub fn jpg(width: u16, height: u16, rgb: [f64; 3]) -> Vec<u8> {
+    let [r, g, b] = rgb;
+    let ycbcr = [
+        0.299 * r + 0.587 * g + 0.114 * b,
+        128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b,
+        128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b,
+    ];
+
+    let mut out = vec![0xff, 0xd8];
+    let mut segment = |marker: u8, body: &[u8]| {
+        out.extend([0xff, marker]);
+        out.extend((body.len() as u16 + 2).to_be_bytes());
+        out.extend(body);
+    };
+    segment(0xe0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+    segment(0xdb, &[[0].as_slice(), &[1; 64]].concat());
+    let [h1, h0] = height.to_be_bytes();
+    let [w1, w0] = width.to_be_bytes();
+    segment(
+        0xc0,
+        &[8, h1, h0, w1, w0, 3, 1, 0x11, 0, 2, 0x11, 0, 3, 0x11, 0],
+    );
+    // DC categories 0 to 11 in four bits, and only the end of block for AC
+    let mut dc = vec![0, 0, 0, 0, 12];
+    dc.extend([0; 12]);
+    dc.extend(0..12);
+    segment(0xc4, &dc);
+    let mut ac = vec![0x10, 1];
+    ac.extend([0; 15]);
+    ac.push(0);
+    segment(0xc4, &ac);
+    segment(0xda, &[3, 1, 0, 2, 0, 3, 0, 0, 63, 0]);
+
+    let mut bits: Vec<bool> = vec![];
+    let mut put = |value: u32, count: u32| {
+        bits.extend((0..count).rev().map(|i| value >> i & 1 == 1));
+    };
+    let blocks = width.div_ceil(8) as usize * height.div_ceil(8) as usize;
+    for block in 0..blocks {
+        for value in ycbcr {
+            // the difference from the previous block of the component
+            let diff = if block == 0 {
+                ((value.round() - 128.0) * 8.0) as i32
+            } else {
+                0
+            };
+            let category = 32 - diff.unsigned_abs().leading_zeros();
+            put(category, 4);
+            let extra = if diff < 0 { diff - 1 } else { diff };
+            put(extra as u32 & ((1 << category) - 1), category);
+            put(0, 1);
+        }
+    }
+    bits.resize(bits.len().div_ceil(8) * 8, true);
+    for byte in bits.chunks(8) {
+        let byte = byte.iter().fold(0, |x, &bit| x << 1 | bit as u8);
+        out.push(byte);
+        if byte == 0xff {
+            out.push(0);
+        }
+    }
+
+    out.extend([0xff, 0xd9]);
+    out
+}
@@ -0,0 +1,159 @@
+mod common;
+
+use common::*;
+use serde_json::json;
+
+fn extension_project() -> serde_json::Value {
+    let mut blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![
+            ("wedo2_motorOn", json!({}), json!({})),
+            (
+                "looks_say",
+                json!({ "MESSAGE": reporter("t", 10) }),
+                json!({}),
+            ),
+            ("looks_say", json!({ "MESSAGE": text("done") }), json!({})),
+        ],
+    );
+    blocks.insert(
+        "t".to_string(),
+        block(
+            "translate_getTranslate",
+            Some("s1"),
+            json!({ "WORDS": text("hello"), "LANGUAGE": text("fr") }),
+            json!({}),
+        ),
+    );
+    project(blocks, &["wedo2", "translate"])
+}
+
+#[test]
+fn unsupported_extensions_fail_by_default() {
+    let path = write_project("fail", &extension_project());
+    let output = scratchnative(&["compile", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Unsupported extensions: wedo2 "),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn stubbed_extensions_do_nothing() {
+    let output = run("stub", &extension_project(), &["--unsupported", "stub"]);
+    // untranslated rather than replaced by a number
+    assert_eq!(output, "Sprite1: hello\nSprite1: done\n");
+}
+
+#[test]
+fn partial_compilation_warns_when_stubs_run() {
+    let path = write_project("partial", &extension_project());
+    let output = scratchnative(&["run", path.to_str().unwrap(), "--partial"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stdout.ends_with("Sprite1: done\n"), "{}", stdout);
+    assert!(stderr.contains("wedo2_motorOn"), "{}", stderr);
+}
+
+#[test]
+fn partial_compilation_of_unknown_operators() {
+    let mut blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![(
+            "looks_say",
+            json!({ "MESSAGE": reporter("o", 10) }),
+            json!({}),
+        )],
+    );
+    blocks.insert(
+        "o".to_string(),
+        block(
+            "operator_frobnicate",
+            Some("s0"),
+            json!({ "NUM": num("1") }),
+            json!({}),
+        ),
+    );
+    let project = project(blocks, &[]);
+
+    let path = write_project("unknown_operator", &project);
+    let output = scratchnative(&["compile", path.to_str().unwrap()]);
+    assert!(!output.status.success());
+
+    let code = compile("unknown_operator", &project, &["--partial"]);
+    assert!(code.contains(
+        "scratch_runtime.say(sprite, scratch_runtime.unsupported(sprite, \"operator_frobnicate\", \"o\"), false);"
+    ));
+}
+
+#[test]
+fn partial_compilation_of_list_inputs() {
+    let blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![(
+            "looks_say",
+            json!({ "MESSAGE": [3, [13, "things", "l"], [10, ""]] }),
+            json!({}),
+        )],
+    );
+    let code = compile("list_input", &project(blocks, &[]), &["--partial"]);
+    assert!(code.contains("scratch_runtime.unsupported(sprite, \"looks_say\", \"s0\")"));
+}
+
+#[test]
+fn unknown_hats_are_stubbed_under_partial() {
+    let mut blocks = script(
+        "s",
+        "event_whenbroadcastreceived",
+        vec![("looks_say", json!({ "MESSAGE": text("hi") }), json!({}))],
+    );
+    blocks["sh"]["fields"] = json!({ "BROADCAST_OPTION": ["go", "b"] });
+    let project = project(blocks, &[]);
+
+    let path = write_project("unknown_hat", &project);
+    let output = scratchnative(&["compile", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Sprite1: block sh (event_whenbroadcastreceived)"),
+        "{}",
+        stderr
+    );
+
+    let output = run("unknown_hat", &project, &["--partial"]);
+    assert_eq!(output, "");
+    let output = scratchnative(&["run", path.to_str().unwrap(), "--partial"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Sprite1: block sh (event_whenbroadcastreceived) is not supported"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn supported_extensions_are_not_reported() {
+    let blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![("pen_clear", json!({}), json!({}))],
+    );
+    let path = write_project("supported", &project(blocks, &["pen", "music"]));
+    let output = scratchnative(&["compile", path.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+
+    let path = write_project("reported", &extension_project());
+    let output = scratchnative(&["compile", path.to_str().unwrap(), "--unsupported", "stub"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Extensions:"), "{}", stderr);
+    assert!(stderr.contains("wedo2"), "{}", stderr);
+    assert!(stderr.contains("translate"), "{}", stderr);
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::*;
+use serde_json::{json, Value};
+
+#[test]
+fn info_as_json() {
+    let mut blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![("looks_say", json!({ "MESSAGE": text("hi") }), json!({}))],
+    );
+    blocks.extend(script("k", "event_whenkeypressed", vec![]));
+    let mut project = project(blocks, &["pen"]);
+    project["targets"][1]["variables"] = json!({ "v": ["score", 3] });
+    project["targets"][0]["lists"] = json!({ "l": ["items", ["a", "b"]] });
+    project["targets"][0]["broadcasts"] = json!({ "b2": "start", "b1": "end" });
+    project["monitors"] = json!([{
+        "id": "v",
+        "mode": "large",
+        "opcode": "data_variable",
+        "params": { "VARIABLE": "score" },
+        "spriteName": "Sprite1",
+        "value": 3,
+        "x": 5,
+        "y": 10,
+        "visible": true,
+    }]);
+
+    let path = write_project("info", &project);
+    let output = scratchnative(&["info", path.to_str().unwrap(), "--json"]);
+    assert!(output.status.success());
+    let info: Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(
+        info,
+        json!({
+            "agent": "test",
+            "semver": "3.0.0",
+            "vm": "0.2.0",
+            "extensions": ["pen"],
+            "targets": [
+                {
+                    "name": "Stage",
+                    "is_stage": true,
+                    "layer_order": 0,
+                    "variables": {},
+                    "lists": { "items": ["a", "b"] },
+                    "broadcasts": ["end", "start"],
+                    "costumes": { "svg": 1 },
+                    "sounds": {},
+                    "scripts": {},
+                    "blocks": 0,
+                },
+                {
+                    "name": "Sprite1",
+                    "is_stage": false,
+                    "layer_order": 1,
+                    "variables": { "score": 3 },
+                    "lists": {},
+                    "broadcasts": [],
+                    "costumes": { "svg": 1 },
+                    "sounds": {},
+                    "scripts": { "event_whenflagclicked": 1, "event_whenkeypressed": 1 },
+                    "blocks": 3,
+                },
+            ],
+            "scripts": 2,
+            "blocks": 3,
+            "monitors": [{
+                "opcode": "data_variable",
+                "name": "score",
+                "sprite": "Sprite1",
+                "mode": "large",
+                "x": 5.0,
+                "y": 10.0,
+                "visible": true,
+            }],
+        })
+    );
+}
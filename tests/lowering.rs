@@ -0,0 +1,226 @@
+mod common;
+
+use common::*;
+use serde_json::json;
+
+#[test]
+fn layer_count_saved_as_integer_input() {
+    let blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![(
+            "looks_goforwardbackwardlayers",
+            json!({ "NUM": [1, [7, "2"]] }),
+            json!({ "FORWARD_BACKWARD": ["backward", null] }),
+        )],
+    );
+    let code = compile("layer_count", &project(blocks, &[]), &[]);
+    assert!(code.contains("scratch_runtime.go_backward_layers(sprite, 2);"));
+}
+
+#[test]
+fn direction_and_duration_saved_as_angle_and_positive_number() {
+    let blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![
+            (
+                "motion_pointindirection",
+                json!({ "DIRECTION": [1, [8, "45"]] }),
+                json!({}),
+            ),
+            (
+                "control_wait",
+                json!({ "DURATION": [1, [5, "2"]] }),
+                json!({}),
+            ),
+        ],
+    );
+    let code = compile("direction", &project(blocks, &[]), &[]);
+    assert!(code.contains("scratch_runtime.point_in_direction(sprite, 45);"));
+    assert!(code.contains("scratch_scheduler.wait(ScratchValue(2).to_number());"));
+}
+
+#[test]
+fn note_for_a_quarter_beat() {
+    let mut blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![(
+            "music_playNoteForBeats",
+            json!({ "NOTE": [1, "n"], "BEATS": num("0.25") }),
+            json!({}),
+        )],
+    );
+    blocks.insert(
+        "n".to_string(),
+        block(
+            "note",
+            Some("s0"),
+            json!({}),
+            json!({ "NOTE": ["60", null] }),
+        ),
+    );
+    let code = compile("quarter_beat", &project(blocks, &["music"]), &[]);
+    assert!(code.contains("scratch_music.play_note(sprite, \"60\", 0.25);"));
+}
+
+#[test]
+fn fractional_repeat_count_is_rounded() {
+    let mut blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![(
+            "control_repeat",
+            json!({ "TIMES": num("2.5"), "SUBSTACK": [2, "b"] }),
+            json!({}),
+        )],
+    );
+    blocks.insert(
+        "b".to_string(),
+        block(
+            "looks_say",
+            Some("s0"),
+            json!({ "MESSAGE": text("hi") }),
+            json!({}),
+        ),
+    );
+    let output = run("repeat", &project(blocks, &[]), &[]);
+    assert_eq!(output, "Sprite1: hi\n".repeat(3));
+}
+
+#[test]
+fn say_and_think_for_half_a_second() {
+    let blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![
+            (
+                "looks_sayforsecs",
+                json!({ "MESSAGE": text("hi"), "SECS": num("0.5") }),
+                json!({}),
+            ),
+            (
+                "looks_thinkforsecs",
+                json!({ "MESSAGE": text("hm"), "SECS": num("0.5") }),
+                json!({}),
+            ),
+        ],
+    );
+    let project = project(blocks, &[]);
+    let code = compile("say_for_secs", &project, &[]);
+    assert!(code.contains("scratch_runtime.say_for_secs(sprite, \"hi\", 0.5, false);"));
+    let output = run("say_for_secs", &project, &[]);
+    assert_eq!(output, "Sprite1: hi\nSprite1 thinks: hm\n");
+}
+
+#[test]
+fn pick_random_between_equal_ends() {
+    let mut blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![
+            (
+                "looks_say",
+                json!({ "MESSAGE": reporter("a", 10) }),
+                json!({}),
+            ),
+            (
+                "looks_say",
+                json!({ "MESSAGE": reporter("b", 10) }),
+                json!({}),
+            ),
+        ],
+    );
+    blocks.insert(
+        "a".to_string(),
+        block(
+            "operator_random",
+            Some("s0"),
+            json!({ "FROM": num("7"), "TO": num("7") }),
+            json!({}),
+        ),
+    );
+    blocks.insert(
+        "b".to_string(),
+        block(
+            "operator_random",
+            Some("s1"),
+            json!({ "FROM": num("0.5"), "TO": num("0.5") }),
+            json!({}),
+        ),
+    );
+    let output = run("random", &project(blocks, &[]), &[]);
+    assert_eq!(output, "Sprite1: 7\nSprite1: 0.5\n");
+}
+
+#[test]
+fn sprites_with_local_variables_of_the_same_name() {
+    let mut blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![
+            (
+                "data_setvariableto",
+                json!({ "VALUE": num("1") }),
+                json!({ "VARIABLE": ["speed", "v1"] }),
+            ),
+            (
+                "data_setvariableto",
+                json!({ "VALUE": num("5") }),
+                json!({ "VARIABLE": ["score", "g"] }),
+            ),
+            ("control_wait", json!({ "DURATION": num("0") }), json!({})),
+            (
+                "looks_say",
+                json!({ "MESSAGE": variable("speed", "v1") }),
+                json!({}),
+            ),
+            (
+                "looks_say",
+                json!({ "MESSAGE": reporter("o", 10) }),
+                json!({}),
+            ),
+        ],
+    );
+    blocks.insert(
+        "o".to_string(),
+        block(
+            "sensing_of",
+            Some("s4"),
+            json!({ "OBJECT": [1, "m"] }),
+            json!({ "PROPERTY": ["speed", null] }),
+        ),
+    );
+    blocks.insert(
+        "m".to_string(),
+        block(
+            "sensing_of_object_menu",
+            Some("o"),
+            json!({}),
+            json!({ "OBJECT": ["Sprite2", null] }),
+        ),
+    );
+    let other = script(
+        "t",
+        "event_whenflagclicked",
+        vec![(
+            "data_setvariableto",
+            json!({ "VALUE": num("2") }),
+            json!({ "VARIABLE": ["speed", "v2"] }),
+        )],
+    );
+
+    let mut project = project(blocks, &[]);
+    project["targets"][0]["variables"] = json!({ "g": ["score", 0] });
+    project["targets"][1]["variables"] = json!({ "v1": ["speed", 0] });
+    let mut sprite2 = sprite("Sprite2", other);
+    sprite2["variables"] = json!({ "v2": ["speed", 0] });
+    project["targets"].as_array_mut().unwrap().push(sprite2);
+
+    let output = run("local_variables", &project, &[]);
+    assert_eq!(
+        output,
+        "Sprite1: 1\nSprite1: 2\nscore = 5\nSprite1: speed = 1\nSprite2: speed = 2\n"
+    );
+}
@@ -0,0 +1,399 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::*;
+use serde_json::json;
+
+fn u16_at(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(data[at..at + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+#[test]
+fn sounds_are_mixed_into_a_wav_file() {
+    let mut blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![
+            (
+                "sound_playuntildone",
+                json!({ "SOUND_MENU": [1, "m"] }),
+                json!({}),
+            ),
+            (
+                "looks_say",
+                json!({ "MESSAGE": reporter("t", 10) }),
+                json!({}),
+            ),
+        ],
+    );
+    blocks.insert(
+        "m".to_string(),
+        block(
+            "sound_sounds_menu",
+            Some("s0"),
+            json!({}),
+            json!({ "SOUND_MENU": ["beep", null] }),
+        ),
+    );
+    blocks.insert(
+        "t".to_string(),
+        block("sensing_timer", Some("s1"), json!({}), json!({})),
+    );
+    let mut project = project(blocks, &[]);
+    project["targets"][1]["sounds"] = json!([{
+        "assetId": "abc",
+        "name": "beep",
+        "dataFormat": "wav",
+        "rate": 22050,
+        "sampleCount": 11025,
+        "md5ext": "abc.wav",
+    }]);
+    let path = write_project("sound", &project);
+    // half a second of a square wave
+    let samples: Vec<i16> = (0..11025)
+        .map(|i| if i / 50 % 2 == 0 { 8000 } else { -8000 })
+        .collect();
+    fs::write(path.with_file_name("abc.wav"), wav(22050, &samples)).unwrap();
+    let exe = path.with_file_name("sound");
+    let output = scratchnative(&["build", path.to_str().unwrap(), "-o", exe.to_str().unwrap()]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let out = path.with_file_name("out.wav");
+    let output = Command::new(&exe)
+        .args(["--frame-time", "0.05", "--sound", out.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // the say block only runs once the sound is over
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let waited: f64 = stdout
+        .trim()
+        .strip_prefix("Sprite1: ")
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((0.5..0.6).contains(&waited), "{}", stdout);
+
+    let data = fs::read(&out).unwrap();
+    assert_eq!(&data[0..4], b"RIFF");
+    assert_eq!(&data[8..16], b"WAVEfmt ");
+    assert_eq!(u16_at(&data, 20), 1, "PCM");
+    assert_eq!(u16_at(&data, 22), 2, "stereo");
+    assert_eq!(u32_at(&data, 24), 44100);
+    assert_eq!(u16_at(&data, 34), 16);
+    assert_eq!(&data[36..40], b"data");
+    assert_eq!(u32_at(&data, 40) as usize, data.len() - 44);
+
+    // the sound is played at the rate of the mix, for as long
+    let frames = (data.len() - 44) / 4;
+    assert!(
+        (22050..=22050 + 44100 / 10).contains(&frames),
+        "{} frames",
+        frames
+    );
+    assert!(data[44..].chunks(2).any(|x| x != [0, 0]));
+}
+
+// (tick, bytes) of every event
+type Track = Vec<(u64, Vec<u8>)>;
+
+fn midi_tracks(data: &[u8]) -> (u16, Vec<Track>) {
+    assert_eq!(&data[0..4], b"MThd");
+    let be16 = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+    assert_eq!(be16(8), 1, "simultaneous tracks");
+    let count = be16(10);
+    let division = be16(12);
+
+    let mut tracks = vec![];
+    let mut at = 14;
+    for _ in 0..count {
+        assert_eq!(&data[at..at + 4], b"MTrk");
+        let len = u32::from_be_bytes(data[at + 4..at + 8].try_into().unwrap()) as usize;
+        let track = &data[at + 8..at + 8 + len];
+        at += 8 + len;
+
+        let varlen = |i: &mut usize| {
+            let mut value = 0u64;
+            loop {
+                let byte = track[*i];
+                *i += 1;
+                value = (value << 7) | (byte & 0x7f) as u64;
+                if byte & 0x80 == 0 {
+                    return value;
+                }
+            }
+        };
+
+        let mut events = vec![];
+        let (mut i, mut tick) = (0, 0);
+        while i < track.len() {
+            tick += varlen(&mut i);
+            let start = i;
+            match track[i] {
+                0xff => {
+                    i += 2;
+                    let len = varlen(&mut i) as usize;
+                    i += len;
+                }
+                status if status & 0xf0 == 0xc0 => i += 2,
+                _ => i += 3,
+            }
+            events.push((tick, track[start..i].to_vec()));
+        }
+        tracks.push(events);
+    }
+
+    (division, tracks)
+}
+
+#[test]
+fn notes_and_drums_are_written_to_a_midi_file() {
+    let mut blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![
+            (
+                "music_playNoteForBeats",
+                json!({ "NOTE": [1, "n"], "BEATS": num("0.25") }),
+                json!({}),
+            ),
+            ("music_setTempo", json!({ "TEMPO": num("120") }), json!({})),
+            (
+                "music_playDrumForBeats",
+                json!({ "DRUM": num("1"), "BEATS": num("0.5") }),
+                json!({}),
+            ),
+        ],
+    );
+    blocks.insert(
+        "n".to_string(),
+        block(
+            "note",
+            Some("s0"),
+            json!({}),
+            json!({ "NOTE": ["60", null] }),
+        ),
+    );
+    let other = script(
+        "t",
+        "event_whenflagclicked",
+        vec![(
+            "music_playNoteForBeats",
+            json!({ "NOTE": num("72"), "BEATS": num("1") }),
+            json!({}),
+        )],
+    );
+    let mut project = project(blocks, &["music"]);
+    project["targets"]
+        .as_array_mut()
+        .unwrap()
+        .push(sprite("Sprite2", other));
+    let exe = build("midi", &project);
+
+    let midi = exe.with_file_name("out.mid");
+    let output = Command::new(&exe)
+        .args(["--frame-time", "0.05", "--midi", midi.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let (division, tracks) = midi_tracks(&fs::read(&midi).unwrap());
+    assert_eq!(division, 480);
+    // the tempos, then the sprites that played something
+    assert_eq!(tracks.len(), 3);
+
+    let tempos: Vec<_> = tracks[0]
+        .iter()
+        .filter(|(_, bytes)| bytes[..2] == [0xff, 0x51])
+        .map(|(tick, bytes)| (*tick, bytes[3..6].to_vec()))
+        .collect();
+    // a beat of a second, then half a second after the first quarter beat
+    assert_eq!(
+        tempos,
+        vec![(0, vec![0x0f, 0x42, 0x40]), (120, vec![0x07, 0xa1, 0x20])]
+    );
+
+    let notes = |track: &Track| -> Track {
+        track
+            .iter()
+            .filter(|(_, bytes)| matches!(bytes[0] & 0xf0, 0x80 | 0x90))
+            .map(|(tick, bytes)| (*tick, bytes[..2].to_vec()))
+            .collect()
+    };
+    // the snare is on the drum channel, its half beat twice as fast
+    assert_eq!(
+        notes(&tracks[1]),
+        vec![
+            (0, vec![0x90, 60]),
+            (120, vec![0x80, 60]),
+            (120, vec![0x99, 38]),
+            (360, vec![0x89, 38]),
+        ]
+    );
+    // a second of which the last three quarters are at the new tempo
+    assert_eq!(
+        notes(&tracks[2]),
+        vec![(0, vec![0x91, 72]), (840, vec![0x81, 72])]
+    );
+}
+
+#[test]
+fn every_frame_is_rendered_to_a_png_file() {
+    let blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![("control_wait", json!({ "DURATION": num("0.1") }), json!({}))],
+    );
+    let mut project = project(blocks, &[]);
+    project["targets"][1]["costumes"] = json!([{
+        "assetId": "abc",
+        "name": "photo",
+        "md5ext": "abc.jpg",
+        "dataFormat": "jpg",
+        "bitmapResolution": 1,
+        "rotationCenterX": 8,
+        "rotationCenterY": 8,
+    }]);
+    let path = write_project("frames", &project);
+    fs::write(
+        path.with_file_name("abc.jpg"),
+        jpg(16, 16, [255.0, 0.0, 0.0]),
+    )
+    .unwrap();
+    let exe = path.with_file_name("frames");
+    let output = scratchnative(&["build", path.to_str().unwrap(), "-o", exe.to_str().unwrap()]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let prefix = path.with_file_name("frame-");
+    let output = Command::new(&exe)
+        .args(["--frame-time", "0.05", "--frames", prefix.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let mut frames: Vec<_> = fs::read_dir(path.parent().unwrap())
+        .unwrap()
+        .map(|x| x.unwrap().file_name().into_string().unwrap())
+        .filter(|x| x.starts_with("frame-"))
+        .collect();
+    frames.sort();
+    // the two frames of waiting, then the state it ended in
+    assert_eq!(
+        frames,
+        ["frame-00001.png", "frame-00002.png", "frame-00003.png"]
+    );
+
+    for frame in &frames {
+        let decoder = png::Decoder::new(fs::File::open(path.with_file_name(frame)).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        assert_eq!((reader.info().width, reader.info().height), (480, 360));
+        assert_eq!(reader.info().color_type, png::ColorType::Rgba);
+
+        // the photo is centered on the white stage
+        let mut rgba = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut rgba).unwrap();
+        let pixel = |x: usize, y: usize| &rgba[(y * 480 + x) * 4..][..3];
+        assert_eq!(pixel(0, 0), [255, 255, 255]);
+        let center = pixel(240, 180);
+        assert!(
+            center[0] > 240 && center[1] < 16 && center[2] < 16,
+            "{:?}",
+            center
+        );
+    }
+}
+
+fn strip_colors(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('\x1b') {
+        out += &rest[..start];
+        let end = rest[start..].find('m').unwrap();
+        rest = &rest[start + end + 1..];
+    }
+    out + rest
+}
+
+#[test]
+fn the_stage_is_drawn_in_the_terminal_with_monitors_and_bubbles() {
+    let blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![
+            (
+                "data_setvariableto",
+                json!({ "VALUE": num("7") }),
+                json!({ "VARIABLE": ["score", "v"] }),
+            ),
+            ("looks_say", json!({ "MESSAGE": text("hello") }), json!({})),
+            ("control_wait", json!({ "DURATION": num("0.1") }), json!({})),
+        ],
+    );
+    let mut project = project(blocks, &[]);
+    project["targets"][0]["variables"] = json!({ "v": ["score", 0] });
+    project["monitors"] = json!([{
+        "id": "v",
+        "mode": "default",
+        "opcode": "data_variable",
+        "params": { "VARIABLE": "score" },
+        "spriteName": null,
+        "value": 0,
+        "x": 0,
+        "y": 0,
+        "visible": true,
+    }]);
+    let exe = build("terminal", &project);
+
+    let output = Command::new(&exe)
+        .args(["--frame-time", "0.05", "--terminal"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // the screen is only cleared for the first frame, then overwritten
+    let (first, rest) = stdout.split_once("\x1b[H").unwrap();
+    assert_eq!(first, "\x1b[0m\x1b[2J");
+    let frames: Vec<_> = rest.split("\x1b[H").collect();
+    assert_eq!(frames.len(), 3);
+    let (last, after) = frames[2].split_once("\x1b[0m\nscore").unwrap();
+    assert_eq!(after, " = 7\n");
+
+    for frame in [frames[0], frames[1], last] {
+        // a monitor of the orange value next to its name, in the top left
+        assert!(frame.starts_with(
+            "\x1b[38;2;87;94;117m\x1b[48;2;230;240;255m score \
+             \x1b[38;2;255;255;255m\x1b[48;2;255;140;26m 7 "
+        ));
+
+        // 80 by 30 when it isn't a terminal, the stage being white
+        let rows: Vec<_> = frame
+            .split("\x1b[0m\n")
+            .map(|row| strip_colors(row).replace('▀', "#"))
+            .filter(|row| !row.is_empty())
+            .collect();
+        assert_eq!(rows.len(), 30, "{:?}", rows);
+        assert!(rows.iter().all(|row| row.chars().count() == 80));
+        assert_eq!(rows[0], format!(" score  7 {}", "#".repeat(70)));
+        // the bubble of the empty costume in the middle of the stage
+        assert_eq!(
+            rows[14],
+            format!("{} hello {}", "#".repeat(40), "#".repeat(33))
+        );
+    }
+}
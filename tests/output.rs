@@ -0,0 +1,184 @@
+mod common;
+
+use std::fs;
+
+use common::*;
+use serde_json::json;
+
+fn say_project() -> serde_json::Value {
+    let blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![
+            ("looks_say", json!({ "MESSAGE": text("hello") }), json!({})),
+            (
+                "control_repeat",
+                json!({ "TIMES": num("2"), "SUBSTACK": [2, "b"] }),
+                json!({}),
+            ),
+        ],
+    );
+    let mut blocks = blocks;
+    blocks.insert(
+        "b".to_string(),
+        block(
+            "looks_think",
+            Some("s1"),
+            json!({ "MESSAGE": text("hm") }),
+            json!({}),
+        ),
+    );
+    project(blocks, &[])
+}
+
+#[test]
+fn compiling_to_a_file_matches_stdout() {
+    let project = say_project();
+    for args in [&[][..], &["--inline-runtime"], &["--block-comments"]] {
+        let stdout = compile("to_file", &project, args);
+
+        let path = write_project("to_file", &project);
+        let output = path.with_file_name("main.cpp");
+        let mut all = vec![
+            "compile",
+            path.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ];
+        all.extend(args);
+        assert!(scratchnative(&all).status.success());
+
+        assert_eq!(fs::read_to_string(&output).unwrap(), stdout, "{:?}", args);
+    }
+}
+
+#[test]
+fn built_executables_run() {
+    let path = write_project("build", &say_project());
+    let exe = path.with_file_name("say");
+    let output = scratchnative(&["build", path.to_str().unwrap(), "-o", exe.to_str().unwrap()]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = std::process::Command::new(&exe).output().unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Sprite1: hello\nSprite1 thinks: hm\nSprite1 thinks: hm\n"
+    );
+}
+
+#[test]
+fn source_map_points_at_the_lines_of_blocks() {
+    let path = write_project("source_map", &say_project());
+    let output = path.with_file_name("main.cpp");
+    let map = path.with_file_name("main.map.json");
+    let status = scratchnative(&[
+        "compile",
+        path.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "--source-map",
+        map.to_str().unwrap(),
+        "--block-comments",
+    ])
+    .status;
+    assert!(status.success());
+
+    let code = fs::read_to_string(&output).unwrap();
+    let lines: Vec<&str> = code.lines().collect();
+    let mappings: Vec<serde_json::Value> =
+        serde_json::from_str(&fs::read_to_string(&map).unwrap()).unwrap();
+    let lines_of = |block: &str| {
+        let mapping = mappings.iter().find(|x| x["block"] == block).unwrap();
+        assert_eq!(mapping["target"], "Sprite1");
+        assert_eq!(mapping["script"], "sh");
+        let range = mapping["lines"].as_array().unwrap();
+        let (first, last) = (range[0].as_u64().unwrap(), range[1].as_u64().unwrap());
+        &lines[first as usize - 1..last as usize]
+    };
+
+    assert_eq!(
+        lines_of("s0"),
+        ["    scratch_runtime.say(sprite, \"hello\", false);"]
+    );
+    // the comment is above the code of the block, not part of it
+    let repeat = lines_of("s1");
+    assert!(repeat[0].trim().starts_with("for (long _"), "{:?}", repeat);
+    assert_eq!(repeat.last().unwrap().trim(), "}");
+    assert!(repeat.iter().any(|x| x.contains("\"hm\"")));
+    assert_eq!(
+        lines_of("b"),
+        ["      scratch_runtime.say(sprite, \"hm\", true);"]
+    );
+}
+
+#[test]
+fn out_dir_builds_with_make() {
+    let mut project = say_project();
+    project["targets"][1]["costumes"] = costumes(&["a.png"]);
+    let path = write_project("out_dir", &project);
+    fs::write(path.with_file_name("a.png"), png(2, 2)).unwrap();
+    let dir = path.with_file_name("out");
+
+    let output = scratchnative(&[
+        "compile",
+        path.to_str().unwrap(),
+        "--out-dir",
+        dir.to_str().unwrap(),
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fs::read(dir.join("assets/a.png")).unwrap(), png(2, 2));
+
+    let output = std::process::Command::new("make")
+        .arg("-C")
+        .arg(&dir)
+        .arg("CXXFLAGS=-O0")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = std::process::Command::new(dir.join("project"))
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Sprite1: hello\nSprite1 thinks: hm\nSprite1 thinks: hm\n"
+    );
+}
+
+#[test]
+fn asset_names_cannot_leave_the_out_dir() {
+    let mut project = say_project();
+    project["targets"][1]["costumes"][0]["md5ext"] = json!("../../pwned.svg");
+    let path = write_project("pwned", &project);
+    let dir = path.with_file_name("a").join("b");
+    fs::create_dir_all(dir.join("assets")).unwrap();
+    fs::write(path.with_file_name("pwned.svg"), "<svg/>").unwrap();
+
+    let output = scratchnative(&[
+        "compile",
+        path.to_str().unwrap(),
+        "--out-dir",
+        dir.to_str().unwrap(),
+    ]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr
+            .contains("Sprite1: costume costume1: ../../pwned.svg is not a <hash>.<ext> file name"),
+        "{}",
+        stderr
+    );
+    assert!(!dir.join("assets").join("../../pwned.svg").exists());
+}
@@ -0,0 +1,291 @@
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::*;
+use serde_json::json;
+
+#[test]
+fn start_time_is_reported_in_utc() {
+    let menus = ["YEAR", "MONTH", "DATE", "DAYOFWEEK", "HOUR", "MINUTE"];
+    let mut blocks = script(
+        "s",
+        "event_whenflagclicked",
+        menus
+            .iter()
+            .enumerate()
+            .map(|(i, _)| {
+                (
+                    "looks_say",
+                    json!({ "MESSAGE": reporter(&format!("c{}", i), 10) }),
+                    json!({}),
+                )
+            })
+            .collect(),
+    );
+    for (i, menu) in menus.iter().enumerate() {
+        blocks.insert(
+            format!("c{}", i),
+            block(
+                "sensing_current",
+                Some(&format!("s{}", i)),
+                json!({}),
+                json!({ "CURRENTMENU": [menu, null] }),
+            ),
+        );
+    }
+    let exe = build("current", &project(blocks, &[]));
+
+    // fourteen hours ahead of UTC, the local date would be the next day
+    let output = Command::new(&exe)
+        .args(["--start-time", "2020-12-31T23:30:00"])
+        .env("TZ", "XYZ-14")
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Sprite1: 2020\nSprite1: 12\nSprite1: 31\nSprite1: 5\nSprite1: 23\nSprite1: 30\n"
+    );
+}
+
+#[test]
+fn replayed_input_ends_the_run_with_stdin_open() {
+    let mut blocks = script(
+        "s",
+        "event_whenkeypressed",
+        vec![(
+            "looks_say",
+            json!({ "MESSAGE": text("pressed") }),
+            json!({}),
+        )],
+    );
+    blocks["sh"]["fields"] = json!({ "KEY_OPTION": ["space", null] });
+    let exe = build("replay", &project(blocks, &[]));
+    let input = exe.with_file_name("input.txt");
+    fs::write(&input, "@1 key down space\n@2 key up space\n").unwrap();
+
+    // a pipe nobody writes to or closes, like an idle terminal
+    let mut child = Command::new(&exe)
+        .args(["--input", input.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let start = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if start.elapsed() > Duration::from_secs(10) {
+            child.kill().unwrap();
+            panic!("still running after the replay ended");
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Sprite1: pressed\n"
+    );
+}
+
+#[test]
+fn time_limit_stops_key_hats_and_endless_loops() {
+    let mut blocks = script(
+        "k",
+        "event_whenkeypressed",
+        vec![(
+            "looks_say",
+            json!({ "MESSAGE": text("pressed") }),
+            json!({}),
+        )],
+    );
+    blocks["kh"]["fields"] = json!({ "KEY_OPTION": ["space", null] });
+    blocks.extend(script(
+        "f",
+        "event_whenflagclicked",
+        vec![(
+            "control_repeat_until",
+            json!({ "SUBSTACK": [2, "t0"] }),
+            json!({}),
+        )],
+    ));
+    blocks.extend(script(
+        "t",
+        "event_whenflagclicked",
+        vec![
+            ("looks_say", json!({ "MESSAGE": text("tick") }), json!({})),
+            ("control_wait", json!({ "DURATION": num("1") }), json!({})),
+        ],
+    ));
+    // the body of a loop without a condition, without its hat
+    blocks.remove("th");
+    blocks["t0"]["parent"] = json!("f0");
+    blocks["t0"]["topLevel"] = json!(false);
+    let exe = build("time_limit", &project(blocks, &[]));
+
+    let mut child = Command::new(&exe)
+        .args(["--frame-time", "0.25", "--time-limit", "2.5"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let start = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if start.elapsed() > Duration::from_secs(10) {
+            child.kill().unwrap();
+            panic!("still running after the time limit");
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Sprite1: tick\nSprite1: tick\nSprite1: tick\n"
+    );
+}
+
+#[test]
+fn boolean_reporters_read_as_true_or_false() {
+    let mut blocks = script(
+        "s",
+        "event_whenflagclicked",
+        vec![
+            (
+                "looks_say",
+                json!({ "MESSAGE": reporter("a", 10) }),
+                json!({}),
+            ),
+            (
+                "looks_say",
+                json!({ "MESSAGE": reporter("b", 10) }),
+                json!({}),
+            ),
+            (
+                "looks_say",
+                json!({ "MESSAGE": reporter("c", 10) }),
+                json!({}),
+            ),
+        ],
+    );
+    blocks.insert(
+        "a".to_string(),
+        block(
+            "sensing_touchingobject",
+            Some("s0"),
+            json!({ "TOUCHINGOBJECTMENU": [1, "m"] }),
+            json!({}),
+        ),
+    );
+    blocks.insert(
+        "m".to_string(),
+        block(
+            "sensing_touchingobjectmenu",
+            Some("a"),
+            json!({}),
+            json!({ "TOUCHINGOBJECTMENU": ["_edge_", null] }),
+        ),
+    );
+    blocks.insert(
+        "b".to_string(),
+        block(
+            "operator_equals",
+            Some("s1"),
+            json!({ "OPERAND1": num("1"), "OPERAND2": num("1") }),
+            json!({}),
+        ),
+    );
+    blocks.insert(
+        "c".to_string(),
+        block(
+            "operator_not",
+            Some("s2"),
+            json!({ "OPERAND": [2, "d"] }),
+            json!({}),
+        ),
+    );
+    blocks.insert(
+        "d".to_string(),
+        block("sensing_mousedown", Some("c"), json!({}), json!({})),
+    );
+    let output = run("booleans", &project(blocks, &[]), &[]);
+    assert_eq!(output, "Sprite1: false\nSprite1: true\nSprite1: true\n");
+}
+
+// asks three times, saying each answer
+fn ask_project() -> serde_json::Value {
+    let mut stmts = vec![];
+    for i in 0..3 {
+        stmts.push((
+            "sensing_askandwait",
+            json!({ "QUESTION": text(&format!("q{}?", i)) }),
+            json!({}),
+        ));
+        stmts.push((
+            "looks_say",
+            json!({ "MESSAGE": reporter(&format!("j{}", i), 10) }),
+            json!({}),
+        ));
+    }
+    let mut blocks = script("s", "event_whenflagclicked", stmts);
+    for i in 0..3 {
+        blocks.insert(
+            format!("j{}", i),
+            block(
+                "operator_join",
+                Some(&format!("s{}", 2 * i + 1)),
+                json!({ "STRING1": text("got "), "STRING2": reporter(&format!("a{}", i), 10) }),
+                json!({}),
+            ),
+        );
+        blocks.insert(
+            format!("a{}", i),
+            block(
+                "sensing_answer",
+                Some(&format!("j{}", i)),
+                json!({}),
+                json!({}),
+            ),
+        );
+    }
+    project(blocks, &[])
+}
+
+// the third question comes after the end of the input
+const ASKED: &str =
+    "Sprite1: q0?\nSprite1: got Ann\nSprite1: q1?\nSprite1: got Bob\nSprite1: q2?\nSprite1: got \n";
+
+#[test]
+fn answers_are_read_from_stdin() {
+    let exe = build("ask_stdin", &ask_project());
+    let mut child = Command::new(&exe)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"Ann\r\nBob\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), ASKED);
+}
+
+#[test]
+fn answers_are_read_from_a_file() {
+    let exe = build("ask_file", &ask_project());
+    let answers = exe.with_file_name("answers.txt");
+    fs::write(&answers, "Ann\nBob\n").unwrap();
+
+    // with nothing on stdin, the answers can only come from the file
+    let output = Command::new(&exe)
+        .args(["--answers", answers.to_str().unwrap()])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), ASKED);
+}